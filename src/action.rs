@@ -0,0 +1,116 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::credentials::Credentials;
+use crate::error::Error;
+use crate::review::ChangeRequest;
+use crate::server::GHPullRequestEvent;
+
+/// Applies credentials from environment variables over `creds`, so a Github
+/// Actions step can be configured with secrets instead of a config file.
+/// Github's own GITHUB_TOKEN, GITHUB_ACTOR and GITHUB_API_URL are used for
+/// Github unless the AUTOCOMMENT_GITHUB_* variables are set. `var` looks up a
+/// variable, which is `std::env::var` outside of tests.
+pub fn credentials_from_vars(mut creds: Credentials, var: impl Fn(&str) -> Option<String>) -> Result<Credentials, Error> {
+    let first = |names: &[&str]| names.iter().find_map(|name| var(name).filter(|value| !value.is_empty()));
+    let list = |value: String| value.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect();
+
+    if let Some(user) = first(&["AUTOCOMMENT_JIRA_USER"]) {
+        creds.jira_user = user;
+    }
+    if let Some(pass) = first(&["AUTOCOMMENT_JIRA_PASS"]) {
+        creds.jira_pass = pass;
+    }
+    if let Some(domain) = first(&["AUTOCOMMENT_JIRA_DOMAIN"]) {
+        creds.jira_domain = domain;
+    }
+    if let Some(flavor) = first(&["AUTOCOMMENT_JIRA_FLAVOR"]) {
+        creds.jira_flavor = flavor.parse()?;
+    }
+    if let Some(tracker) = first(&["AUTOCOMMENT_TRACKER"]) {
+        creds.tracker = tracker.parse()?;
+    }
+
+    if let Some(user) = first(&["AUTOCOMMENT_GITHUB_USER", "GITHUB_ACTOR"]) {
+        creds.github_user = user;
+    }
+    if let Some(token) = first(&["AUTOCOMMENT_GITHUB_TOKEN", "GITHUB_TOKEN"]) {
+        creds.github_pass = token;
+    }
+    if let Some(api_url) = first(&["GITHUB_API_URL"]) {
+        let domain = api_url.trim_start_matches("https://").trim_start_matches("http://").trim_end_matches('/');
+        creds.github_domain = domain.to_string();
+    }
+
+    if let Some(projects) = first(&["AUTOCOMMENT_TICKET_PROJECTS"]) {
+        creds.ticket_projects = list(projects);
+    }
+    if let Some(statuses) = first(&["AUTOCOMMENT_TICKET_STATUSES"]) {
+        creds.ticket_statuses = list(statuses);
+    }
+
+    Ok(creds)
+}
+
+/// Reads the pull request from a Github `pull_request` or
+/// `pull_request_target` event payload, such as the one at $GITHUB_EVENT_PATH
+pub fn read_event(path: &Path) -> Result<ChangeRequest, Error> {
+    let payload = std::fs::read_to_string(path)?;
+    let event: GHPullRequestEvent = serde_json::from_str(payload.as_str())
+        .map_err(|err| Error::ConfigError(format!("{} isn't a pull_request event: {}", path.display(), err)))?;
+
+    Ok(event.pull_request)
+}
+
+/// Appends Markdown to the job summary file at $GITHUB_STEP_SUMMARY, which
+/// Github shows on the workflow run's summary page
+pub fn write_step_summary(path: &Path, markdown: &str) -> Result<(), Error> {
+    let mut f = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    f.write_all(markdown.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use crate::action::{credentials_from_vars, read_event};
+    use crate::credentials::Credentials;
+    use crate::tracker::Tracker;
+
+    #[test]
+    fn reads_credentials_from_vars() {
+        let vars = HashMap::from([
+            ("AUTOCOMMENT_JIRA_DOMAIN", "example.atlassian.net"),
+            ("AUTOCOMMENT_JIRA_PASS", "jira-token"),
+            ("AUTOCOMMENT_TRACKER", "jira"),
+            ("AUTOCOMMENT_TICKET_PROJECTS", "ABC, DEF"),
+            ("GITHUB_ACTOR", "octocat"),
+            ("GITHUB_TOKEN", "ghs_token"),
+            ("GITHUB_API_URL", "https://api.github.com"),
+        ]);
+
+        let base = Credentials { jira_user: "bot@example.com".to_string(), ..Default::default() };
+        let creds = credentials_from_vars(base, |name| vars.get(name).map(|value| value.to_string())).unwrap();
+
+        assert_eq!(creds.jira_user, "bot@example.com");
+        assert_eq!(creds.jira_pass, "jira-token");
+        assert_eq!(creds.jira_domain, "example.atlassian.net");
+        assert_eq!(creds.tracker, Tracker::Jira);
+        assert_eq!(creds.ticket_projects, vec!["ABC".to_string(), "DEF".to_string()]);
+        assert_eq!(creds.github_user, "octocat");
+        assert_eq!(creds.github_pass, "ghs_token");
+        assert_eq!(creds.github_domain, "api.github.com");
+
+        let err = credentials_from_vars(Credentials::default(), |name| (name == "AUTOCOMMENT_TRACKER").then(|| "trello".to_string()));
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn reads_pull_request_from_event() {
+        let pr = read_event(Path::new("tests/fixtures/pull_request_opened.json")).unwrap();
+        assert_eq!(pr.html_url, "https://github.com/org/repo/pull/42");
+        assert_eq!(pr.base.repo.full_name, "org/repo");
+    }
+}
@@ -0,0 +1,540 @@
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+
+/// Root of an Atlassian Document Format document, used as the body of Jira
+/// Cloud comments. `Document::validate` checks the document against the ADF
+/// schema's rules for which nodes can contain which.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Document {
+    pub version: u8,
+
+    #[serde(rename = "type")]
+    pub doc_type: DocType,
+
+    pub content: Vec<Node>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DocType {
+    Doc,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Node {
+    Paragraph {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        content: Vec<Node>,
+    },
+    Heading {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        content: Vec<Node>,
+        attrs: HeadingAttrs,
+    },
+    Text {
+        text: String,
+
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        marks: Vec<Mark>,
+    },
+    HardBreak,
+    Rule,
+    BulletList {
+        content: Vec<Node>,
+    },
+    OrderedList {
+        content: Vec<Node>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        attrs: Option<OrderedListAttrs>,
+    },
+    ListItem {
+        content: Vec<Node>,
+    },
+    CodeBlock {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        content: Vec<Node>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        attrs: Option<CodeBlockAttrs>,
+    },
+    Blockquote {
+        content: Vec<Node>,
+    },
+    Panel {
+        content: Vec<Node>,
+        attrs: PanelAttrs,
+    },
+    Table {
+        content: Vec<Node>,
+    },
+    TableRow {
+        content: Vec<Node>,
+    },
+    TableHeader {
+        content: Vec<Node>,
+    },
+    TableCell {
+        content: Vec<Node>,
+    },
+    TaskList {
+        content: Vec<Node>,
+        attrs: LocalIdAttrs,
+    },
+    TaskItem {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        content: Vec<Node>,
+        attrs: TaskItemAttrs,
+    },
+    Mention {
+        attrs: MentionAttrs,
+    },
+    Emoji {
+        attrs: EmojiAttrs,
+    },
+    Status {
+        attrs: StatusAttrs,
+    },
+    InlineCard {
+        attrs: InlineCardAttrs,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Mark {
+    Strong,
+    Em,
+    Code,
+    Strike,
+    Underline,
+    Link {
+        attrs: LinkAttrs,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct HeadingAttrs {
+    pub level: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct OrderedListAttrs {
+    pub order: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CodeBlockAttrs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PanelAttrs {
+    #[serde(rename = "panelType")]
+    pub panel_type: PanelType,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PanelType {
+    Info,
+    Note,
+    Warning,
+    Success,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LocalIdAttrs {
+    #[serde(rename = "localId")]
+    pub local_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TaskItemAttrs {
+    #[serde(rename = "localId")]
+    pub local_id: String,
+    pub state: TaskState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TaskState {
+    Todo,
+    Done,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MentionAttrs {
+    /// Atlassian account ID of the mentioned user
+    pub id: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct EmojiAttrs {
+    #[serde(rename = "shortName")]
+    pub short_name: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct StatusAttrs {
+    pub text: String,
+    pub color: StatusColor,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusColor {
+    Neutral,
+    Purple,
+    Blue,
+    Red,
+    Yellow,
+    Green,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct InlineCardAttrs {
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LinkAttrs {
+    pub href: String,
+}
+
+impl Document {
+    pub fn new(content: Vec<Node>) -> Self {
+        Document { version: 1, doc_type: DocType::Doc, content }
+    }
+
+    /// Appends a block to the end of the document
+    pub fn push(mut self, node: Node) -> Self {
+        self.content.push(node);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.version != 1 {
+            return Err(Error::from(format!("Unsupported ADF version {}", self.version)));
+        }
+
+        for child in &self.content {
+            if !child.is_block() {
+                return Err(Error::from(format!("ADF node {} is not allowed inside doc", child.type_name())));
+            }
+            child.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Node {
+    pub fn paragraph(content: Vec<Node>) -> Self {
+        Node::Paragraph { content }
+    }
+
+    pub fn heading(level: u8, content: Vec<Node>) -> Self {
+        Node::Heading { content, attrs: HeadingAttrs { level } }
+    }
+
+    pub fn text(text: &str) -> Self {
+        Node::Text { text: text.to_string(), marks: Vec::new() }
+    }
+
+    pub fn link(text: &str, href: &str) -> Self {
+        Node::text(text).with_mark(Mark::link(href))
+    }
+
+    pub fn hard_break() -> Self {
+        Node::HardBreak
+    }
+
+    pub fn rule() -> Self {
+        Node::Rule
+    }
+
+    pub fn bullet_list(items: Vec<Node>) -> Self {
+        Node::BulletList { content: items }
+    }
+
+    pub fn ordered_list(order: u64, items: Vec<Node>) -> Self {
+        Node::OrderedList { content: items, attrs: Some(OrderedListAttrs { order }) }
+    }
+
+    pub fn list_item(content: Vec<Node>) -> Self {
+        Node::ListItem { content }
+    }
+
+    pub fn code_block(language: Option<&str>, code: &str) -> Self {
+        let content = if code.is_empty() { Vec::new() } else { vec![Node::text(code)] };
+        let attrs = language.map(|language| CodeBlockAttrs { language: Some(language.to_string()) });
+        Node::CodeBlock { content, attrs }
+    }
+
+    pub fn blockquote(content: Vec<Node>) -> Self {
+        Node::Blockquote { content }
+    }
+
+    pub fn panel(panel_type: PanelType, content: Vec<Node>) -> Self {
+        Node::Panel { content, attrs: PanelAttrs { panel_type } }
+    }
+
+    pub fn table(rows: Vec<Node>) -> Self {
+        Node::Table { content: rows }
+    }
+
+    pub fn table_row(cells: Vec<Node>) -> Self {
+        Node::TableRow { content: cells }
+    }
+
+    pub fn table_header(content: Vec<Node>) -> Self {
+        Node::TableHeader { content }
+    }
+
+    pub fn table_cell(content: Vec<Node>) -> Self {
+        Node::TableCell { content }
+    }
+
+    pub fn task_list(local_id: &str, items: Vec<Node>) -> Self {
+        Node::TaskList { content: items, attrs: LocalIdAttrs { local_id: local_id.to_string() } }
+    }
+
+    pub fn task_item(local_id: &str, state: TaskState, content: Vec<Node>) -> Self {
+        Node::TaskItem { content, attrs: TaskItemAttrs { local_id: local_id.to_string(), state } }
+    }
+
+    pub fn mention(account_id: &str, text: Option<&str>) -> Self {
+        Node::Mention { attrs: MentionAttrs { id: account_id.to_string(), text: text.map(str::to_string) } }
+    }
+
+    pub fn emoji(short_name: &str) -> Self {
+        Node::Emoji { attrs: EmojiAttrs { short_name: short_name.to_string(), text: None } }
+    }
+
+    pub fn status(text: &str, color: StatusColor) -> Self {
+        Node::Status { attrs: StatusAttrs { text: text.to_string(), color } }
+    }
+
+    pub fn inline_card(url: &str) -> Self {
+        Node::InlineCard { attrs: InlineCardAttrs { url: url.to_string() } }
+    }
+
+    /// Adds a mark to a text node. Other nodes can't have marks, so they are
+    /// returned unchanged.
+    pub fn with_mark(mut self, mark: Mark) -> Self {
+        if let Node::Text { marks, .. } = &mut self {
+            marks.push(mark);
+        }
+        self
+    }
+
+    /// Appends a child node, for nodes which have content
+    pub fn push(mut self, child: Node) -> Self {
+        if let Some(content) = self.content_mut() {
+            content.push(child);
+        }
+        self
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Node::Paragraph { .. } => "paragraph",
+            Node::Heading { .. } => "heading",
+            Node::Text { .. } => "text",
+            Node::HardBreak => "hardBreak",
+            Node::Rule => "rule",
+            Node::BulletList { .. } => "bulletList",
+            Node::OrderedList { .. } => "orderedList",
+            Node::ListItem { .. } => "listItem",
+            Node::CodeBlock { .. } => "codeBlock",
+            Node::Blockquote { .. } => "blockquote",
+            Node::Panel { .. } => "panel",
+            Node::Table { .. } => "table",
+            Node::TableRow { .. } => "tableRow",
+            Node::TableHeader { .. } => "tableHeader",
+            Node::TableCell { .. } => "tableCell",
+            Node::TaskList { .. } => "taskList",
+            Node::TaskItem { .. } => "taskItem",
+            Node::Mention { .. } => "mention",
+            Node::Emoji { .. } => "emoji",
+            Node::Status { .. } => "status",
+            Node::InlineCard { .. } => "inlineCard",
+        }
+    }
+
+    pub fn content(&self) -> &[Node] {
+        match self {
+            Node::Paragraph { content }
+            | Node::Heading { content, .. }
+            | Node::BulletList { content }
+            | Node::OrderedList { content, .. }
+            | Node::ListItem { content }
+            | Node::CodeBlock { content, .. }
+            | Node::Blockquote { content }
+            | Node::Panel { content, .. }
+            | Node::Table { content }
+            | Node::TableRow { content }
+            | Node::TableHeader { content }
+            | Node::TableCell { content }
+            | Node::TaskList { content, .. }
+            | Node::TaskItem { content, .. } => content,
+            _ => &[],
+        }
+    }
+
+    pub fn content_mut(&mut self) -> Option<&mut Vec<Node>> {
+        match self {
+            Node::Paragraph { content }
+            | Node::Heading { content, .. }
+            | Node::BulletList { content }
+            | Node::OrderedList { content, .. }
+            | Node::ListItem { content }
+            | Node::CodeBlock { content, .. }
+            | Node::Blockquote { content }
+            | Node::Panel { content, .. }
+            | Node::Table { content }
+            | Node::TableRow { content }
+            | Node::TableHeader { content }
+            | Node::TableCell { content }
+            | Node::TaskList { content, .. }
+            | Node::TaskItem { content, .. } => Some(content),
+            _ => None,
+        }
+    }
+
+    /// Whether the node can be a direct child of the document
+    pub fn is_block(&self) -> bool {
+        matches!(self, Node::Paragraph { .. }
+            | Node::Heading { .. }
+            | Node::Rule
+            | Node::BulletList { .. }
+            | Node::OrderedList { .. }
+            | Node::CodeBlock { .. }
+            | Node::Blockquote { .. }
+            | Node::Panel { .. }
+            | Node::Table { .. }
+            | Node::TaskList { .. })
+    }
+
+    pub fn is_inline(&self) -> bool {
+        matches!(self, Node::Text { .. }
+            | Node::HardBreak
+            | Node::Mention { .. }
+            | Node::Emoji { .. }
+            | Node::Status { .. }
+            | Node::InlineCard { .. })
+    }
+
+    fn allows_child(&self, child: &Node) -> bool {
+        match self {
+            Node::Paragraph { .. } | Node::Heading { .. } | Node::TaskItem { .. } => child.is_inline(),
+            Node::BulletList { .. } | Node::OrderedList { .. } => matches!(child, Node::ListItem { .. }),
+            Node::ListItem { .. } | Node::Blockquote { .. } => matches!(child, Node::Paragraph { .. }
+                | Node::BulletList { .. }
+                | Node::OrderedList { .. }
+                | Node::CodeBlock { .. }),
+            Node::CodeBlock { .. } => matches!(child, Node::Text { marks, .. } if marks.is_empty()),
+            Node::Panel { .. } => matches!(child, Node::Paragraph { .. }
+                | Node::Heading { .. }
+                | Node::BulletList { .. }
+                | Node::OrderedList { .. }),
+            Node::Table { .. } => matches!(child, Node::TableRow { .. }),
+            Node::TableRow { .. } => matches!(child, Node::TableHeader { .. } | Node::TableCell { .. }),
+            Node::TableHeader { .. } | Node::TableCell { .. } => child.is_block()
+                && !matches!(child, Node::Table { .. } | Node::TaskList { .. }),
+            Node::TaskList { .. } => matches!(child, Node::TaskItem { .. } | Node::TaskList { .. }),
+            _ => false,
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for child in self.content() {
+            if !self.allows_child(child) {
+                return Err(Error::from(format!("ADF node {} is not allowed inside {}", child.type_name(), self.type_name())));
+            }
+            child.validate()?;
+        }
+
+        let requires_content = matches!(self, Node::BulletList { .. }
+            | Node::OrderedList { .. }
+            | Node::ListItem { .. }
+            | Node::Blockquote { .. }
+            | Node::Panel { .. }
+            | Node::Table { .. }
+            | Node::TableRow { .. }
+            | Node::TableHeader { .. }
+            | Node::TableCell { .. }
+            | Node::TaskList { .. });
+        if requires_content && self.content().is_empty() {
+            return Err(Error::from(format!("ADF node {} must not be empty", self.type_name())));
+        }
+
+        match self {
+            Node::Heading { attrs, .. } if !(1..=6).contains(&attrs.level) => {
+                Err(Error::from(format!("ADF heading level {} must be between 1 and 6", attrs.level)))
+            }
+            Node::ListItem { content } if !matches!(content.first(), Some(Node::Paragraph { .. } | Node::CodeBlock { .. })) => {
+                Err(Error::from("ADF listItem must start with a paragraph or codeBlock".to_string()))
+            }
+            Node::Text { text, .. } if text.is_empty() => {
+                Err(Error::from("ADF text nodes must not be empty".to_string()))
+            }
+            Node::Text { marks, .. } if marks.contains(&Mark::Code) && marks.iter().any(|mark| !matches!(mark, Mark::Code | Mark::Link { .. })) => {
+                Err(Error::from("ADF code marks can only be combined with links".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Mark {
+    pub fn link(href: &str) -> Self {
+        Mark::Link { attrs: LinkAttrs { href: href.to_string() } }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::adf::{Document, Mark, Node, PanelType, StatusColor};
+
+    #[test]
+    fn round_trips_through_json() {
+        let doc = Document::new(vec![
+            Node::panel(PanelType::Info, vec![
+                Node::paragraph(vec![
+                    Node::mention("abc123", Some("@me")),
+                    Node::text(" status ").with_mark(Mark::Strong),
+                    Node::status("In Review", StatusColor::Blue),
+                    Node::emoji(":tada:"),
+                ]),
+            ]),
+            Node::code_block(Some("rust"), "fn main() {}"),
+        ]);
+
+        let json = serde_json::to_string(&doc).unwrap();
+        assert_eq!(json, "{\"version\":1,\"type\":\"doc\",\"content\":[{\"type\":\"panel\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"mention\",\"attrs\":{\"id\":\"abc123\",\"text\":\"@me\"}},{\"type\":\"text\",\"text\":\" status \",\"marks\":[{\"type\":\"strong\"}]},{\"type\":\"status\",\"attrs\":{\"text\":\"In Review\",\"color\":\"blue\"}},{\"type\":\"emoji\",\"attrs\":{\"shortName\":\":tada:\"}}]}],\"attrs\":{\"panelType\":\"info\"}},{\"type\":\"codeBlock\",\"content\":[{\"type\":\"text\",\"text\":\"fn main() {}\"}],\"attrs\":{\"language\":\"rust\"}}]}");
+        assert_eq!(doc, serde_json::from_str(&json).unwrap());
+        assert!(doc.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_invalid_nesting() {
+        assert!(Document::new(vec![Node::text("loose text")]).validate().is_err());
+        assert!(Document::new(vec![Node::paragraph(vec![Node::paragraph(Vec::new())])]).validate().is_err());
+        assert!(Document::new(vec![Node::bullet_list(vec![Node::paragraph(Vec::new())])]).validate().is_err());
+        assert!(Document::new(vec![Node::bullet_list(Vec::new())]).validate().is_err());
+        assert!(Document::new(vec![Node::heading(7, vec![Node::text("title")])]).validate().is_err());
+        assert!(Document::new(vec![Node::paragraph(vec![Node::text("code").with_mark(Mark::Code).with_mark(Mark::Strong)])]).validate().is_err());
+    }
+}
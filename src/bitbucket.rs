@@ -0,0 +1,564 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use tokio::sync::{OnceCell, Semaphore};
+
+use crate::credentials::{Credentials, ProviderCredentials};
+use crate::error::Error;
+use crate::http::{self, HttpResponse};
+use crate::pool;
+use crate::review::{Account, AsyncReviewClient, ChangeBase, ChangeHead, ChangeRepo, ChangeRequest, Provider, ReviewClient, UpdatedChanges};
+
+/// Number of pull requests requested per page from Bitbucket Cloud, the most
+/// it allows
+const CLOUD_PAGE_LEN: usize = 50;
+
+/// Number of pull requests requested per page from Bitbucket Server
+const SERVER_PAGE_LIMIT: u64 = 100;
+
+/// Every state a Bitbucket Cloud pull request can be in
+const CLOUD_STATES: [&str; 4] = ["OPEN", "MERGED", "DECLINED", "SUPERSEDED"];
+
+/// A page of results from the Bitbucket Cloud 2.0 API
+#[derive(Deserialize)]
+struct BBPage<T> {
+    values: Vec<T>,
+
+    /// URL of the next page, including the original query
+    next: Option<String>,
+}
+
+/// Representation of a Bitbucket Cloud pull request, only including the
+/// fields needed to build a `ChangeRequest`
+#[derive(Deserialize, Clone)]
+pub struct BBPullRequest {
+    pub title: String,
+    pub description: Option<String>,
+    pub state: String,
+    pub created_on: String,
+    pub updated_on: String,
+    pub author: BBAccount,
+    pub source: BBSource,
+    pub links: BBLinks,
+
+    /// Only returned when fetching a single pull request
+    #[serde(default)]
+    pub reviewers: Vec<BBAccount>,
+}
+
+impl BBPullRequest {
+    /// Converts the pull request into a change in the repo. Declined and
+    /// superseded pull requests are closed, and Bitbucket doesn't record when
+    /// a pull request was merged so its last update is used.
+    pub fn into_change(self, repo: &str) -> ChangeRequest {
+        let merged_at = if self.state == "MERGED" { Some(self.updated_on.clone()) } else { None };
+
+        ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: repo.to_string() } },
+            html_url: self.links.html.href,
+            title: self.title,
+            body: self.description,
+            created_at: self.created_on,
+            user: Account { login: self.author.nickname },
+            state: if self.state == "OPEN" { "open" } else { "closed" }.to_string(),
+            merged_at,
+            updated_at: self.updated_on,
+            head: ChangeHead { branch: self.source.branch.name, sha: None },
+            labels: Vec::new(),
+            requested_reviewers: self.reviewers.into_iter().map(|user| Account { login: user.nickname }).collect(),
+            provider: Provider::Bitbucket,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BBAccount {
+    #[serde(default)]
+    pub nickname: String,
+    pub account_id: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BBSource {
+    pub branch: BBBranch,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BBBranch {
+    pub name: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BBLinks {
+    pub html: BBLink,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BBLink {
+    pub href: String,
+}
+
+/// Converts filters written as Github's pull request query parameters into
+/// Bitbucket Cloud's parameters. Other parameters, such as `q`, are passed
+/// through unchanged.
+pub fn bitbucket_filters(filters: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+
+    for (key, value) in split_filters(filters) {
+        match (key.as_str(), value.as_str()) {
+            ("state", "open") => params.push((key, "OPEN".to_string())),
+            ("state", "closed") => params.extend(CLOUD_STATES[1..].iter().map(|state| ("state".to_string(), state.to_string()))),
+            ("state", "all") => params.extend(CLOUD_STATES.iter().map(|state| ("state".to_string(), state.to_string()))),
+            ("sort", "updated") => params.push((key, "-updated_on".to_string())),
+            ("sort", "created") => params.push((key, "-created_on".to_string())),
+            ("direction", _) => {}
+            _ => params.push((key, value)),
+        }
+    }
+
+    params
+}
+
+fn split_filters(filters: &str) -> Vec<(String, String)> {
+    filters.trim_start_matches('?')
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            (key.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+async fn send(client: &Client, limit: &Semaphore, request: RequestBuilder) -> Result<HttpResponse, Error> {
+    let resp = http::send(client, limit, request).await?;
+    if resp.is_success() {
+        Ok(resp)
+    } else {
+        Err(resp.error())
+    }
+}
+
+/// Bitbucket Cloud client which sends requests to the 2.0 API with the async
+/// reqwest client, authenticating with an app password
+pub struct DefaultAsyncBitbucketClient {
+    client: Client,
+    creds: ProviderCredentials,
+
+    /// Limits the requests sent to Bitbucket at once
+    limit: Semaphore,
+
+    /// Account ID of the configured user, which pull request authors are
+    /// matched against
+    account_id: OnceCell<String>,
+}
+
+impl DefaultAsyncBitbucketClient {
+    /// `creds` are used for the host limit of the Bitbucket domain
+    pub fn new(bitbucket: &ProviderCredentials, creds: &Credentials) -> DefaultAsyncBitbucketClient {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let limit = Semaphore::new(creds.host_limit(bitbucket.domain.as_str()).max(1));
+        DefaultAsyncBitbucketClient { client, creds: bitbucket.clone(), limit, account_id: OnceCell::new() }
+    }
+
+    fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+            .basic_auth(self.creds.user.as_str(), Some(self.creds.token.as_str()))
+    }
+
+    async fn account_id(&self) -> Result<&str, Error> {
+        let account_id = self.account_id.get_or_try_init(|| async {
+            let url = format!("{}/2.0/user", self.creds.base_url());
+            let user: BBAccount = send(&self.client, &self.limit, self.get(&url)).await?.parse()?;
+            user.account_id.ok_or(Error::from(format!("Bitbucket user {} doesn't have an account ID", self.creds.user)))
+        }).await?;
+
+        Ok(account_id.as_str())
+    }
+
+    /// Fetches the repo's pull requests, a page at a time. Paging stops once
+    /// `keep` rejects a pull request or there are no more pages.
+    async fn pull_requests<F>(&self, repo: &str, params: Vec<(String, String)>, keep: F) -> Result<Vec<BBPullRequest>, Error>
+        where F: Fn(&BBPullRequest) -> bool + Send + Sync
+    {
+        let url = format!("{}/2.0/repositories/{}/pullrequests", self.creds.base_url(), repo);
+        let mut request = self.get(&url).query(&params).query(&[("pagelen", CLOUD_PAGE_LEN)]);
+        let mut changes = Vec::new();
+
+        loop {
+            let page: BBPage<BBPullRequest> = send(&self.client, &self.limit, request).await?.parse()?;
+
+            let count = page.values.len();
+            let kept: Vec<BBPullRequest> = page.values.into_iter().take_while(&keep).collect();
+            let stopped = kept.len() < count;
+
+            changes.extend(kept);
+
+            match page.next {
+                Some(next) if !stopped => request = self.get(&next),
+                _ => break,
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[async_trait]
+impl AsyncReviewClient for DefaultAsyncBitbucketClient {
+    async fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        let author = self.account_id().await?.to_string();
+        let prs = self.pull_requests(repo, bitbucket_filters(filters), |_| true).await?;
+
+        Ok(prs.into_iter()
+            .filter(|pr| pr.author.account_id.as_deref() == Some(author.as_str()))
+            .map(|pr| pr.into_change(repo))
+            .collect())
+    }
+
+    async fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        let author = self.account_id().await?.to_string();
+        let mut params = bitbucket_filters("?state=all&sort=updated");
+        if let Some(since) = since {
+            params.push(("q".to_string(), format!("updated_on > {}", since)));
+        }
+
+        let prs = self.pull_requests(repo, params, |pr| since.map(|since| pr.updated_on.as_str() > since).unwrap_or(true)).await?;
+        let latest_update = prs.first().map(|pr| pr.updated_on.clone());
+
+        let changes = prs.into_iter()
+            .filter(|pr| pr.author.account_id.as_deref() == Some(author.as_str()))
+            .map(|pr| pr.into_change(repo))
+            .collect();
+        Ok(UpdatedChanges { changes, latest_update })
+    }
+
+    /// Bitbucket Cloud doesn't show other users' email addresses
+    async fn get_user_email(&self, _login: &str) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+}
+
+/// Blocking Bitbucket Cloud client, which waits for
+/// `DefaultAsyncBitbucketClient`'s requests on a shared runtime. It can't be
+/// used from inside an async runtime.
+pub struct DefaultBitbucketClient {
+    inner: DefaultAsyncBitbucketClient,
+}
+
+impl DefaultBitbucketClient {
+    pub fn new(bitbucket: &ProviderCredentials, creds: &Credentials) -> DefaultBitbucketClient {
+        DefaultBitbucketClient { inner: DefaultAsyncBitbucketClient::new(bitbucket, creds) }
+    }
+}
+
+impl ReviewClient for DefaultBitbucketClient {
+    fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        pool::block_on(self.inner.get_changes_for_repo(repo, filters))
+    }
+
+    fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        pool::block_on(self.inner.get_changes_updated_since(repo, since))
+    }
+
+    fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.get_user_email(login))
+    }
+}
+
+/// A page of results from the Bitbucket Server 1.0 API
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BBServerPage<T> {
+    values: Vec<T>,
+    is_last_page: bool,
+    next_page_start: Option<u64>,
+}
+
+/// Representation of a Bitbucket Server or Data Center pull request, only
+/// including the fields needed to build a `ChangeRequest`
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BBServerPullRequest {
+    pub title: String,
+    pub description: Option<String>,
+    pub state: String,
+
+    /// Milliseconds since the Unix epoch
+    pub created_date: i64,
+    pub updated_date: i64,
+    pub closed_date: Option<i64>,
+
+    pub author: BBServerParticipant,
+    pub from_ref: BBServerRef,
+    pub links: BBServerLinks,
+
+    #[serde(default)]
+    pub reviewers: Vec<BBServerParticipant>,
+}
+
+impl BBServerPullRequest {
+    /// Converts the pull request into a change in the repo, which is named
+    /// `PROJECT/repo`. Declined pull requests are closed.
+    pub fn into_change(self, repo: &str) -> ChangeRequest {
+        let merged_at = match self.state.as_str() {
+            "MERGED" => Some(timestamp(self.closed_date.unwrap_or(self.updated_date))),
+            _ => None,
+        };
+
+        ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: repo.to_string() } },
+            html_url: self.links.self_links.into_iter().next().map(|link| link.href).unwrap_or_default(),
+            title: self.title,
+            body: self.description,
+            created_at: timestamp(self.created_date),
+            user: Account { login: self.author.user.name },
+            state: if self.state == "OPEN" { "open" } else { "closed" }.to_string(),
+            merged_at,
+            updated_at: timestamp(self.updated_date),
+            head: ChangeHead { branch: self.from_ref.display_id, sha: None },
+            labels: Vec::new(),
+            requested_reviewers: self.reviewers.into_iter().map(|reviewer| Account { login: reviewer.user.name }).collect(),
+            provider: Provider::BitbucketServer,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BBServerParticipant {
+    pub user: BBServerUser,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BBServerUser {
+    /// The user's slug, which they log in with
+    pub name: String,
+    pub email_address: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BBServerRef {
+    pub display_id: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BBServerLinks {
+    #[serde(rename = "self")]
+    pub self_links: Vec<BBLink>,
+}
+
+/// Converts filters written as Github's pull request query parameters into
+/// Bitbucket Server's parameters. Bitbucket Server can't list only closed
+/// pull requests, so the second value is whether open pull requests need to be
+/// filtered out.
+pub fn bitbucket_server_filters(filters: &str) -> (Vec<(String, String)>, bool) {
+    let mut params = Vec::new();
+    let mut closed_only = false;
+
+    for (key, value) in split_filters(filters) {
+        match (key.as_str(), value.as_str()) {
+            ("state", "open") => params.push((key, "OPEN".to_string())),
+            ("state", "all") => params.push((key, "ALL".to_string())),
+            ("state", "closed") => {
+                params.push((key, "ALL".to_string()));
+                closed_only = true;
+            }
+            ("sort", _) | ("direction", _) => {}
+            _ => params.push((key, value)),
+        }
+    }
+
+    (params, closed_only)
+}
+
+/// Formats milliseconds since the Unix epoch as an RFC 3339 UTC time, like
+/// Github's timestamps
+pub fn timestamp(millis: i64) -> String {
+    let secs = millis.div_euclid(1000);
+    let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // Converts days since the epoch to a date in the proleptic Gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// Bitbucket Server and Data Center client which sends requests to the 1.0
+/// REST API with the async reqwest client, authenticating with an HTTP access
+/// token
+pub struct DefaultAsyncBitbucketServerClient {
+    client: Client,
+    creds: ProviderCredentials,
+
+    /// Limits the requests sent to Bitbucket at once
+    limit: Semaphore,
+}
+
+impl DefaultAsyncBitbucketServerClient {
+    /// `creds` are used for the host limit of the Bitbucket domain
+    pub fn new(bitbucket: &ProviderCredentials, creds: &Credentials) -> DefaultAsyncBitbucketServerClient {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let limit = Semaphore::new(creds.host_limit(bitbucket.domain.as_str()).max(1));
+        DefaultAsyncBitbucketServerClient { client, creds: bitbucket.clone(), limit }
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.client.get(format!("{}/rest/api/1.0{}", self.creds.base_url(), path))
+            .bearer_auth(self.creds.token.as_str())
+    }
+
+    /// Fetches every page of the repo's pull requests. The repo is named
+    /// `PROJECT/repo`.
+    async fn pull_requests(&self, repo: &str, params: Vec<(String, String)>) -> Result<Vec<ChangeRequest>, Error> {
+        let (project, slug) = repo.split_once('/')
+            .ok_or(Error::ConfigError(format!("Bitbucket Server repo {} should be named PROJECT/repo", repo)))?;
+        let path = format!("/projects/{}/repos/{}/pull-requests", project, slug);
+
+        let mut changes = Vec::new();
+        let mut start = 0;
+
+        loop {
+            let request = self.get(&path)
+                .query(&params)
+                .query(&[("start", start), ("limit", SERVER_PAGE_LIMIT)]);
+            let page: BBServerPage<BBServerPullRequest> = send(&self.client, &self.limit, request).await?.parse()?;
+
+            changes.extend(page.values.into_iter().map(|pr| pr.into_change(repo)));
+
+            match page.next_page_start {
+                Some(next) if !page.is_last_page => start = next,
+                _ => break,
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[async_trait]
+impl AsyncReviewClient for DefaultAsyncBitbucketServerClient {
+    async fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        let (mut params, closed_only) = bitbucket_server_filters(filters);
+        params.push(("role.1".to_string(), "AUTHOR".to_string()));
+        params.push(("username.1".to_string(), self.creds.user.clone()));
+        let changes = self.pull_requests(repo, params).await?;
+
+        Ok(changes.into_iter().filter(|change| !closed_only || change.state == "closed").collect())
+    }
+
+    /// Bitbucket Server can't sort pull requests by when they were updated,
+    /// so every page is fetched and then filtered
+    async fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        let (params, _) = bitbucket_server_filters("?state=all");
+        let mut changes: Vec<ChangeRequest> = self.pull_requests(repo, params).await?.into_iter()
+            .filter(|change| since.map(|since| change.updated_at.as_str() > since).unwrap_or(true))
+            .collect();
+
+        changes.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        Ok(UpdatedChanges::new(changes, |change| change.user.login == self.creds.user))
+    }
+
+    async fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        let user: BBServerUser = send(&self.client, &self.limit, self.get(&format!("/users/{}", login))).await?.parse()?;
+        Ok(user.email_address)
+    }
+}
+
+/// Blocking Bitbucket Server client, which waits for
+/// `DefaultAsyncBitbucketServerClient`'s requests on a shared runtime. It
+/// can't be used from inside an async runtime.
+pub struct DefaultBitbucketServerClient {
+    inner: DefaultAsyncBitbucketServerClient,
+}
+
+impl DefaultBitbucketServerClient {
+    pub fn new(bitbucket: &ProviderCredentials, creds: &Credentials) -> DefaultBitbucketServerClient {
+        DefaultBitbucketServerClient { inner: DefaultAsyncBitbucketServerClient::new(bitbucket, creds) }
+    }
+}
+
+impl ReviewClient for DefaultBitbucketServerClient {
+    fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        pool::block_on(self.inner.get_changes_for_repo(repo, filters))
+    }
+
+    fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        pool::block_on(self.inner.get_changes_updated_since(repo, since))
+    }
+
+    fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.get_user_email(login))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bitbucket::{bitbucket_filters, bitbucket_server_filters, timestamp, BBPullRequest, BBServerPullRequest};
+    use crate::jira::parse_jira_ticket_number;
+
+    const CLOUD_MERGED: &str = include_str!("../tests/fixtures/bitbucket_pull_request_merged.json");
+    const SERVER_OPEN: &str = include_str!("../tests/fixtures/bitbucket_server_pull_request_open.json");
+
+    #[test]
+    fn converts_cloud_pull_request() {
+        let pr: BBPullRequest = serde_json::from_str(CLOUD_MERGED).unwrap();
+        let change = pr.into_change("workspace/repo");
+
+        assert_eq!(change.html_url, "https://bitbucket.org/workspace/repo/pull-requests/12");
+        assert_eq!(change.state, "closed");
+        assert_eq!(change.merged_at.as_deref(), Some("2023-01-05T16:20:00.123456+00:00"));
+        assert_eq!(change.head.branch, "feature/retry");
+        assert_eq!(parse_jira_ticket_number(change.body.as_deref().unwrap(), "jira.domain"), Some("ABC-123".to_string()));
+        assert_eq!(change.build_jira_remote_link().object.summary.as_deref(), Some("Pull Request in workspace/repo (merged)"));
+    }
+
+    #[test]
+    fn converts_server_pull_request() {
+        let pr: BBServerPullRequest = serde_json::from_str(SERVER_OPEN).unwrap();
+        let change = pr.into_change("PROJ/repo");
+
+        assert_eq!(change.html_url, "https://bitbucket.example.com/projects/PROJ/repos/repo/pull-requests/3");
+        assert_eq!(change.state, "open");
+        assert_eq!(change.created_at, "2023-01-01T00:00:00Z");
+        assert_eq!(change.user.login, "me");
+        assert_eq!(change.requested_reviewers.iter().map(|user| user.login.as_str()).collect::<Vec<_>>(), vec!["reviewer"]);
+        assert_eq!(parse_jira_ticket_number(change.body.as_deref().unwrap(), "jira.domain"), Some("ABC-124".to_string()));
+    }
+
+    #[test]
+    fn converts_github_filters() {
+        let params = bitbucket_filters("?state=closed&sort=updated&direction=desc");
+        let params: Vec<(&str, &str)> = params.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+        assert_eq!(params, vec![("state", "MERGED"), ("state", "DECLINED"), ("state", "SUPERSEDED"), ("sort", "-updated_on")]);
+
+        let (params, closed_only) = bitbucket_server_filters("?state=closed&sort=updated");
+        assert_eq!(params, vec![("state".to_string(), "ALL".to_string())]);
+        assert!(closed_only);
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(timestamp(1672531200000), "2023-01-01T00:00:00Z");
+        assert_eq!(timestamp(951825723000), "2000-02-29T12:02:03Z");
+    }
+}
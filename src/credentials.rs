@@ -1,10 +1,16 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use serde::{Serialize, Deserialize};
 
 use crate::error::Error;
+use crate::jira::JiraFlavor;
+use crate::pool::DEFAULT_HOST_LIMIT;
+use crate::review::Provider;
+use crate::template::CommentTemplate;
+use crate::tracker::Tracker;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
 pub struct Credentials {
     /// Jira Username
     pub jira_user: String,
@@ -23,6 +29,160 @@ pub struct Credentials {
 
     /// Github Domain
     pub github_domain: String,
+
+    /// Whether Jira is a Cloud or Server deployment
+    #[serde(default)]
+    pub jira_flavor: JiraFlavor,
+
+    /// Named comment templates
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub templates: HashMap<String, String>,
+
+    /// Secret shared with Github to sign webhook deliveries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_secret: Option<String>,
+
+    /// Jira account IDs (or usernames on Server) to mention, keyed by Github login
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub users: HashMap<String, String>,
+
+    /// Whether to find the Jira accounts of Github users without a configured
+    /// account by searching for their public email address
+    #[serde(default)]
+    pub resolve_users_by_email: bool,
+
+    /// Projects referenced tickets must be in when they're validated, such
+    /// as ABC for ABC-123. Tickets in any project are allowed when it's empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ticket_projects: Vec<String>,
+
+    /// Statuses referenced tickets must be in when they're validated, such
+    /// as In Progress. Tickets in any status are allowed when it's empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ticket_statuses: Vec<String>,
+
+    /// Settings for individual repositories, keyed by the repository's full name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub repos: HashMap<String, RepoConfig>,
+
+    /// Number of pull requests to process at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+
+    /// Maximum number of requests to send to a host at once, keyed by domain
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub host_limits: HashMap<String, usize>,
+
+    /// GitLab instance and access token, for repositories hosted on GitLab
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gitlab: Option<ProviderCredentials>,
+
+    /// Bitbucket Cloud user and app password, for repositories hosted on
+    /// Bitbucket Cloud. The domain is api.bitbucket.org.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitbucket: Option<ProviderCredentials>,
+
+    /// Bitbucket Server or Data Center instance and HTTP access token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitbucket_server: Option<ProviderCredentials>,
+
+    /// Gitea or Forgejo instance and access token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gitea: Option<ProviderCredentials>,
+
+    /// The issue tracker tickets are synced to, Jira unless set
+    #[serde(default)]
+    pub tracker: Tracker,
+
+    /// Linear workspace and API key, when Linear is the tracker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linear: Option<LinearCredentials>,
+
+    /// YouTrack instance and permanent token, when YouTrack is the tracker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub youtrack: Option<TrackerCredentials>,
+
+    /// Redmine instance and API key, when Redmine is the tracker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redmine: Option<TrackerCredentials>,
+}
+
+/// Credentials for a code review provider other than Github
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct ProviderCredentials {
+    /// Domain of the provider's instance, such as gitlab.com. A URL with a
+    /// scheme, like http://localhost:3000, can be used for instances which
+    /// aren't served over HTTPS.
+    pub domain: String,
+
+    /// User whose changes are synced
+    pub user: String,
+
+    /// Access token for the user
+    pub token: String,
+}
+
+impl ProviderCredentials {
+    /// The scheme and host requests are sent to
+    pub fn base_url(&self) -> String {
+        base_url(&self.domain)
+    }
+}
+
+/// Credentials for a self-hosted issue tracker other than Jira
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct TrackerCredentials {
+    /// Domain of the tracker's instance. Like `ProviderCredentials`, a URL
+    /// with a scheme can be used for instances which aren't served over HTTPS.
+    pub domain: String,
+
+    /// Access token or API key
+    pub token: String,
+}
+
+impl TrackerCredentials {
+    /// The scheme and host requests are sent to
+    pub fn base_url(&self) -> String {
+        base_url(&self.domain)
+    }
+
+    /// The domain without a scheme, which ticket links point to
+    pub fn host(&self) -> &str {
+        self.domain.trim_start_matches("https://").trim_start_matches("http://").trim_end_matches('/')
+    }
+}
+
+fn base_url(domain: &str) -> String {
+    if domain.starts_with("http://") || domain.starts_with("https://") {
+        domain.trim_end_matches('/').to_string()
+    } else {
+        format!("https://{}", domain)
+    }
+}
+
+/// Credentials for Linear
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct LinearCredentials {
+    /// The workspace's URL key, as in https://linear.app/<workspace>
+    pub workspace: String,
+
+    /// Personal API key
+    pub api_key: String,
+
+    /// GraphQL endpoint requests are sent to, which defaults to Linear's API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct RepoConfig {
+    /// Name of the comment template to use for this repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    /// Where the repository is hosted
+    #[serde(default)]
+    pub provider: Provider,
 }
 
 impl Credentials {
@@ -49,11 +209,52 @@ impl Credentials {
         serde_yaml::to_writer(f, self).map_err(Error::from)
     }
 
-    /// Gets the default config file from the current user's home directory or
-    /// from the current directory if there is no home
-    fn config_file() -> PathBuf {
+    /// Gets the comment template for a repository. Uses the repository's
+    /// configured template, then the template named "default", and finally
+    /// the built in template.
+    pub fn template_for_repo(&self, repo: &str) -> Result<CommentTemplate, Error> {
+        let name = self.repos.get(repo)
+            .and_then(|repo_config| repo_config.template.as_deref());
+
+        match name {
+            Some(name) => {
+                let source = self.templates.get(name)
+                    .ok_or(Error::ConfigError(format!("Template {} for repo {} is not defined", name, repo)))?;
+                parse_template(name, source)
+            }
+            None => match self.templates.get("default") {
+                Some(source) => parse_template("default", source),
+                None => Ok(CommentTemplate::default()),
+            }
+        }
+    }
+
+    /// Gets where a repository is hosted, which is Github unless the
+    /// repository is configured otherwise
+    pub fn provider_for_repo(&self, repo: &str) -> Provider {
+        self.repos.get(repo).map(|repo_config| repo_config.provider).unwrap_or_default()
+    }
+
+    /// Gets the maximum number of requests to send to the host at once
+    pub fn host_limit(&self, host: &str) -> usize {
+        self.host_limits.get(host).copied().unwrap_or(DEFAULT_HOST_LIMIT)
+    }
+
+    /// Gets the directory holding autocomment's files from the current user's
+    /// home directory or from the current directory if there is no home
+    pub fn config_dir() -> PathBuf {
         home::home_dir()
-            .map(|home_dir| home_dir.join(Path::new(".autocomment/config.yaml")))
-            .unwrap_or(PathBuf::from(".autocomment/config.yaml"))
+            .map(|home_dir| home_dir.join(Path::new(".autocomment")))
+            .unwrap_or(PathBuf::from(".autocomment"))
     }
+
+    /// Gets the default config file
+    pub fn config_file() -> PathBuf {
+        Self::config_dir().join("config.yaml")
+    }
+}
+
+fn parse_template(name: &str, source: &str) -> Result<CommentTemplate, Error> {
+    CommentTemplate::parse(source)
+        .map_err(|err| Error::ConfigError(format!("Template {} is invalid: {}", name, err)))
 }
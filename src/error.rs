@@ -1,4 +1,7 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Deserialize};
 use thiserror::Error;
 use crate::Error::AutocommentError;
 
@@ -7,6 +10,41 @@ pub enum Error {
     #[error("Error occurred: {0}")]
     AutocommentError(String),
 
+    /// The config file or something configured in it is missing or invalid
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+
+    /// Github or Jira rejected the configured credentials (401)
+    #[error("Authentication failed: {0}")]
+    AuthError(HttpError),
+
+    /// The configured user isn't allowed to do this (403)
+    #[error("Permission denied: {0}")]
+    PermissionError(HttpError),
+
+    /// The repo, ticket or user doesn't exist, or isn't visible to the
+    /// configured user (404)
+    #[error("Not found: {0}")]
+    NotFoundError(HttpError),
+
+    /// Any other unsuccessful response
+    #[error("Request failed: {0}")]
+    HttpError(HttpError),
+
+    /// A response which couldn't be parsed
+    #[error("Unable to parse response from {url}: {source}")]
+    ParseError {
+        url: String,
+        source: serde_json::Error,
+    },
+
+    /// An error which happened while syncing a particular pull request
+    #[error("{error} ({context})")]
+    ContextError {
+        context: ErrorContext,
+        error: Box<Error>,
+    },
+
     #[error(transparent)]
     SerdeJsonError(#[from] serde_json::Error),
 
@@ -18,6 +56,9 @@ pub enum Error {
 
     #[error(transparent)]
     FsError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
 }
 
 impl From<String> for Error {
@@ -25,3 +66,209 @@ impl From<String> for Error {
         AutocommentError(cause)
     }
 }
+
+/// An unsuccessful response from Github or Jira
+#[derive(Clone, PartialEq, Debug)]
+pub struct HttpError {
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+
+    /// The error message from the response body, or the body itself when it
+    /// doesn't contain one
+    pub message: String,
+}
+
+impl HttpError {
+    /// Whether the request could succeed if it's sent again later
+    pub fn is_retryable(&self) -> bool {
+        self.status == 429 || self.status >= 500
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let status = StatusCode::from_u16(self.status).map(|status| status.to_string()).unwrap_or(self.status.to_string());
+        write!(f, "{} {} returned {}", self.method, self.url, status)?;
+
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// The repo, pull request and ticket an error happened for
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ErrorContext {
+    pub repo: Option<String>,
+    pub pr: Option<String>,
+    pub ticket: Option<String>,
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = [("repo", &self.repo), ("PR", &self.pr), ("ticket", &self.ticket)].iter()
+            .filter_map(|(name, value)| value.as_ref().map(|value| format!("{} {}", name, value)))
+            .collect();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Broad categories of errors, used to decide how to exit
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    Config,
+    Auth,
+    Network,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Config => "config",
+            ErrorKind::Auth => "auth",
+            ErrorKind::Network => "network",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+/// Error bodies returned by Jira and Github. Jira has `errorMessages` and
+/// `errors` for individual fields, while Github has `message`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ErrorBody {
+    #[serde(rename = "errorMessages")]
+    error_messages: Vec<String>,
+    errors: serde_json::Map<String, serde_json::Value>,
+    message: Option<String>,
+}
+
+impl ErrorBody {
+    fn message(body: &str) -> String {
+        let parsed: ErrorBody = match serde_json::from_str(body) {
+            Ok(parsed) => parsed,
+            Err(_) => return body.trim().to_string(),
+        };
+
+        let field_errors = parsed.errors.iter()
+            .map(|(field, error)| format!("{}: {}", field, error.as_str().map(String::from).unwrap_or(error.to_string())));
+        let messages: Vec<String> = parsed.error_messages.into_iter()
+            .chain(field_errors)
+            .chain(parsed.message)
+            .collect();
+
+        if messages.is_empty() {
+            body.trim().to_string()
+        } else {
+            messages.join("; ")
+        }
+    }
+}
+
+impl Error {
+    /// Creates the error for an unsuccessful response from Github or Jira
+    pub fn from_response(method: &str, url: &str, status: StatusCode, body: &str) -> Self {
+        let error = HttpError {
+            method: method.to_string(),
+            url: url.to_string(),
+            status: status.as_u16(),
+            message: ErrorBody::message(body),
+        };
+
+        match status {
+            StatusCode::UNAUTHORIZED => Error::AuthError(error),
+            StatusCode::FORBIDDEN => Error::PermissionError(error),
+            StatusCode::NOT_FOUND => Error::NotFoundError(error),
+            _ => Error::HttpError(error),
+        }
+    }
+
+    /// Parses a successful response body
+    pub fn parse_response<T: DeserializeOwned>(url: &str, body: &str) -> Result<T, Self> {
+        serde_json::from_str(body).map_err(|source| Error::ParseError { url: url.to_string(), source })
+    }
+
+    /// Attaches the repo, pull request or ticket the error happened for.
+    /// Context already attached to the error is kept.
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            Error::ContextError { context: existing, error } => Error::ContextError {
+                context: ErrorContext {
+                    repo: existing.repo.or(context.repo),
+                    pr: existing.pr.or(context.pr),
+                    ticket: existing.ticket.or(context.ticket),
+                },
+                error,
+            },
+            error => Error::ContextError { context, error: Box::new(error) },
+        }
+    }
+
+    /// The error without any context attached to it
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::ContextError { error, .. } => error.root(),
+            error => error,
+        }
+    }
+
+    /// The HTTP response the error was created from, if any
+    pub fn http(&self) -> Option<&HttpError> {
+        match self.root() {
+            Error::AuthError(error) | Error::PermissionError(error) | Error::NotFoundError(error) | Error::HttpError(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    /// Whether the same request could succeed if it's sent again later
+    pub fn is_retryable(&self) -> bool {
+        match self.root() {
+            Error::ReqwestError(err) => err.is_timeout() || err.is_connect(),
+            error => error.http().map(HttpError::is_retryable).unwrap_or(false),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self.root() {
+            Error::ConfigError(_) | Error::SerdeYamlError(_) => ErrorKind::Config,
+            Error::AuthError(_) | Error::PermissionError(_) => ErrorKind::Auth,
+            Error::ReqwestError(_) => ErrorKind::Network,
+            error if error.is_retryable() => ErrorKind::Network,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+
+    use crate::error::{Error, ErrorContext, ErrorKind};
+
+    #[test]
+    fn parses_jira_error_response() {
+        let body = "{\"errorMessages\":[\"You do not have permission to comment on this issue.\"],\"errors\":{}}";
+        let err = Error::from_response("POST", "https://jira.domain/rest/api/3/issue/A-1/comment", StatusCode::FORBIDDEN, body)
+            .with_context(ErrorContext { ticket: Some("A-1".to_string()), ..Default::default() })
+            .with_context(ErrorContext { repo: Some("org/repo".to_string()), pr: Some("https://url/org/repo/1".to_string()), ticket: None });
+
+        assert_eq!(err.to_string(), "Permission denied: POST https://jira.domain/rest/api/3/issue/A-1/comment returned 403 Forbidden: You do not have permission to comment on this issue. (repo org/repo, PR https://url/org/repo/1, ticket A-1)");
+        assert_eq!(err.kind(), ErrorKind::Auth);
+        assert_eq!(err.http().map(|http| http.status), Some(403));
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn parses_github_error_response() {
+        let body = "{\"message\":\"Server Error\",\"documentation_url\":\"https://docs.github.com/rest\"}";
+        let err = Error::from_response("GET", "https://api.github.com/repos/org/repo/pulls", StatusCode::BAD_GATEWAY, body);
+
+        assert_eq!(err.to_string(), "Request failed: GET https://api.github.com/repos/org/repo/pulls returned 502 Bad Gateway: Server Error");
+        assert_eq!(err.kind(), ErrorKind::Network);
+        assert!(err.is_retryable());
+    }
+}
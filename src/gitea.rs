@@ -0,0 +1,242 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+use crate::credentials::{Credentials, ProviderCredentials};
+use crate::error::Error;
+use crate::http::{self, HttpResponse};
+use crate::pool;
+use crate::review::{Account, AsyncReviewClient, ChangeBase, ChangeHead, ChangeRequest, Label, Provider, ReviewClient, UpdatedChanges};
+
+/// Number of pull requests requested per page, Gitea's default maximum
+const PAGE_LIMIT: usize = 50;
+
+/// Representation of a Gitea or Forgejo pull request. It's shaped like a
+/// Github pull request, but lists can be null.
+#[derive(Deserialize, Clone)]
+pub struct GTPullRequest {
+    pub html_url: String,
+    pub title: String,
+    pub body: Option<String>,
+    pub state: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub merged_at: Option<String>,
+    pub user: GTUser,
+    pub head: ChangeHead,
+    pub base: ChangeBase,
+    pub labels: Option<Vec<Label>>,
+    pub requested_reviewers: Option<Vec<GTUser>>,
+}
+
+impl GTPullRequest {
+    pub fn into_change(self) -> ChangeRequest {
+        ChangeRequest {
+            base: self.base,
+            html_url: self.html_url,
+            title: self.title,
+            body: self.body,
+            created_at: self.created_at,
+            user: Account { login: self.user.login },
+            state: self.state,
+            merged_at: self.merged_at,
+            updated_at: self.updated_at,
+            head: self.head,
+            labels: self.labels.unwrap_or_default(),
+            requested_reviewers: self.requested_reviewers.unwrap_or_default().into_iter().map(|user| Account { login: user.login }).collect(),
+            provider: Provider::Gitea,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct GTUser {
+    pub login: String,
+
+    /// Empty when the user hides their email address
+    #[serde(default)]
+    pub email: String,
+}
+
+/// Converts filters written as Github's pull request query parameters into
+/// Gitea's. `state` is the same, while sorting uses Gitea's own names.
+pub fn gitea_filters(filters: &str) -> Vec<(String, String)> {
+    filters.trim_start_matches('?')
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match (key, value) {
+                ("sort", "updated") => Some(("sort".to_string(), "recentupdate".to_string())),
+                ("sort", "created") => Some(("sort".to_string(), "newest".to_string())),
+                ("direction", _) => None,
+                (key, value) => Some((key.to_string(), value.to_string())),
+            }
+        })
+        .collect()
+}
+
+/// Gitea and Forgejo client which sends requests to the v1 API with the async
+/// reqwest client, authenticating with an access token
+pub struct DefaultAsyncGiteaClient {
+    client: Client,
+    creds: ProviderCredentials,
+
+    /// Limits the requests sent to Gitea at once
+    limit: Semaphore,
+}
+
+impl DefaultAsyncGiteaClient {
+    /// `creds` are used for the host limit of the Gitea domain
+    pub fn new(gitea: &ProviderCredentials, creds: &Credentials) -> DefaultAsyncGiteaClient {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let limit = Semaphore::new(creds.host_limit(gitea.domain.as_str()).max(1));
+        DefaultAsyncGiteaClient { client, creds: gitea.clone(), limit }
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.client.get(format!("{}/api/v1{}", self.creds.base_url(), path))
+            .header("Authorization", format!("token {}", self.creds.token))
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<HttpResponse, Error> {
+        let resp = http::send(&self.client, &self.limit, request).await?;
+        if resp.is_success() {
+            Ok(resp)
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    /// Fetches the repo's pull requests, a page at a time. Paging stops once `keep` rejects a pull request or
+    /// there are no more pages.
+    async fn pull_requests<F>(&self, repo: &str, params: Vec<(String, String)>, keep: F) -> Result<Vec<ChangeRequest>, Error>
+        where F: Fn(&GTPullRequest) -> bool + Send + Sync
+    {
+        let path = format!("/repos/{}/pulls", repo);
+        let mut changes = Vec::new();
+
+        for page in 1.. {
+            let request = self.get(&path)
+                .query(&params)
+                .query(&[("limit", PAGE_LIMIT), ("page", page)]);
+            let prs: Vec<GTPullRequest> = self.send(request).await?.parse()?;
+
+            let count = prs.len();
+            let kept: Vec<GTPullRequest> = prs.into_iter().take_while(&keep).collect();
+            let stopped = kept.len() < count;
+
+            changes.extend(kept.into_iter().map(GTPullRequest::into_change));
+            if stopped || count < PAGE_LIMIT {
+                break;
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[async_trait]
+impl AsyncReviewClient for DefaultAsyncGiteaClient {
+    async fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        let changes = self.pull_requests(repo, gitea_filters(filters), |_| true).await?;
+        Ok(changes.into_iter().filter(|change| change.user.login == self.creds.user).collect())
+    }
+
+    async fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        let params = gitea_filters("?state=all&sort=updated");
+        let updated = self.pull_requests(repo, params, |pr| since.map(|since| pr.updated_at.as_str() > since).unwrap_or(true)).await?;
+        Ok(UpdatedChanges::new(updated, |change| change.user.login == self.creds.user))
+    }
+
+    async fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        let user: GTUser = self.send(self.get(&format!("/users/{}", login))).await?.parse()?;
+        Ok(Some(user.email).filter(|email| !email.is_empty()))
+    }
+}
+
+/// Blocking Gitea client, which waits for `DefaultAsyncGiteaClient`'s
+/// requests on a shared runtime. It can't be used from inside an async runtime.
+pub struct DefaultGiteaClient {
+    inner: DefaultAsyncGiteaClient,
+}
+
+impl DefaultGiteaClient {
+    pub fn new(gitea: &ProviderCredentials, creds: &Credentials) -> DefaultGiteaClient {
+        DefaultGiteaClient { inner: DefaultAsyncGiteaClient::new(gitea, creds) }
+    }
+}
+
+impl ReviewClient for DefaultGiteaClient {
+    fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        pool::block_on(self.inner.get_changes_for_repo(repo, filters))
+    }
+
+    fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        pool::block_on(self.inner.get_changes_updated_since(repo, since))
+    }
+
+    fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.get_user_email(login))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use crate::credentials::{Credentials, ProviderCredentials};
+    use crate::gitea::DefaultGiteaClient;
+    use crate::jira::parse_jira_ticket_number;
+    use crate::review::{Provider, ReviewClient};
+
+    const PULLS: &str = include_str!("../tests/fixtures/gitea_pulls.json");
+
+    /// Serves `body` for a single request on a local port, sending back the
+    /// request's URL and Authorization header
+    fn stub_server(body: &'static str) -> (String, mpsc::Receiver<(String, Option<String>)>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let request = server.recv().unwrap();
+            let auth = request.headers().iter()
+                .find(|header| header.field.equiv("Authorization"))
+                .map(|header| header.value.to_string());
+            sender.send((request.url().to_string(), auth)).unwrap();
+            request.respond(tiny_http::Response::from_string(body)).unwrap();
+        });
+
+        (addr, receiver)
+    }
+
+    #[test]
+    fn fetches_pull_requests_from_stub_server() {
+        let (addr, requests) = stub_server(PULLS);
+        let gitea = ProviderCredentials { domain: addr, user: "me".to_string(), token: "secret".to_string() };
+        let client = DefaultGiteaClient::new(&gitea, &Credentials::default());
+
+        let changes = client.get_changes_for_repo("org/tools", "?state=open&sort=updated").unwrap();
+
+        let (url, auth) = requests.recv().unwrap();
+        assert_eq!(url, "/api/v1/repos/org/tools/pulls?state=open&sort=recentupdate&limit=50&page=1");
+        assert_eq!(auth.as_deref(), Some("token secret"));
+
+        // Pull requests by other users are skipped
+        assert_eq!(changes.len(), 1);
+        let change = &changes[0];
+        assert_eq!(change.html_url, "https://forgejo.example.com/org/tools/pulls/5");
+        assert_eq!(change.base.repo.full_name, "org/tools");
+        assert!(change.requested_reviewers.is_empty());
+        assert_eq!(change.provider, Provider::Gitea);
+        assert_eq!(parse_jira_ticket_number(change.body.as_deref().unwrap(), "jira.domain"), Some("ABC-125".to_string()));
+    }
+}
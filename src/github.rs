@@ -1,152 +1,315 @@
 use std::time::Duration;
 
-use reqwest::blocking::Client;
+use async_trait::async_trait;
+use reqwest::Client;
 use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tokio::sync::Semaphore;
 
 use crate::credentials::Credentials;
 use crate::error::Error;
-use crate::jira::{JiraCommentElement, JiraCommentRequest};
-use crate::TakeUntil;
+use crate::github_issues::GHIssueComment;
+use crate::http;
+use crate::pool;
+use crate::review::{AsyncReviewClient, ChangeComment, ChangeRequest, CheckRun, CommitStatus, ReviewClient, UpdatedChanges};
 
-/// Representation of a Github Pull Request, only including
-/// the fields needed to create a comment on a matching Jira
-/// ticket.
-#[derive(Serialize, Deserialize, Clone)]
-pub struct GHPullRequest {
-    pub base: GHPullRequestBase,
-    pub html_url: String,
-    pub title: String,
-    pub body: Option<String>,
-    pub created_at: String,
-    pub user: GHPullRequestOwner,
-}
+/// Number of comments requested per page, the most Github allows
+const PER_PAGE: usize = 100;
 
-impl GHPullRequest {
-    pub fn build_jira_comment(&self) -> Result<JiraCommentRequest, Error> {
-        let pr_body = self.body.clone().ok_or(Error::from(format!("Pull Request {} has an invalid description", self.html_url)))?;
-
-        let jira_comment = JiraCommentRequest {
-            body: JiraCommentElement::doc(vec![
-                JiraCommentElement::paragraph(vec![
-                    JiraCommentElement::text(format!("Pull Request in {}: ", self.base.repo.full_name)),
-                    JiraCommentElement::link(self.title.clone(), self.html_url.clone())
-                ]),
-                JiraCommentElement::paragraph(vec![
-                    JiraCommentElement::text(pr_body.as_str().take_until('\n').trim().to_string())
-                ]),
-                JiraCommentElement::paragraph(vec![
-                    JiraCommentElement::text(format!("Created at: {}", self.created_at))
-                ])
-            ])
-        };
+/// Context commit statuses are set with, so each sync replaces the last status
+const STATUS_CONTEXT: &str = "autocomment/ticket";
 
-        Ok(jira_comment)
-    }
-}
+/// Name of the check run, which is also how an existing run is found to update
+const CHECK_NAME: &str = "Jira ticket";
 
+/// A Github user's public profile
 #[derive(Serialize, Deserialize, Clone)]
-pub struct GHPullRequestBase {
-    pub repo: GHRepo
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct GHRepo {
-    pub full_name: String,
+pub struct GHUser {
+    pub login: String,
+    pub email: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct GHPullRequestOwner {
-    pub login: String,
+#[derive(Deserialize)]
+struct GHCheckRuns {
+    check_runs: Vec<GHCheckRun>,
 }
 
-pub trait GithubClient {
-    /// Get a list of all pull requests for a repo, using the filters provided.
-    /// Only pull requests created by the user found in the Credentials will be
-    /// returned.
-    fn get_pull_requests_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<GHPullRequest>, Error>;
+#[derive(Deserialize)]
+struct GHCheckRun {
+    id: u64,
 }
 
-pub struct DefaultGithubClient<'a> {
+/// Github client which sends requests with the async reqwest client
+pub struct DefaultAsyncGithubClient {
     client: Client,
-    creds: &'a Credentials
+    creds: Credentials,
+
+    /// Limits the requests sent to Github at once
+    limit: Semaphore,
 }
 
-impl<'a> DefaultGithubClient<'a> {
-    pub fn new(creds: &'a Credentials) -> DefaultGithubClient<'a> {
+impl DefaultAsyncGithubClient {
+    pub fn new(creds: &Credentials) -> DefaultAsyncGithubClient {
         let client: Client = Client::builder()
             .timeout(Duration::from_secs(10))
             .danger_accept_invalid_hostnames(true)
             .build()
             .unwrap();
 
-        DefaultGithubClient { client, creds }
+        let limit = Semaphore::new(creds.host_limit(creds.github_domain.as_str()).max(1));
+        DefaultAsyncGithubClient { client, creds: creds.clone(), limit }
     }
 }
 
-impl<'a> GithubClient for DefaultGithubClient<'a> {
-    fn get_pull_requests_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<GHPullRequest>, Error> {
+#[async_trait]
+impl AsyncReviewClient for DefaultAsyncGithubClient {
+    async fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
         let gh_url = format!("https://{}/repos/{}/pulls{}", self.creds.github_domain, repo, filters);
 
-        let resp = self.client.get(gh_url)
-            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()))
-            .send()?;
+        let request = self.client.get(&gh_url)
+            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()));
+        let resp = http::send(&self.client, &self.limit, request).await?;
 
-        if resp.status().is_success() {
-            let prs: Vec<GHPullRequest> = serde_json::from_str(resp.text()?.as_str())?;
+        if resp.is_success() {
+            let prs: Vec<ChangeRequest> = resp.parse()?;
             Ok(prs.into_iter().filter(|pr| pr.user.login == self.creds.github_user).collect())
         } else {
-            Err(Error::from(resp.text()?))
+            Err(resp.error())
+        }
+    }
+
+    async fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        let mut updated = Vec::new();
+
+        for page in 1.. {
+            let filters = format!("?state=all&sort=updated&direction=desc&per_page=100&page={}", page);
+            let gh_url = format!("https://{}/repos/{}/pulls{}", self.creds.github_domain, repo, filters);
+
+            let request = self.client.get(&gh_url)
+                .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()));
+            let resp = http::send(&self.client, &self.limit, request).await?;
+
+            if !resp.is_success() {
+                return Err(resp.error());
+            }
+
+            let prs: Vec<ChangeRequest> = resp.parse()?;
+            if prs.is_empty() {
+                break;
+            }
+
+            // Pull requests are sorted by when they were updated, so paging
+            // can stop at the first one which is older than the watermark
+            let count = prs.len();
+            let newer: Vec<ChangeRequest> = prs.into_iter()
+                .take_while(|pr| since.map(|since| pr.updated_at.as_str() > since).unwrap_or(true))
+                .collect();
+            let reached_since = newer.len() < count;
+
+            updated.extend(newer);
+            if reached_since {
+                break;
+            }
+        }
+
+        Ok(UpdatedChanges::new(updated, |pr| pr.user.login == self.creds.github_user))
+    }
+
+    async fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        let gh_url = format!("https://{}/users/{}", self.creds.github_domain, login);
+
+        let request = self.client.get(&gh_url)
+            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()));
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            let user: GHUser = resp.parse()?;
+            Ok(user.email)
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    async fn get_change_comments(&self, change: &ChangeRequest) -> Result<Vec<ChangeComment>, Error> {
+        let gh_url = format!("https://{}/repos/{}/issues/{}/comments", self.creds.github_domain, change.base.repo.full_name, pull_number(change)?);
+        let mut comments = Vec::new();
+
+        for page in 1.. {
+            let request = self.client.get(&gh_url)
+                .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()))
+                .query(&[("per_page", PER_PAGE), ("page", page)]);
+            let resp = http::send(&self.client, &self.limit, request).await?;
+
+            if !resp.is_success() {
+                return Err(resp.error());
+            }
+
+            let page: Vec<GHIssueComment> = resp.parse()?;
+            let count = page.len();
+            comments.extend(page.into_iter().map(|comment| ChangeComment { id: comment.id.to_string(), body: comment.body }));
+            if count < PER_PAGE {
+                break;
+            }
+        }
+
+        Ok(comments)
+    }
+
+    async fn post_change_comment(&self, change: &ChangeRequest, body: &str) -> Result<String, Error> {
+        let gh_url = format!("https://{}/repos/{}/issues/{}/comments", self.creds.github_domain, change.base.repo.full_name, pull_number(change)?);
+
+        let request = self.client.post(&gh_url)
+            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&json!({ "body": body }))?);
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            let comment: GHIssueComment = resp.parse()?;
+            Ok(comment.id.to_string())
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    async fn update_change_comment(&self, change: &ChangeRequest, comment_id: &str, body: &str) -> Result<(), Error> {
+        let gh_url = format!("https://{}/repos/{}/issues/comments/{}", self.creds.github_domain, change.base.repo.full_name, comment_id);
+
+        let request = self.client.patch(&gh_url)
+            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&json!({ "body": body }))?);
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            Ok(())
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    async fn set_commit_status(&self, change: &ChangeRequest, status: &CommitStatus) -> Result<(), Error> {
+        let sha = head_sha(change)?;
+        let gh_url = format!("https://{}/repos/{}/statuses/{}", self.creds.github_domain, change.base.repo.full_name, sha);
+
+        // Github rejects descriptions longer than 140 characters
+        let description: String = status.description.chars().take(140).collect();
+        let body = json!({
+            "state": if status.success { "success" } else { "failure" },
+            "context": STATUS_CONTEXT,
+            "description": description,
+            "target_url": status.target_url,
+        });
+
+        let request = self.client.post(&gh_url)
+            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&body)?);
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            Ok(())
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    /// Check runs can only be created with a Github App's installation token
+    async fn set_check_run(&self, change: &ChangeRequest, check: &CheckRun) -> Result<(), Error> {
+        let sha = head_sha(change)?;
+        let repo_url = format!("https://{}/repos/{}", self.creds.github_domain, change.base.repo.full_name);
+
+        let request = self.client.get(format!("{}/commits/{}/check-runs", repo_url, sha))
+            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()))
+            .query(&[("check_name", CHECK_NAME)]);
+        let resp = http::send(&self.client, &self.limit, request).await?;
+        if !resp.is_success() {
+            return Err(resp.error());
+        }
+        let existing: GHCheckRuns = resp.parse()?;
+
+        let body = json!({
+            "name": CHECK_NAME,
+            "head_sha": sha,
+            "status": "completed",
+            "conclusion": if check.success { "success" } else { "failure" },
+            "details_url": check.details_url,
+            "output": { "title": check.title, "summary": check.summary },
+        });
+
+        let request = match existing.check_runs.first() {
+            Some(run) => self.client.patch(format!("{}/check-runs/{}", repo_url, run.id)),
+            None => self.client.post(format!("{}/check-runs", repo_url)),
+        };
+        let request = request
+            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&body)?);
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            Ok(())
+        } else {
+            Err(resp.error())
         }
     }
 }
 
-pub struct MockGithubClient {
-    pub data: Box<Vec<GHPullRequest>>
+/// The pull request's head commit, which statuses and check runs are set on
+fn head_sha(change: &ChangeRequest) -> Result<&str, Error> {
+    change.head.sha.as_deref()
+        .ok_or(Error::from(format!("Pull request {} doesn't have a head commit", change.html_url)))
 }
 
-impl GithubClient for MockGithubClient {
-    fn get_pull_requests_for_repo(&self, _repo: &str, _filters: &str) -> Result<Vec<GHPullRequest>, Error> {
-        Ok(*self.data.clone())
+/// The number of a pull request, which is the last part of its URL
+fn pull_number(change: &ChangeRequest) -> Result<&str, Error> {
+    change.html_url.rsplit('/').next()
+        .filter(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+        .ok_or(Error::from(format!("Unable to find the pull request number in {}", change.html_url)))
+}
+
+/// Blocking Github client, which waits for `DefaultAsyncGithubClient`'s
+/// requests on a shared runtime. It can't be used from inside an async runtime.
+pub struct DefaultGithubClient {
+    inner: DefaultAsyncGithubClient,
+}
+
+impl DefaultGithubClient {
+    pub fn new(creds: &Credentials) -> DefaultGithubClient {
+        DefaultGithubClient { inner: DefaultAsyncGithubClient::new(creds) }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use crate::GHPullRequest;
-    use crate::github::{GHPullRequestBase, GHPullRequestOwner};
-    use crate::github::GHRepo;
-
-    #[test]
-    fn build_jira_comment_success() {
-        let pr = GHPullRequest{
-            base: GHPullRequestBase {
-                repo: GHRepo { full_name: "test".to_string() }
-            },
-            html_url: "https://url/org/repo".to_string(),
-            title: "test title".to_string(),
-            body: Some("test body\nwith two lines".to_string()),
-            created_at: "datetime".to_string(),
-            user: GHPullRequestOwner { login: "me".to_string() }
-        };
+impl ReviewClient for DefaultGithubClient {
+    fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        pool::block_on(self.inner.get_changes_for_repo(repo, filters))
+    }
 
-        let format = "{\"body\":{\"version\":1,\"type\":\"doc\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"Pull Request in test: \"},{\"type\":\"text\",\"text\":\"test title\",\"marks\":[{\"type\":\"link\",\"attrs\":{\"href\":\"https://url/org/repo\"}}]}]},{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"test body\"}]},{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"Created at: datetime\"}]}]}}".to_string();
+    fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        pool::block_on(self.inner.get_changes_updated_since(repo, since))
+    }
 
-        assert_eq!(format, serde_json::to_string(&pr.build_jira_comment().unwrap()).unwrap())
+    fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.get_user_email(login))
     }
 
-    #[test]
-    fn build_jira_comment_failure() {
-        let pr = GHPullRequest{
-            base: GHPullRequestBase {
-                repo: GHRepo { full_name: "test".to_string() }
-            },
-            html_url: "https://url/org/repo".to_string(),
-            title: "test title".to_string(),
-            body: None,
-            created_at: "datetime".to_string(),
-            user: GHPullRequestOwner { login: "me".to_string() }
-        };
+    fn get_change_comments(&self, change: &ChangeRequest) -> Result<Vec<ChangeComment>, Error> {
+        pool::block_on(self.inner.get_change_comments(change))
+    }
+
+    fn post_change_comment(&self, change: &ChangeRequest, body: &str) -> Result<String, Error> {
+        pool::block_on(self.inner.post_change_comment(change, body))
+    }
+
+    fn update_change_comment(&self, change: &ChangeRequest, comment_id: &str, body: &str) -> Result<(), Error> {
+        pool::block_on(self.inner.update_change_comment(change, comment_id, body))
+    }
+
+    fn set_commit_status(&self, change: &ChangeRequest, status: &CommitStatus) -> Result<(), Error> {
+        pool::block_on(self.inner.set_commit_status(change, status))
+    }
 
-        assert!(pr.build_jira_comment().is_err())
+    fn set_check_run(&self, change: &ChangeRequest, check: &CheckRun) -> Result<(), Error> {
+        pool::block_on(self.inner.set_check_run(change, check))
     }
 }
@@ -0,0 +1,285 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::{Serialize, Deserialize};
+use tokio::sync::Semaphore;
+
+use crate::credentials::Credentials;
+use crate::error::Error;
+use crate::http::{self, HttpResponse};
+use crate::pool;
+use crate::tracker::{AsyncIssueTracker, CommentFormat, IssueLink, IssueTracker, TrackerComment};
+
+/// Number of comments requested per page, the most Github allows
+const PER_PAGE: usize = 100;
+
+/// A comment on a Github issue, written in Markdown
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GHIssueComment {
+    #[serde(skip_serializing)]
+    pub id: u64,
+    pub body: String,
+}
+
+#[derive(Serialize)]
+struct GHCommentRequest<'a> {
+    body: &'a str,
+}
+
+#[derive(Deserialize)]
+struct GHUserSearch {
+    items: Vec<GHLogin>,
+}
+
+#[derive(Deserialize)]
+struct GHLogin {
+    login: String,
+}
+
+/// The hidden HTML comment added to pull request comments on Github issues,
+/// so they're recognised even when someone else links the pull request
+pub fn comment_marker(pr_url: &str) -> String {
+    format!("<!-- autocomment: {} -->", pr_url)
+}
+
+/// The host Github's web pages are served from, for the domain of its API.
+/// Github Enterprise serves its API from /api/v3 on the same host.
+pub fn web_domain(api_domain: &str) -> &str {
+    let domain = api_domain.trim_end_matches('/').trim_end_matches("/api/v3");
+    domain.strip_prefix("api.").unwrap_or(domain)
+}
+
+/// Finds the first Github issue referenced in the text, either as
+/// `owner/repo#42` or as a link to the issue on `domain`. Issues are
+/// identified as `owner/repo#42`.
+pub fn parse_github_issue(text: &str, domain: &str) -> Option<String> {
+    let re = regex::Regex::new(format!(r"(?:https://{}/|(?:^|[^\w/.-]))([\w.-]+/[\w.-]+)(?:/issues/|#)(\d+)\b", domain.replace('.', r"\.")).as_str()).unwrap();
+    re.captures(text).map(|group| format!("{}#{}", &group[1], &group[2]))
+}
+
+/// Splits an issue identified as `owner/repo#42` into its repository and number
+fn split_issue(ticket_id: &str) -> Result<(&str, &str), Error> {
+    ticket_id.split_once('#')
+        .ok_or(Error::from(format!("{} isn't a Github issue, expected owner/repo#number", ticket_id)))
+}
+
+/// Tracker which posts comments on Github issues, using the Github credentials
+pub struct DefaultAsyncGithubIssuesClient {
+    client: Client,
+    creds: Credentials,
+
+    /// Limits the requests sent to Github at once
+    limit: Semaphore,
+}
+
+impl DefaultAsyncGithubIssuesClient {
+    pub fn new(creds: &Credentials) -> DefaultAsyncGithubIssuesClient {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let limit = Semaphore::new(creds.host_limit(creds.github_domain.as_str()).max(1));
+        DefaultAsyncGithubIssuesClient { client, creds: creds.clone(), limit }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        self.client.request(method, format!("https://{}{}", self.creds.github_domain, path))
+            .basic_auth(self.creds.github_user.clone(), Some(self.creds.github_pass.clone()))
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<HttpResponse, Error> {
+        let resp = http::send(&self.client, &self.limit, request).await?;
+        if resp.is_success() {
+            Ok(resp)
+        } else {
+            Err(resp.error())
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncIssueTracker for DefaultAsyncGithubIssuesClient {
+    fn name(&self) -> &str {
+        "Github"
+    }
+
+    fn get_domain(&self) -> &str {
+        web_domain(self.creds.github_domain.as_str())
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        CommentFormat::Markdown
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        match ticket_id.split_once('#') {
+            Some((repo, number)) => format!("https://{}/{}/issues/{}", self.get_domain(), repo, number),
+            None => format!("https://{}/{}", self.get_domain(), ticket_id),
+        }
+    }
+
+    fn parse_ticket(&self, text: &str) -> Option<String> {
+        parse_github_issue(text, self.get_domain())
+    }
+
+    fn comment_marker(&self, pr_url: &str) -> Option<String> {
+        Some(comment_marker(pr_url))
+    }
+
+    /// Issues belong to the repository they're in
+    fn ticket_project(&self, ticket_id: &str) -> Option<String> {
+        split_issue(ticket_id).ok().map(|(repo, _)| repo.to_string())
+    }
+
+    async fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        let (repo, number) = split_issue(ticket_id)?;
+        let path = format!("/repos/{}/issues/{}/comments", repo, number);
+        let mut comments = Vec::new();
+
+        for page in 1.. {
+            let request = self.request(reqwest::Method::GET, &path)
+                .query(&[("per_page", PER_PAGE), ("page", page)]);
+            let page: Vec<GHIssueComment> = self.send(request).await?.parse()?;
+
+            let count = page.len();
+            comments.extend(page.into_iter().map(|comment| TrackerComment { id: comment.id.to_string(), body: comment.body }));
+            if count < PER_PAGE {
+                break;
+            }
+        }
+
+        Ok(comments)
+    }
+
+    async fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        let (repo, number) = split_issue(ticket_id)?;
+        let request = self.request(reqwest::Method::POST, &format!("/repos/{}/issues/{}/comments", repo, number))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&GHCommentRequest { body: text })?);
+        let comment: GHIssueComment = self.send(request).await?.parse()?;
+
+        Ok(comment.id.to_string())
+    }
+
+    async fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        let (repo, _) = split_issue(ticket_id)?;
+        let request = self.request(reqwest::Method::PATCH, &format!("/repos/{}/issues/comments/{}", repo, comment_id))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&GHCommentRequest { body: text })?);
+        self.send(request).await?;
+
+        Ok(())
+    }
+
+    async fn get_links(&self, _ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        Err(Error::ConfigError("Github issues don't support remote links, sync with --mode comment".to_string()))
+    }
+
+    async fn post_link(&self, _ticket_id: &str, _link: &IssueLink) -> Result<(), Error> {
+        Err(Error::ConfigError("Github issues don't support remote links, sync with --mode comment".to_string()))
+    }
+
+    /// Finds the login of the user with this public email address
+    async fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        let request = self.request(reqwest::Method::GET, "/search/users")
+            .query(&[("q", format!("{} in:email", email))]);
+        let search: GHUserSearch = self.send(request).await?.parse()?;
+
+        Ok(search.items.into_iter().next().map(|user| user.login))
+    }
+}
+
+/// Blocking Github issues tracker, which waits for
+/// `DefaultAsyncGithubIssuesClient`'s requests on a shared runtime. It can't
+/// be used from inside an async runtime.
+pub struct DefaultGithubIssuesClient {
+    inner: DefaultAsyncGithubIssuesClient,
+}
+
+impl DefaultGithubIssuesClient {
+    pub fn new(creds: &Credentials) -> DefaultGithubIssuesClient {
+        DefaultGithubIssuesClient { inner: DefaultAsyncGithubIssuesClient::new(creds) }
+    }
+}
+
+impl IssueTracker for DefaultGithubIssuesClient {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn get_domain(&self) -> &str {
+        self.inner.get_domain()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        self.inner.comment_format()
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        self.inner.ticket_url(ticket_id)
+    }
+
+    fn parse_ticket(&self, text: &str) -> Option<String> {
+        self.inner.parse_ticket(text)
+    }
+
+    fn comment_marker(&self, pr_url: &str) -> Option<String> {
+        self.inner.comment_marker(pr_url)
+    }
+
+    fn ticket_project(&self, ticket_id: &str) -> Option<String> {
+        self.inner.ticket_project(ticket_id)
+    }
+
+    fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        pool::block_on(self.inner.get_comments(ticket_id))
+    }
+
+    fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        pool::block_on(self.inner.post_comment(ticket_id, text))
+    }
+
+    fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        pool::block_on(self.inner.update_comment(ticket_id, comment_id, text))
+    }
+
+    fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        pool::block_on(self.inner.get_links(ticket_id))
+    }
+
+    fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        pool::block_on(self.inner.post_link(ticket_id, link))
+    }
+
+    fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.find_account_id(email))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::credentials::Credentials;
+    use crate::github_issues::{parse_github_issue, web_domain, DefaultGithubIssuesClient};
+    use crate::tracker::IssueTracker;
+
+    #[test]
+    fn parses_github_issue_references() {
+        assert_eq!(parse_github_issue("Part of org/tracker#42", "github.com"), Some("org/tracker#42".to_string()));
+        assert_eq!(parse_github_issue("See https://github.com/org/tracker/issues/7 for details", "github.com"), Some("org/tracker#7".to_string()));
+        assert_eq!(parse_github_issue("Fixes #12 in this repo", "github.com"), None);
+    }
+
+    #[test]
+    fn builds_issue_urls() {
+        assert_eq!(web_domain("api.github.com"), "github.com");
+        assert_eq!(web_domain("ghe.example.com/api/v3"), "ghe.example.com");
+
+        let creds = Credentials { github_domain: "api.github.com".to_string(), ..Default::default() };
+        let client = DefaultGithubIssuesClient::new(&creds);
+        assert_eq!(client.ticket_url("org/tracker#42"), "https://github.com/org/tracker/issues/42");
+        assert_eq!(client.comment_marker("https://github.com/org/repo/pull/9").as_deref(), Some("<!-- autocomment: https://github.com/org/repo/pull/9 -->"));
+        assert_eq!(client.ticket_project("org/tracker#42").as_deref(), Some("org/tracker"));
+    }
+}
@@ -0,0 +1,254 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+use crate::credentials::{Credentials, ProviderCredentials};
+use crate::error::Error;
+use crate::http::{self, HttpResponse};
+use crate::pool;
+use crate::review::{Account, AsyncReviewClient, ChangeBase, ChangeHead, ChangeRepo, ChangeRequest, Label, Provider, ReviewClient, UpdatedChanges};
+
+/// Number of merge requests requested per page, the most GitLab allows
+const PER_PAGE: usize = 100;
+
+/// Representation of a GitLab merge request from the v4 API, only including
+/// the fields needed to build a `ChangeRequest`
+#[derive(Deserialize, Clone)]
+pub struct GLMergeRequest {
+    pub web_url: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub state: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub merged_at: Option<String>,
+    pub author: GLUser,
+    pub source_branch: String,
+
+    #[serde(default)]
+    pub labels: Vec<String>,
+
+    #[serde(default)]
+    pub reviewers: Vec<GLUser>,
+}
+
+impl GLMergeRequest {
+    /// Converts the merge request into a change in the project. GitLab's
+    /// `opened` and `locked` states are open, and merged merge requests are
+    /// closed with a merge time.
+    pub fn into_change(self, project: &str) -> ChangeRequest {
+        let state = match self.state.as_str() {
+            "merged" | "closed" => "closed",
+            _ => "open",
+        };
+        // Merge requests merged before GitLab recorded merge times don't have one
+        let merged_at = match self.state.as_str() {
+            "merged" => self.merged_at.or(Some(self.updated_at.clone())),
+            _ => self.merged_at,
+        };
+
+        ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: project.to_string() } },
+            html_url: self.web_url,
+            title: self.title,
+            body: self.description,
+            created_at: self.created_at,
+            user: Account { login: self.author.username },
+            state: state.to_string(),
+            merged_at,
+            updated_at: self.updated_at,
+            head: ChangeHead { branch: self.source_branch, sha: None },
+            labels: self.labels.into_iter().map(|name| Label { name }).collect(),
+            requested_reviewers: self.reviewers.into_iter().map(|user| Account { login: user.username }).collect(),
+            provider: Provider::Gitlab,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct GLUser {
+    pub id: u64,
+    pub username: String,
+
+    /// Only returned when fetching a single user
+    #[serde(default)]
+    pub public_email: Option<String>,
+}
+
+/// Converts filters written as Github's pull request query parameters into
+/// GitLab's merge request parameters. Parameters GitLab shares with Github
+/// are passed through unchanged.
+pub fn gitlab_filters(filters: &str) -> Vec<(String, String)> {
+    filters.trim_start_matches('?')
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match (key, value) {
+                ("state", "open") => ("state".to_string(), "opened".to_string()),
+                ("sort", "updated") => ("order_by".to_string(), "updated_at".to_string()),
+                ("sort", "created") => ("order_by".to_string(), "created_at".to_string()),
+                ("direction", direction) => ("sort".to_string(), direction.to_string()),
+                (key, value) => (key.to_string(), value.to_string()),
+            }
+        })
+        .collect()
+}
+
+/// GitLab client which sends requests to the v4 API with the async reqwest
+/// client, authenticating with a personal or project access token
+pub struct DefaultAsyncGitlabClient {
+    client: Client,
+    creds: ProviderCredentials,
+
+    /// Limits the requests sent to GitLab at once
+    limit: Semaphore,
+}
+
+impl DefaultAsyncGitlabClient {
+    /// `creds` are used for the host limit of the GitLab domain
+    pub fn new(gitlab: &ProviderCredentials, creds: &Credentials) -> DefaultAsyncGitlabClient {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let limit = Semaphore::new(creds.host_limit(gitlab.domain.as_str()).max(1));
+        DefaultAsyncGitlabClient { client, creds: gitlab.clone(), limit }
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.client.get(format!("{}/api/v4{}", self.creds.base_url(), path))
+            .header("PRIVATE-TOKEN", self.creds.token.as_str())
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<HttpResponse, Error> {
+        let resp = http::send(&self.client, &self.limit, request).await?;
+        if resp.is_success() {
+            Ok(resp)
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    /// Fetches the project's merge requests, a page at a time. Paging stops once `keep` rejects a merge request or
+    /// there are no more pages.
+    async fn merge_requests<F>(&self, project: &str, params: Vec<(String, String)>, keep: F) -> Result<Vec<ChangeRequest>, Error>
+        where F: Fn(&GLMergeRequest) -> bool + Send + Sync
+    {
+        let path = format!("/projects/{}/merge_requests", project.replace('/', "%2F"));
+        let mut changes = Vec::new();
+
+        for page in 1.. {
+            let request = self.get(&path)
+                .query(&params)
+                .query(&[("per_page", PER_PAGE), ("page", page)]);
+            let mrs: Vec<GLMergeRequest> = self.send(request).await?.parse()?;
+
+            let count = mrs.len();
+            let kept: Vec<GLMergeRequest> = mrs.into_iter().take_while(&keep).collect();
+            let stopped = kept.len() < count;
+
+            changes.extend(kept.into_iter().map(|mr| mr.into_change(project)));
+            if stopped || count < PER_PAGE {
+                break;
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[async_trait]
+impl AsyncReviewClient for DefaultAsyncGitlabClient {
+    async fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        let mut params = gitlab_filters(filters);
+        params.push(("author_username".to_string(), self.creds.user.clone()));
+        self.merge_requests(repo, params, |_| true).await
+    }
+
+    async fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        let mut params = gitlab_filters("?state=all&order_by=updated_at&sort=desc");
+        if let Some(since) = since {
+            params.push(("updated_after".to_string(), since.to_string()));
+        }
+
+        // GitLab's updated_after includes merge requests updated at exactly
+        // `since`, which were synced last time
+        let updated = self.merge_requests(repo, params, |mr| since.map(|since| mr.updated_at.as_str() > since).unwrap_or(true)).await?;
+        Ok(UpdatedChanges::new(updated, |change| change.user.login == self.creds.user))
+    }
+
+    async fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        let users: Vec<GLUser> = self.send(self.get("/users").query(&[("username", login)])).await?.parse()?;
+        let id = match users.first() {
+            Some(user) => user.id,
+            None => return Ok(None),
+        };
+
+        let user: GLUser = self.send(self.get(&format!("/users/{}", id))).await?.parse()?;
+        Ok(user.public_email.filter(|email| !email.is_empty()))
+    }
+}
+
+/// Blocking GitLab client, which waits for `DefaultAsyncGitlabClient`'s
+/// requests on a shared runtime. It can't be used from inside an async runtime.
+pub struct DefaultGitlabClient {
+    inner: DefaultAsyncGitlabClient,
+}
+
+impl DefaultGitlabClient {
+    pub fn new(gitlab: &ProviderCredentials, creds: &Credentials) -> DefaultGitlabClient {
+        DefaultGitlabClient { inner: DefaultAsyncGitlabClient::new(gitlab, creds) }
+    }
+}
+
+impl ReviewClient for DefaultGitlabClient {
+    fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        pool::block_on(self.inner.get_changes_for_repo(repo, filters))
+    }
+
+    fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        pool::block_on(self.inner.get_changes_updated_since(repo, since))
+    }
+
+    fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.get_user_email(login))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gitlab::{gitlab_filters, GLMergeRequest};
+    use crate::jira::parse_jira_ticket_number;
+
+    const MERGED: &str = include_str!("../tests/fixtures/merge_request_merged.json");
+
+    #[test]
+    fn converts_merge_request() {
+        let mr: GLMergeRequest = serde_json::from_str(MERGED).unwrap();
+        let change = mr.into_change("group/project");
+
+        assert_eq!(change.html_url, "https://gitlab.example.com/group/project/-/merge_requests/7");
+        assert_eq!(change.state, "closed");
+        assert_eq!(change.merged_at.as_deref(), Some("2023-01-05T16:20:00.000Z"));
+        assert_eq!(change.user.login, "me");
+        assert_eq!(change.requested_reviewers.iter().map(|user| user.login.as_str()).collect::<Vec<_>>(), vec!["reviewer"]);
+        assert_eq!(parse_jira_ticket_number(change.body.as_deref().unwrap(), "jira.domain"), Some("ABC-123".to_string()));
+
+        let link = change.build_jira_remote_link();
+        assert_eq!(link.object.summary.as_deref(), Some("Merge Request in group/project (merged)"));
+        assert!(link.object.status.unwrap().resolved);
+    }
+
+    #[test]
+    fn converts_github_filters() {
+        let params = gitlab_filters("?state=open&sort=updated&direction=desc&labels=bug");
+        let params: Vec<(&str, &str)> = params.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+
+        assert_eq!(params, vec![("state", "opened"), ("order_by", "updated_at"), ("sort", "desc"), ("labels", "bug")]);
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::Instant;
+
+use reqwest::header::HeaderMap;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use tokio::sync::Semaphore;
+
+use crate::error::Error;
+use crate::metrics::metrics;
+
+/// Headers whose values are never logged
+const REDACTED_HEADERS: [&str; 6] = ["authorization", "cookie", "set-cookie", "proxy-authorization", "x-redmine-api-key", "private-token"];
+
+/// Headers containing any of these in their name are never logged either, so
+/// a provider's own credential header is redacted without being listed
+const REDACTED_HEADER_PARTS: [&str; 3] = ["token", "key", "auth"];
+
+/// Header Github, and Jira Cloud when it's limiting, report the requests left
+/// in the current rate limit window with
+const RATE_LIMIT_REMAINING: &str = "x-ratelimit-remaining";
+
+/// Longest response body logged, in characters
+const MAX_LOGGED_BODY: usize = 2000;
+
+/// A response from Github or Jira, with the request it was for
+pub struct HttpResponse {
+    pub method: String,
+    pub url: String,
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        Error::parse_response(&self.url, &self.body)
+    }
+
+    /// The error for an unsuccessful response
+    pub fn error(&self) -> Error {
+        Error::from_response(&self.method, &self.url, self.status, &self.body)
+    }
+}
+
+/// Sends the request once a permit is available, logging the request and
+/// response with credentials redacted
+pub async fn send(client: &Client, limit: &Semaphore, request: RequestBuilder) -> Result<HttpResponse, Error> {
+    let request = request.build()?;
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+    let upstream = request.url().host_str().unwrap_or_default().to_string();
+
+    let _permit = limit.acquire().await.map_err(|err| Error::from(err.to_string()))?;
+
+    tracing::debug!(%method, %url, "sending request");
+    tracing::trace!(%method, %url, headers = %redact(request.headers()), "request headers");
+
+    let started = Instant::now();
+    let resp = client.execute(request).await
+        .inspect_err(|err| {
+            tracing::warn!(%method, %url, error = %err, "request failed");
+            metrics().record_response(&upstream, None, started.elapsed(), None);
+        })?;
+
+    let status = resp.status();
+    let headers = redact(resp.headers());
+    let rate_limit_remaining = resp.headers().get(RATE_LIMIT_REMAINING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let body = resp.text().await?;
+    let elapsed = started.elapsed();
+    let elapsed_ms = elapsed.as_millis() as u64;
+    metrics().record_response(&upstream, Some(status.as_u16()), elapsed, rate_limit_remaining);
+
+    if status.is_success() {
+        tracing::debug!(%method, %url, status = status.as_u16(), elapsed_ms, "received response");
+    } else {
+        tracing::warn!(%method, %url, status = status.as_u16(), elapsed_ms, "received unsuccessful response");
+    }
+    tracing::trace!(%method, %url, %headers, body = %truncate(&body), "response");
+
+    Ok(HttpResponse { method, url, status, body })
+}
+
+fn redact(headers: &HeaderMap) -> String {
+    headers.iter()
+        .map(|(name, value)| {
+            let value = if is_redacted(name.as_str()) {
+                "[redacted]"
+            } else {
+                value.to_str().unwrap_or("[binary]")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Header names are always lowercase in a `HeaderMap`
+fn is_redacted(name: &str) -> bool {
+    REDACTED_HEADERS.contains(&name) || REDACTED_HEADER_PARTS.iter().any(|part| name.contains(part))
+}
+
+fn truncate(body: &str) -> &str {
+    match body.char_indices().nth(MAX_LOGGED_BODY) {
+        Some((idx, _)) => &body[..idx],
+        None => body,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+
+    use crate::http::redact;
+
+    #[test]
+    fn redacts_credentials() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic dXNlcjpwYXNz"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert("X-Redmine-API-Key", HeaderValue::from_static("0123456789abcdef"));
+
+        assert_eq!(redact(&headers), "authorization: [redacted], content-type: application/json, x-redmine-api-key: [redacted]");
+    }
+
+    #[test]
+    fn redacts_provider_token_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("PRIVATE-TOKEN", HeaderValue::from_static("glpat-secret"));
+        headers.insert("X-Custom-Auth", HeaderValue::from_static("secret"));
+        headers.insert("X-Ratelimit-Remaining", HeaderValue::from_static("42"));
+
+        assert_eq!(redact(&headers), "private-token: [redacted], x-custom-auth: [redacted], x-ratelimit-remaining: 42");
+    }
+}
@@ -1,8 +1,17 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::adf::Document;
 use crate::credentials::Credentials;
 use crate::error::Error;
+use crate::http;
+use crate::pool;
+use crate::tracker::{AsyncIssueTracker, CommentFormat, IssueLink, IssueTracker, TicketSummary, TrackerComment};
 
-use reqwest::blocking::Client;
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
 use serde::{Serialize, Deserialize};
+use tokio::sync::Semaphore;
 
 
 /// Representation of a Jira comment response, with only
@@ -15,185 +24,541 @@ pub struct JiraCommentResponse {
 
 impl JiraCommentResponse {
     pub fn contains_text(&self, text: &str) -> bool {
-        self.comments.iter().any(|comment| comment.rendered_body.contains(&text))
+        self.comments.iter().any(|comment| comment.rendered_body.contains(text))
+    }
+
+    pub fn into_tracker_comments(self) -> Vec<TrackerComment> {
+        self.comments.into_iter()
+            .map(|comment| TrackerComment { id: comment.id, body: comment.rendered_body })
+            .collect()
     }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct JiraComment {
+    #[serde(default)]
+    pub id: String,
+
     #[serde(rename = "renderedBody")]
     pub rendered_body: String,
 }
 
+/// The parts of a created comment autocomment keeps track of
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraCreatedComment {
+    pub id: String,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct JiraCommentRequest {
-    pub body: JiraCommentElement
+    pub body: Document
 }
 
+/// Comment request for Jira Server, which takes wiki markup instead of ADF
 #[derive(Serialize, Deserialize, Clone)]
-pub struct JiraCommentElement {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub version: Option<u8>,
+pub struct JiraWikiCommentRequest {
+    pub body: String
+}
 
-    #[serde(rename = "type")]
-    pub comment_type: String,
+/// The kind of Jira deployment being synced to. Cloud uses the v3 API with
+/// ADF comments, Server uses the v2 API with wiki markup comments.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JiraFlavor {
+    #[default]
+    Cloud,
+    Server,
+}
 
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub content: Vec<JiraCommentElement>,
+impl JiraFlavor {
+    pub fn api_version(&self) -> u8 {
+        match self {
+            JiraFlavor::Cloud => 3,
+            JiraFlavor::Server => 2,
+        }
+    }
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub text: Option<String>,
+    pub fn comment_format(&self) -> CommentFormat {
+        match self {
+            JiraFlavor::Cloud => CommentFormat::Adf,
+            JiraFlavor::Server => CommentFormat::Wiki,
+        }
+    }
+}
 
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub marks: Vec<JiraCommentElement>,
+impl FromStr for JiraFlavor {
+    type Err = Error;
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub attrs: Option<JiraCommentAttrs>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cloud" => Ok(JiraFlavor::Cloud),
+            "server" => Ok(JiraFlavor::Server),
+            _ => Err(Error::from(format!("Unknown Jira flavor {}, expected cloud or server", s))),
+        }
+    }
 }
 
+/// Representation of a Jira remote issue link. Jira treats `globalId` as the
+/// identity of a link, so posting the same `globalId` twice updates the
+/// existing link instead of creating a duplicate.
 #[derive(Serialize, Deserialize, Clone)]
-pub struct JiraCommentAttrs {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    href: Option<String>
-}
-
-impl JiraCommentElement {
-    pub fn doc(content: Vec<JiraCommentElement>) -> Self {
-        JiraCommentElement {
-            version: Some(1),
-            comment_type: "doc".to_string(),
-            content: content,
-            text: None,
-            marks: Vec::new(),
-            attrs: None
-        }
+pub struct JiraRemoteLink {
+    #[serde(rename = "globalId")]
+    pub global_id: String,
+    pub object: JiraRemoteLinkObject,
+}
+
+impl JiraRemoteLink {
+    pub fn is_resolved(&self) -> bool {
+        self.object.status.as_ref().map(|status| status.resolved).unwrap_or(false)
     }
+}
 
-    pub fn text(text: String) -> Self {
-        JiraCommentElement {
-            version: None,
-            comment_type: "text".to_string(),
-            content: Vec::new(),
-            text: Some(text),
-            marks: Vec::new(),
-            attrs: None
+impl From<&IssueLink> for JiraRemoteLink {
+    fn from(link: &IssueLink) -> Self {
+        JiraRemoteLink {
+            global_id: link.url.clone(),
+            object: JiraRemoteLinkObject {
+                url: link.url.clone(),
+                title: link.title.clone(),
+                summary: link.summary.clone(),
+                icon: link.icon_url.clone().map(|url| JiraRemoteLinkIcon {
+                    url,
+                    title: link.icon_title.clone().unwrap_or_default(),
+                }),
+                status: Some(JiraRemoteLinkStatus { resolved: link.resolved }),
+            }
         }
     }
+}
 
-    pub fn paragraph(content: Vec<JiraCommentElement>) -> Self {
-        JiraCommentElement {
-            version: None,
-            comment_type: "paragraph".to_string(),
-            content: content,
-            text: None,
-            marks: Vec::new(),
-            attrs: None
+impl From<JiraRemoteLink> for IssueLink {
+    fn from(link: JiraRemoteLink) -> Self {
+        let resolved = link.is_resolved();
+        IssueLink {
+            url: link.global_id,
+            title: link.object.title,
+            summary: link.object.summary,
+            icon_url: link.object.icon.as_ref().map(|icon| icon.url.clone()),
+            icon_title: link.object.icon.map(|icon| icon.title),
+            resolved,
         }
     }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraRemoteLinkObject {
+    pub url: String,
+    pub title: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<JiraRemoteLinkIcon>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<JiraRemoteLinkStatus>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraRemoteLinkIcon {
+    #[serde(rename = "url16x16")]
+    pub url: String,
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraRemoteLinkStatus {
+    pub resolved: bool,
+}
+
+/// The fields of a Jira issue shown in ticket summaries
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraIssue {
+    pub key: String,
+    pub fields: JiraIssueFields,
+}
 
-    pub fn link(text: String, link: String) -> Self {
-        JiraCommentElement {
-            version: None,
-            comment_type: "text".to_string(),
-            content: Vec::new(),
-            text: Some(text),
-            marks: vec![JiraCommentElement {
-                version: None,
-                comment_type: "link".to_string(),
-                content: Vec::new(),
-                text: None,
-                marks: Vec::new(),
-                attrs: Some(JiraCommentAttrs { href: Some(link) })
-            }],
-            attrs: None
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraIssueFields {
+    pub summary: String,
+    pub status: Option<JiraNamed>,
+    pub assignee: Option<JiraIssueUser>,
+    pub priority: Option<JiraNamed>,
+
+    #[serde(rename = "fixVersions", default)]
+    pub fix_versions: Vec<JiraNamed>,
+}
+
+/// A status, priority or version, which are identified by name
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraNamed {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraIssueUser {
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+}
+
+impl JiraIssue {
+    pub fn into_summary(self) -> TicketSummary {
+        TicketSummary {
+            key: self.key,
+            summary: self.fields.summary,
+            status: self.fields.status.map(|status| status.name).unwrap_or_default(),
+            assignee: self.fields.assignee.map(|assignee| assignee.display_name),
+            priority: self.fields.priority.map(|priority| priority.name),
+            fix_versions: self.fields.fix_versions.into_iter().map(|version| version.name).collect(),
         }
     }
 }
 
-pub trait JiraClient {
-    fn get_domain(&self) -> &str;
-    fn post_jira_comment(&self, ticket_id: &str, text: &str) -> Result<(), Error>;
-    fn get_jira_comments(&self, ticket_id: &str) -> Result<JiraCommentResponse, Error>;
+/// A Jira user returned by user search. Cloud identifies users by account
+/// ID, while Server identifies them by username.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JiraUser {
+    #[serde(rename = "accountId")]
+    pub account_id: Option<String>,
+    pub name: Option<String>,
 }
 
-pub struct DefaultJiraClient<'a> {
+/// Jira client which sends requests with the async reqwest client
+pub struct DefaultAsyncJiraClient {
     client: Client,
-    creds: &'a Credentials,
+    creds: Credentials,
+
+    /// Limits the requests sent to Jira at once
+    limit: Semaphore,
 }
 
-impl<'a> DefaultJiraClient<'a> {
-    pub fn new(creds: &'a Credentials) -> DefaultJiraClient<'a> {
+impl DefaultAsyncJiraClient {
+    pub fn new(creds: &Credentials) -> DefaultAsyncJiraClient {
         let client = Client::new();
-        DefaultJiraClient { client, creds }
+        let limit = Semaphore::new(creds.host_limit(creds.jira_domain.as_str()).max(1));
+        DefaultAsyncJiraClient { client, creds: creds.clone(), limit }
     }
 }
 
-impl<'a> JiraClient for DefaultJiraClient<'a> {
+#[async_trait]
+impl AsyncIssueTracker for DefaultAsyncJiraClient {
+    fn name(&self) -> &str {
+        "Jira"
+    }
+
     fn get_domain(&self) -> &str {
         self.creds.jira_domain.as_str()
     }
 
-    fn post_jira_comment(&self, ticket_id: &str, text: &str) -> Result<(), Error> {
-        let jira_url = format!("https://{}/rest/api/3/issue/{}/comment?expand=renderedBody", self.creds.jira_domain, ticket_id);
-        let resp = self.client.post(jira_url)
+    fn comment_format(&self) -> CommentFormat {
+        self.creds.jira_flavor.comment_format()
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        format!("https://{}/browse/{}", self.creds.jira_domain, ticket_id)
+    }
+
+    async fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        let jira_url = format!("https://{}/rest/api/{}/issue/{}/comment?expand=renderedBody", self.creds.jira_domain, self.creds.jira_flavor.api_version(), ticket_id);
+        let request = self.client.post(&jira_url)
+            .basic_auth(self.creds.jira_user.clone(), Some(self.creds.jira_pass.clone()))
+            .header("Content-Type", "application/json")
+            .body(text.to_string());
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            let comment: JiraCreatedComment = resp.parse()?;
+            Ok(comment.id)
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    async fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        let jira_url = format!("https://{}/rest/api/{}/issue/{}/comment/{}", self.creds.jira_domain, self.creds.jira_flavor.api_version(), ticket_id, comment_id);
+        let request = self.client.put(&jira_url)
+            .basic_auth(self.creds.jira_user.clone(), Some(self.creds.jira_pass.clone()))
+            .header("Content-Type", "application/json")
+            .body(text.to_string());
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            Ok(())
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    async fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        let jira_url = format!("https://{}/rest/api/{}/issue/{}/comment?expand=renderedBody", self.creds.jira_domain, self.creds.jira_flavor.api_version(), ticket_id);
+
+        let request = self.client.get(&jira_url)
+            .basic_auth(self.creds.jira_user.clone(), Some(self.creds.jira_pass.clone()));
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            let comments: JiraCommentResponse = resp.parse()?;
+            Ok(comments.into_tracker_comments())
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    async fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        let jira_url = format!("https://{}/rest/api/{}/issue/{}/remotelink", self.creds.jira_domain, self.creds.jira_flavor.api_version(), ticket_id);
+        let request = self.client.post(&jira_url)
             .basic_auth(self.creds.jira_user.clone(), Some(self.creds.jira_pass.clone()))
             .header("Content-Type", "application/json")
-            .body(text.to_string())
-            .send()?;
+            .body(serde_json::to_string(&JiraRemoteLink::from(link))?);
+        let resp = http::send(&self.client, &self.limit, request).await?;
 
-        if resp.status().is_success() {
+        if resp.is_success() {
             Ok(())
         } else {
-            Err(Error::from("Unable to post Jira comment: ".to_owned() + &resp.status().to_string()))
+            Err(resp.error())
         }
     }
 
-    fn get_jira_comments(&self, ticket_id: &str) -> Result<JiraCommentResponse, Error> {
-        let jira_url = format!("https://{}/rest/api/3/issue/{}/comment?expand=renderedBody", self.creds.jira_domain, ticket_id);
+    async fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        let query = match self.creds.jira_flavor {
+            JiraFlavor::Cloud => "query",
+            JiraFlavor::Server => "username",
+        };
+        let jira_url = format!("https://{}/rest/api/{}/user/search", self.creds.jira_domain, self.creds.jira_flavor.api_version());
 
-        let resp = self.client.get(jira_url)
+        let request = self.client.get(&jira_url)
             .basic_auth(self.creds.jira_user.clone(), Some(self.creds.jira_pass.clone()))
-            .send()?;
+            .query(&[(query, email)]);
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            let users: Vec<JiraUser> = resp.parse()?;
+            Ok(users.into_iter().find_map(|user| match self.creds.jira_flavor {
+                JiraFlavor::Cloud => user.account_id,
+                JiraFlavor::Server => user.name,
+            }))
+        } else {
+            Err(resp.error())
+        }
+    }
+
+    async fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        let jira_url = format!("https://{}/rest/api/{}/issue/{}/remotelink", self.creds.jira_domain, self.creds.jira_flavor.api_version(), ticket_id);
+
+        let request = self.client.get(&jira_url)
+            .basic_auth(self.creds.jira_user.clone(), Some(self.creds.jira_pass.clone()));
+        let resp = http::send(&self.client, &self.limit, request).await?;
 
-        if resp.status().is_success() {
-            Ok(serde_json::from_str(resp.text()?.as_str())?)
+        if resp.is_success() {
+            let links: Vec<JiraRemoteLink> = resp.parse()?;
+            Ok(links.into_iter().map(IssueLink::from).collect())
         } else {
-            Err(Error::from(resp.text()?))
+            Err(resp.error())
         }
     }
+
+    async fn get_ticket(&self, ticket_id: &str) -> Result<TicketSummary, Error> {
+        let jira_url = format!("https://{}/rest/api/{}/issue/{}", self.creds.jira_domain, self.creds.jira_flavor.api_version(), ticket_id);
+
+        let request = self.client.get(&jira_url)
+            .basic_auth(self.creds.jira_user.clone(), Some(self.creds.jira_pass.clone()))
+            .query(&[("fields", "summary,status,assignee,priority,fixVersions")]);
+        let resp = http::send(&self.client, &self.limit, request).await?;
+
+        if resp.is_success() {
+            let issue: JiraIssue = resp.parse()?;
+            Ok(issue.into_summary())
+        } else {
+            Err(resp.error())
+        }
+    }
+}
+
+/// Blocking Jira client, which waits for `DefaultAsyncJiraClient`'s requests
+/// on a shared runtime. It can't be used from inside an async runtime.
+pub struct DefaultJiraClient {
+    inner: DefaultAsyncJiraClient,
+}
+
+impl DefaultJiraClient {
+    pub fn new(creds: &Credentials) -> DefaultJiraClient {
+        DefaultJiraClient { inner: DefaultAsyncJiraClient::new(creds) }
+    }
+}
+
+impl IssueTracker for DefaultJiraClient {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn get_domain(&self) -> &str {
+        self.inner.get_domain()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        self.inner.comment_format()
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        self.inner.ticket_url(ticket_id)
+    }
+
+    fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        pool::block_on(self.inner.get_comments(ticket_id))
+    }
+
+    fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        pool::block_on(self.inner.post_comment(ticket_id, text))
+    }
+
+    fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        pool::block_on(self.inner.update_comment(ticket_id, comment_id, text))
+    }
+
+    fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        pool::block_on(self.inner.get_links(ticket_id))
+    }
+
+    fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        pool::block_on(self.inner.post_link(ticket_id, link))
+    }
+
+    fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.find_account_id(email))
+    }
+
+    fn get_ticket(&self, ticket_id: &str) -> Result<TicketSummary, Error> {
+        pool::block_on(self.inner.get_ticket(ticket_id))
+    }
 }
 
 pub fn parse_jira_ticket_number(pr_body: &str, domain: &str) -> Option<String> {
     let re = regex::Regex::new(format!(r"\[(\w+\-\d+)\]\(https://{}\S+\)", domain.replace(".", r"\.")).as_str()).unwrap();
-    for group in re.captures_iter(pr_body) {
-        // Return the first matching ticket
-        return Some(group[1].to_string());
-    }
-    None
+    // Return the first matching ticket
+    re.captures(pr_body).map(|group| group[1].to_string())
 }
 
 pub struct MockJiraClient {
     pub domain: String,
-    pub data: Box<JiraCommentResponse>
+    pub data: Box<JiraCommentResponse>,
+    pub links: Box<Vec<JiraRemoteLink>>,
 }
 
-impl JiraClient for MockJiraClient {
+impl IssueTracker for MockJiraClient {
+    fn name(&self) -> &str {
+        "Jira"
+    }
+
     fn get_domain(&self) -> &str {
         self.domain.as_str()
     }
 
-    fn post_jira_comment(&self, _ticket_id: &str, _text: &str) -> Result<(), Error> {
+    fn comment_format(&self) -> CommentFormat {
+        CommentFormat::Adf
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        format!("https://{}/browse/{}", self.domain, ticket_id)
+    }
+
+    fn get_comments(&self, _ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        Ok(self.data.clone().into_tracker_comments())
+    }
+
+    fn post_comment(&self, _ticket_id: &str, _text: &str) -> Result<String, Error> {
+        Ok("10000".to_string())
+    }
+
+    fn update_comment(&self, _ticket_id: &str, _comment_id: &str, _text: &str) -> Result<(), Error> {
         Ok(())
     }
 
-    fn get_jira_comments(&self, _ticket_id: &str) -> Result<JiraCommentResponse, Error> {
-        Ok(*self.data.clone())
+    fn get_links(&self, _ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        Ok(self.links.iter().cloned().map(IssueLink::from).collect())
+    }
+
+    fn post_link(&self, _ticket_id: &str, _link: &IssueLink) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn find_account_id(&self, _email: &str) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    fn get_ticket(&self, ticket_id: &str) -> Result<TicketSummary, Error> {
+        Ok(TicketSummary {
+            key: ticket_id.to_string(),
+            summary: "Mock ticket".to_string(),
+            status: "In Progress".to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Fails to read comments with a 502 response the given number of times,
+/// then works like the mock it wraps
+pub struct FlakyJiraClient {
+    pub inner: MockJiraClient,
+    pub failures: AtomicUsize,
+}
+
+impl IssueTracker for FlakyJiraClient {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn get_domain(&self) -> &str {
+        self.inner.get_domain()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        self.inner.comment_format()
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        self.inner.ticket_url(ticket_id)
+    }
+
+    fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        if self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| failures.checked_sub(1)).is_ok() {
+            let url = format!("https://{}/rest/api/3/issue/{}/comment", self.inner.domain, ticket_id);
+            return Err(Error::from_response("GET", &url, StatusCode::BAD_GATEWAY, ""));
+        }
+        self.inner.get_comments(ticket_id)
+    }
+
+    fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        self.inner.post_comment(ticket_id, text)
+    }
+
+    fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        self.inner.update_comment(ticket_id, comment_id, text)
+    }
+
+    fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        self.inner.get_links(ticket_id)
+    }
+
+    fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        self.inner.post_link(ticket_id, link)
+    }
+
+    fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        self.inner.find_account_id(email)
+    }
+
+    fn get_ticket(&self, ticket_id: &str) -> Result<TicketSummary, Error> {
+        self.inner.get_ticket(ticket_id)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::jira::{JiraComment, JiraCommentResponse, parse_jira_ticket_number};
+    use crate::jira::{JiraComment, JiraCommentResponse, JiraIssue, parse_jira_ticket_number};
+
+    const ISSUE: &str = include_str!("../tests/fixtures/jira_issue.json");
 
     #[test]
     fn jira_comment_contains_text_true() {
@@ -201,9 +566,11 @@ mod test {
             total: 2,
             comments: vec![
                 JiraComment {
+                    id: String::new(),
                     rendered_body: "asdfas asdf asdf ads".to_string()
                 },
                 JiraComment {
+                    id: String::new(),
                     rendered_body: "asdf asdf afsd adfs https://url/org/repo asdfasdf".to_string()
                 },
             ]
@@ -226,9 +593,11 @@ mod test {
             total: 2,
             comments: vec![
                 JiraComment {
+                    id: String::new(),
                     rendered_body: "asdf asdf afsd adfs https://url/org/otherrepo asdfasdf".to_string()
                 },
                 JiraComment {
+                    id: String::new(),
                     rendered_body: "asdfas asdf asdf ads".to_string()
                 }
             ]
@@ -245,4 +614,14 @@ mod test {
     fn parse_jira_ticket_number_no_match() {
         assert!(parse_jira_ticket_number("dsaaerl; are aerg \nasfwqrwrv\nasdfawfr\tasdfar w\nasdf", "jira.domain").is_none())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn renders_ticket_summary() {
+        let issue: JiraIssue = serde_json::from_str(ISSUE).unwrap();
+        let summary = issue.into_summary();
+        assert_eq!(summary.fix_versions, vec!["2.4.0".to_string(), "2.3.1".to_string()]);
+
+        let rendered = summary.render_markdown("https://jira.domain/browse/ABC-123");
+        assert_eq!(rendered, "<!-- autocomment: ticket summary -->\n### [ABC-123](https://jira.domain/browse/ABC-123): Login redirect loops | staging\n\n| Status | Assignee | Priority | Fix versions |\n| --- | --- | --- | --- |\n| In Review | Dana Reviewer | High | 2.4.0, 2.3.1 |\n");
+    }
+}
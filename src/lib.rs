@@ -1,47 +1,595 @@
+pub mod action;
+pub mod adf;
+pub mod bitbucket;
 pub mod error;
+pub mod gitea;
 pub mod github;
+pub mod github_issues;
+pub mod gitlab;
+mod http;
 pub mod jira;
+pub mod linear;
+pub mod markdown;
+pub mod metrics;
+pub mod pool;
+pub mod redmine;
+pub mod report;
+pub mod review;
+pub mod server;
 pub mod credentials;
+pub mod state;
+pub mod template;
+pub mod tracker;
+pub mod users;
+pub mod watch;
+pub mod youtrack;
 
 pub use crate::credentials::Credentials;
-pub use crate::github::DefaultGithubClient;
-pub use crate::jira::DefaultJiraClient;
+pub use crate::github::{DefaultAsyncGithubClient, DefaultGithubClient};
+pub use crate::gitlab::{DefaultAsyncGitlabClient, DefaultGitlabClient};
+pub use crate::jira::{DefaultAsyncJiraClient, DefaultJiraClient};
+pub use crate::linear::{DefaultAsyncLinearClient, DefaultLinearClient};
 pub use crate::error::Error;
 
-use crate::github::GHPullRequest;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
 
-pub fn sync_comments(repo: &str, filters: &str, gh_client: &dyn github::GithubClient, jira_client: &dyn jira::JiraClient) -> Result<Vec<String>, Error> {
-    gh_client.get_pull_requests_for_repo(repo, filters)?.iter()
-        .map(|pr| process_pull_request(jira_client, pr))
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+
+use crate::error::ErrorContext;
+use crate::review::{AsyncReviewClient, ChangeRequest, CheckRun, CommitStatus};
+use crate::jira::JiraWikiCommentRequest;
+use crate::pool::{map_ordered, DEFAULT_CONCURRENCY};
+use crate::state::{content_hash, RecordKind, StateStore, SyncRecord};
+use crate::template::CommentTemplate;
+use crate::tracker::{AsyncIssueTracker, CommentFormat, IssueTracker, SUMMARY_MARKER};
+use crate::users::UserDirectory;
+
+/// What autocomment should create on a Jira ticket for each pull request
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum SyncMode {
+    /// Post a comment describing the pull request
+    #[default]
+    Comment,
+
+    /// Create a remote issue link to the pull request
+    RemoteLink,
+
+    /// Post a comment and create a remote issue link
+    Both,
+}
+
+impl SyncMode {
+    fn comments(&self) -> bool {
+        matches!(self, SyncMode::Comment | SyncMode::Both)
+    }
+
+    fn remote_links(&self) -> bool {
+        matches!(self, SyncMode::RemoteLink | SyncMode::Both)
+    }
+}
+
+impl FromStr for SyncMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "comment" => Ok(SyncMode::Comment),
+            "link" => Ok(SyncMode::RemoteLink),
+            "both" => Ok(SyncMode::Both),
+            _ => Err(Error::from(format!("Unknown sync mode {}, expected one of comment, link, both", s))),
+        }
+    }
+}
+
+/// Options controlling what is created on Jira tickets during a sync
+#[derive(Clone)]
+pub struct SyncOptions {
+    pub mode: SyncMode,
+    pub template: CommentTemplate,
+    pub users: UserDirectory,
+
+    /// Where to record what has been synced. Pull requests whose content is
+    /// unchanged since it was recorded are skipped without calling Jira.
+    pub state: Option<Arc<StateStore>>,
+
+    /// Number of pull requests to process at once
+    pub concurrency: usize,
+
+    /// Whether to post the ticket's summary as a comment on the pull request,
+    /// updating it whenever the ticket has changed
+    pub pr_summary: bool,
+
+    /// Whether to check that referenced tickets exist before syncing them
+    pub validate_tickets: bool,
+
+    /// Projects tickets may be in, such as ABC for ABC-123. Tickets in any
+    /// project are allowed when it's empty.
+    pub ticket_projects: Vec<String>,
+
+    /// Statuses tickets may be in, compared ignoring case. Tickets in any
+    /// status are allowed when it's empty.
+    pub ticket_statuses: Vec<String>,
+
+    /// Whether to set a commit status on the pull request's head reporting
+    /// whether it references a valid ticket. Tickets are validated when set.
+    pub commit_status: bool,
+
+    /// Whether to create a check run on the pull request's head, like
+    /// `commit_status` but explaining what's wrong with the reference
+    pub check_run: bool,
+}
+
+impl SyncOptions {
+    fn validates_tickets(&self) -> bool {
+        self.validate_tickets || self.commit_status || self.check_run
+    }
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            mode: SyncMode::default(),
+            template: CommentTemplate::default(),
+            users: UserDirectory::default(),
+            state: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            pr_summary: false,
+            validate_tickets: false,
+            ticket_projects: Vec::new(),
+            ticket_statuses: Vec::new(),
+            commit_status: false,
+            check_run: false,
+        }
+    }
+}
+
+/// What was done on a Jira ticket for a pull request
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncAction {
+    CommentAdded,
+    CommentUpdated,
+    CommentExists,
+    CommentUnchanged,
+    LinkAdded,
+    LinkUpdated,
+    LinkExists,
+    LinkUnchanged,
+    SummaryAdded,
+    SummaryUpdated,
+    SummaryUnchanged,
+
+    /// The referenced ticket doesn't exist
+    TicketNotFound,
+
+    /// The referenced ticket isn't visible to the configured user
+    TicketNoPermission,
+
+    /// The referenced ticket isn't in one of the allowed projects
+    TicketWrongProject,
+
+    /// The referenced ticket isn't in one of the allowed statuses
+    TicketWrongStatus,
+
+    /// The pull request doesn't reference a Jira ticket
+    NoTicket,
+}
+
+impl SyncAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncAction::CommentAdded => "comment_added",
+            SyncAction::CommentUpdated => "comment_updated",
+            SyncAction::CommentExists => "comment_exists",
+            SyncAction::CommentUnchanged => "comment_unchanged",
+            SyncAction::LinkAdded => "link_added",
+            SyncAction::LinkUpdated => "link_updated",
+            SyncAction::LinkExists => "link_exists",
+            SyncAction::LinkUnchanged => "link_unchanged",
+            SyncAction::SummaryAdded => "summary_added",
+            SyncAction::SummaryUpdated => "summary_updated",
+            SyncAction::SummaryUnchanged => "summary_unchanged",
+            SyncAction::TicketNotFound => "ticket_not_found",
+            SyncAction::TicketNoPermission => "ticket_no_permission",
+            SyncAction::TicketWrongProject => "ticket_wrong_project",
+            SyncAction::TicketWrongStatus => "ticket_wrong_status",
+            SyncAction::NoTicket => "no_ticket",
+        }
+    }
+
+    /// Describes the action, where `tracker` is the name of the issue tracker
+    pub fn message(&self, tracker: &str, ticket_url: &str, pr_url: &str) -> String {
+        match self {
+            SyncAction::CommentAdded => format!("Added {} Comment on ticket {} from {}.", tracker, ticket_url, pr_url),
+            SyncAction::CommentUpdated => format!("Updated {} Comment on ticket {} from {}.", tracker, ticket_url, pr_url),
+            SyncAction::CommentExists => format!("{} ticket {} already has comment for {}.", tracker, ticket_url, pr_url),
+            SyncAction::CommentUnchanged => format!("{} ticket {} comment for {} is unchanged.", tracker, ticket_url, pr_url),
+            SyncAction::LinkAdded => format!("Added {} link on ticket {} to {}.", tracker, ticket_url, pr_url),
+            SyncAction::LinkUpdated => format!("Updated {} link status on ticket {} for {}.", tracker, ticket_url, pr_url),
+            SyncAction::LinkExists => format!("{} ticket {} already has link for {}.", tracker, ticket_url, pr_url),
+            SyncAction::LinkUnchanged => format!("{} ticket {} link for {} is unchanged.", tracker, ticket_url, pr_url),
+            SyncAction::SummaryAdded => format!("Added summary of {} ticket {} on {}.", tracker, ticket_url, pr_url),
+            SyncAction::SummaryUpdated => format!("Updated summary of {} ticket {} on {}.", tracker, ticket_url, pr_url),
+            SyncAction::SummaryUnchanged => format!("Summary of {} ticket {} on {} is unchanged.", tracker, ticket_url, pr_url),
+            SyncAction::TicketNotFound => format!("{} ticket {} referenced by {} does not exist.", tracker, ticket_url, pr_url),
+            SyncAction::TicketNoPermission => format!("{} ticket {} referenced by {} is not visible to the configured user.", tracker, ticket_url, pr_url),
+            SyncAction::TicketWrongProject => format!("{} ticket {} referenced by {} is not in an allowed project.", tracker, ticket_url, pr_url),
+            SyncAction::TicketWrongStatus => format!("{} ticket {} referenced by {} is not in an allowed status.", tracker, ticket_url, pr_url),
+            SyncAction::NoTicket => format!("PR {} does not contain a {} ticket!", pr_url, tracker),
+        }
+    }
+
+    /// Whether the action reports a problem with the referenced ticket
+    pub fn is_ticket_problem(&self) -> bool {
+        matches!(self, SyncAction::TicketNotFound | SyncAction::TicketNoPermission | SyncAction::TicketWrongProject | SyncAction::TicketWrongStatus)
+    }
+}
+
+/// The outcome of syncing a pull request to Jira
+#[derive(Clone, PartialEq, Debug)]
+pub struct PullRequestSync {
+    /// Name of the issue tracker synced to
+    pub tracker: String,
+    pub pr_url: String,
+    pub ticket_key: Option<String>,
+    pub ticket_url: Option<String>,
+    pub actions: Vec<SyncAction>,
+
+    /// ID of the pull request's Jira comment, when it's known
+    pub comment_id: Option<String>,
+}
+
+impl PullRequestSync {
+    /// Describes each action taken, one per line
+    pub fn message(&self) -> String {
+        let ticket_url = self.ticket_url.as_deref().unwrap_or_default();
+
+        self.actions.iter()
+            .map(|action| action.message(self.tracker.as_str(), ticket_url, self.pr_url.as_str()))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Lets async code use a blocking client, such as `MockReviewClient` or
+/// `MockJiraClient`. Requests block the thread polling the future.
+pub struct Blocking<'a, C: ?Sized>(pub &'a C);
+
+pub fn sync_comments(repo: &str, filters: &str, review_client: &dyn review::ReviewClient, tracker: &dyn IssueTracker) -> Result<Vec<String>, Error> {
+    sync_pull_requests(repo, filters, &SyncOptions::default(), review_client, tracker)
+}
+
+#[tracing::instrument(skip_all, fields(repo))]
+pub fn sync_pull_requests(repo: &str, filters: &str, options: &SyncOptions, review_client: &dyn review::ReviewClient, tracker: &dyn IssueTracker) -> Result<Vec<String>, Error> {
+    let prs = review_client.get_changes_for_repo(repo, filters)?;
+    process_pull_requests(review_client, tracker, &prs, options).into_iter().collect()
+}
+
+/// Syncs the pull requests using up to `options.concurrency` threads. The
+/// results are in the same order as the pull requests.
+pub fn process_pull_requests(review_client: &dyn review::ReviewClient, tracker: &dyn IssueTracker, prs: &[ChangeRequest], options: &SyncOptions) -> Vec<Result<String, Error>> {
+    // Workers run on their own threads, so they need to enter the caller's span
+    let span = tracing::Span::current();
+    map_ordered(prs, options.concurrency, |pr| span.in_scope(|| process_pull_request(review_client, tracker, pr, options)))
+}
+
+/// Syncs a single pull request to the Jira ticket it references
+pub fn process_pull_request(review_client: &dyn review::ReviewClient, tracker: &dyn IssueTracker, pr: &ChangeRequest, options: &SyncOptions) -> Result<String, Error> {
+    sync_pull_request(review_client, tracker, pr, options).map(|sync| sync.message())
+}
+
+/// Syncs a single pull request, returning what was done on the Jira ticket
+pub fn sync_pull_request(review_client: &dyn review::ReviewClient, tracker: &dyn IssueTracker, pr: &ChangeRequest, options: &SyncOptions) -> Result<PullRequestSync, Error> {
+    futures::executor::block_on(sync_pull_request_async(&Blocking(review_client), &Blocking(tracker), pr, options))
+}
+
+pub async fn sync_comments_async(repo: &str, filters: &str, review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker) -> Result<Vec<String>, Error> {
+    sync_pull_requests_async(repo, filters, &SyncOptions::default(), review_client, tracker).await
+}
+
+#[tracing::instrument(skip_all, fields(repo))]
+pub async fn sync_pull_requests_async(repo: &str, filters: &str, options: &SyncOptions, review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker) -> Result<Vec<String>, Error> {
+    let prs = review_client.get_changes_for_repo(repo, filters).await?;
+    process_pull_requests_async(review_client, tracker, &prs, options).await.into_iter().collect()
+}
+
+/// Syncs up to `options.concurrency` pull requests at once. The results are
+/// in the same order as the pull requests.
+pub async fn process_pull_requests_async(review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker, prs: &[ChangeRequest], options: &SyncOptions) -> Vec<Result<String, Error>> {
+    // The futures are created up front, since a stream mapping over the pull
+    // requests wouldn't be Send
+    let syncs: Vec<_> = prs.iter()
+        .map(|pr| process_pull_request_async(review_client, tracker, pr, options))
+        .collect();
+
+    stream::iter(syncs)
+        .buffered(options.concurrency.max(1))
         .collect()
+        .await
 }
 
-fn process_pull_request(jira_client: &dyn jira::JiraClient, pr: &GHPullRequest) -> Result<String, Error> {
+/// Syncs a single pull request to the Jira ticket it references
+pub async fn process_pull_request_async(review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker, pr: &ChangeRequest, options: &SyncOptions) -> Result<String, Error> {
+    sync_pull_request_async(review_client, tracker, pr, options).await.map(|sync| sync.message())
+}
+
+/// Syncs a single pull request, returning what was done on the Jira ticket
+#[tracing::instrument(skip_all, fields(pr = %pr.html_url, ticket = tracing::field::Empty))]
+pub async fn sync_pull_request_async(review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker, pr: &ChangeRequest, options: &SyncOptions) -> Result<PullRequestSync, Error> {
+    let result = try_sync_pull_request(review_client, tracker, pr, options).await;
+    metrics::metrics().record_sync(&result);
+    result
+}
+
+async fn try_sync_pull_request(review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker, pr: &ChangeRequest, options: &SyncOptions) -> Result<PullRequestSync, Error> {
     let pr_body = pr.body.clone().ok_or(Error::AutocommentError(format!("PR {} does not have a description!", pr.html_url.clone())))?;
 
-    // Parse the PR body to find a JIRA ticket
-    if let Some(jira_id) = jira::parse_jira_ticket_number(pr_body.as_str(), jira_client.get_domain()) {
+    let mut sync = PullRequestSync {
+        tracker: tracker.name().to_string(),
+        pr_url: pr.html_url.clone(),
+        ticket_key: None,
+        ticket_url: None,
+        actions: Vec::new(),
+        comment_id: None,
+    };
 
+    // Parse the PR body to find a ticket
+    if let Some(jira_id) = tracker.parse_ticket(pr_body.as_str()) {
         // Create the URL linking to this specific ticket
-        let ticket_url = format!("https://{}/browse/{}", jira_client.get_domain(), jira_id);
+        sync.ticket_url = Some(tracker.ticket_url(jira_id.as_str()));
+        tracing::Span::current().record("ticket", jira_id.as_str());
 
-        // Do HTTP request to get the comments for this PR
-        let comments = jira_client.get_jira_comments(jira_id.as_str())?;
+        let context = || ErrorContext {
+            repo: Some(pr.base.repo.full_name.clone()),
+            pr: Some(pr.html_url.clone()),
+            ticket: Some(jira_id.clone()),
+        };
 
-        // Check whether the comments already contain this PR's URL
-        if !comments.contains_text(pr.html_url.as_str()) {
+        let problem = if options.validates_tickets() {
+            validate_ticket(tracker, jira_id.as_str(), options).await
+                .map_err(|err| err.with_context(context()))?
+        } else {
+            None
+        };
 
-            let comment_text = serde_json::to_string(&pr.build_jira_comment()?);
+        // Nothing is synced to a ticket which can't be found
+        if let Some(problem) = problem {
+            sync.actions.push(problem);
+        } else {
+            if options.mode.comments() {
+                let (action, comment_id) = sync_ticket_comment(review_client, tracker, pr, jira_id.as_str(), options).await
+                    .map_err(|err| err.with_context(context()))?;
+                sync.actions.push(action);
+                sync.comment_id = comment_id;
+            }
 
-            // Do HTTP request to post the comment
-            jira_client.post_jira_comment(jira_id.as_str(), comment_text?.as_str())
-                .map(|_| format!("Added Jira Comment on ticket {} from {}.", ticket_url, pr.html_url.clone()))
+            if options.mode.remote_links() {
+                let action = sync_ticket_link(tracker, pr, jira_id.as_str(), options).await
+                    .map_err(|err| err.with_context(context()))?;
+                sync.actions.push(action);
+            }
 
-        } else {
-            Ok(format!("Jira ticket {} already has comment for {}.", ticket_url, pr.html_url.clone()))
+            if options.pr_summary {
+                let action = sync_pr_summary(review_client, tracker, pr, jira_id.as_str()).await
+                    .map_err(|err| err.with_context(context()))?;
+                sync.actions.push(action);
+            }
         }
+
+        sync.ticket_key = Some(jira_id);
+    } else {
+        sync.actions.push(SyncAction::NoTicket);
+    }
+
+    if options.commit_status || options.check_run {
+        report_ticket_check(review_client, pr, &sync, options).await
+            .map_err(|err| err.with_context(ErrorContext {
+                repo: Some(pr.base.repo.full_name.clone()),
+                pr: Some(pr.html_url.clone()),
+                ticket: sync.ticket_key.clone(),
+            }))?;
+    }
+
+    for action in &sync.actions {
+        tracing::info!(action = action.as_str(), "synced pull request");
+    }
+
+    Ok(sync)
+}
+
+/// Posts the comment for the pull request unless the ticket already has one,
+/// or updates the comment posted last time when the pull request has changed.
+/// Returns the ID of the comment when it's known.
+async fn sync_ticket_comment(review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker, pr: &ChangeRequest, jira_id: &str, options: &SyncOptions) -> Result<(SyncAction, Option<String>), Error> {
+    // The hash is of the comment mentioning only the configured accounts, so
+    // unchanged PRs are skipped without looking up users
+    let hash = content_hash(render_comment(tracker, pr, &options.users.configured_accounts_for(pr), options)?.as_str());
+
+    let synced = synced_record(options, pr, jira_id, RecordKind::Comment)?;
+    if let Some(synced) = synced.as_ref().filter(|synced| synced.content_hash == hash) {
+        return Ok((SyncAction::CommentUnchanged, synced.jira_id.clone()));
+    }
+
+    // Look up the tracker accounts of the author and reviewers to mention them
+    let accounts = options.users.accounts_for(pr, review_client, tracker).await?;
+    let comment_text = render_comment(tracker, pr, &accounts, options)?;
+    let marker = tracker.comment_marker(pr.html_url.as_str());
+
+    // Do HTTP request to get the comments for this PR
+    let comments = tracker.get_comments(jira_id).await?;
+
+    // Update the comment posted last time, as long as it hasn't been deleted
+    let previous = synced.and_then(|synced| synced.jira_id)
+        .filter(|comment_id| comments.iter().any(|comment| &comment.id == comment_id));
+    if let Some(comment_id) = previous {
+        tracker.update_comment(jira_id, comment_id.as_str(), comment_text.as_str()).await?;
+        record(options, pr, jira_id, RecordKind::Comment, Some(comment_id.as_str()), hash.as_str())?;
+
+        return Ok((SyncAction::CommentUpdated, Some(comment_id)));
+    }
+
+    // Check whether the comments already contain this PR's marker or URL
+    let identifier = marker.unwrap_or(pr.html_url.clone());
+    if !comments.iter().any(|comment| comment.body.contains(identifier.as_str())) {
+
+        // Do HTTP request to post the comment
+        let comment_id = tracker.post_comment(jira_id, comment_text.as_str()).await?;
+        record(options, pr, jira_id, RecordKind::Comment, Some(comment_id.as_str()), hash.as_str())?;
+
+        Ok((SyncAction::CommentAdded, Some(comment_id)))
+
     } else {
-        Ok(format!("PR {} does not contain a Jira ticket!", pr.html_url.clone()))
+        record(options, pr, jira_id, RecordKind::Comment, None, hash.as_str())?;
+
+        Ok((SyncAction::CommentExists, None))
+    }
+}
+
+/// Renders the PR's comment in the tracker's format, ending with the
+/// tracker's marker for the PR if it has one
+fn render_comment(tracker: &dyn AsyncIssueTracker, pr: &ChangeRequest, accounts: &HashMap<String, String>, options: &SyncOptions) -> Result<String, Error> {
+    let comment_text = match tracker.comment_format() {
+        CommentFormat::Adf => serde_json::to_string(&options.template.render_adf(pr, accounts)?)?,
+        CommentFormat::Wiki => serde_json::to_string(&JiraWikiCommentRequest { body: options.template.render_wiki(pr, accounts)? })?,
+        CommentFormat::Markdown => options.template.render_markdown(pr, accounts)?,
+        CommentFormat::Textile => options.template.render_textile(pr, accounts)?,
+    };
+
+    Ok(match tracker.comment_marker(pr.html_url.as_str()) {
+        Some(marker) => format!("{}\n\n{}", comment_text, marker),
+        None => comment_text,
+    })
+}
+
+async fn sync_ticket_link(tracker: &dyn AsyncIssueTracker, pr: &ChangeRequest, jira_id: &str, options: &SyncOptions) -> Result<SyncAction, Error> {
+    let link = pr.build_issue_link();
+    let hash = content_hash(serde_json::to_string(&link)?.as_str());
+
+    let synced = synced_record(options, pr, jira_id, RecordKind::Link)?;
+    if synced.map(|synced| synced.content_hash == hash).unwrap_or(false) {
+        return Ok(SyncAction::LinkUnchanged);
+    }
+
+    // Links are keyed by the PR's URL, so an existing link only needs to be
+    // posted again when the PR's resolved status has changed
+    let existing = tracker.get_links(jira_id).await?.into_iter()
+        .find(|existing| existing.url == pr.html_url);
+
+    let action = match existing {
+        Some(existing) if existing.resolved == pr.is_resolved() => SyncAction::LinkExists,
+        Some(_) => {
+            tracker.post_link(jira_id, &link).await?;
+            SyncAction::LinkUpdated
+        }
+        None => {
+            tracker.post_link(jira_id, &link).await?;
+            SyncAction::LinkAdded
+        }
+    };
+
+    record(options, pr, jira_id, RecordKind::Link, None, hash.as_str())?;
+    Ok(action)
+}
+
+/// Checks that the ticket is in an allowed project and exists, returning the
+/// problem with it if there is one
+async fn validate_ticket(tracker: &dyn AsyncIssueTracker, jira_id: &str, options: &SyncOptions) -> Result<Option<SyncAction>, Error> {
+    // Trackers whose ticket IDs don't name a project can't be checked
+    if let Some(project) = tracker.ticket_project(jira_id) {
+        if !options.ticket_projects.is_empty() && !options.ticket_projects.contains(&project) {
+            return Ok(Some(SyncAction::TicketWrongProject));
+        }
+    }
+
+    match tracker.get_ticket(jira_id).await {
+        Ok(ticket) if !options.ticket_statuses.is_empty() && !options.ticket_statuses.iter().any(|allowed| allowed.eq_ignore_ascii_case(&ticket.status)) =>
+            Ok(Some(SyncAction::TicketWrongStatus)),
+        Ok(_) => Ok(None),
+        Err(Error::NotFoundError(_)) => Ok(Some(SyncAction::TicketNotFound)),
+        Err(Error::PermissionError(_)) => Ok(Some(SyncAction::TicketNoPermission)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reports on the pull request's head commit whether it references a valid
+/// ticket, so ticket references can be required before merging
+async fn report_ticket_check(review_client: &dyn AsyncReviewClient, pr: &ChangeRequest, sync: &PullRequestSync, options: &SyncOptions) -> Result<(), Error> {
+    let ticket = sync.ticket_key.as_deref().unwrap_or_default();
+    let problem = sync.actions.iter().find(|action| action.is_ticket_problem() || **action == SyncAction::NoTicket);
+
+    let description = match problem {
+        None => format!("{} ticket {} found", sync.tracker, ticket),
+        Some(SyncAction::TicketNotFound) => format!("{} ticket {} does not exist", sync.tracker, ticket),
+        Some(SyncAction::TicketNoPermission) => format!("{} ticket {} is not visible to autocomment", sync.tracker, ticket),
+        Some(SyncAction::TicketWrongProject) => format!("{} ticket {} is not in an allowed project", sync.tracker, ticket),
+        Some(SyncAction::TicketWrongStatus) => format!("{} ticket {} is not in an allowed status", sync.tracker, ticket),
+        Some(_) => format!("{} ticket missing", sync.tracker),
+    };
+
+    if options.commit_status {
+        let status = CommitStatus { success: problem.is_none(), description: description.clone(), target_url: sync.ticket_url.clone() };
+        review_client.set_commit_status(pr, &status).await?;
+    }
+
+    if options.check_run {
+        let explanation = match problem {
+            None => String::new(),
+            Some(SyncAction::TicketWrongProject) => format!("Tickets must be in one of these projects: {}.", options.ticket_projects.join(", ")),
+            Some(SyncAction::TicketWrongStatus) => format!("Tickets must be in one of these statuses: {}.", options.ticket_statuses.join(", ")),
+            Some(SyncAction::NoTicket) => format!("Link the {} ticket for this pull request in its description.", sync.tracker),
+            Some(_) => "Link a ticket which exists and is visible to autocomment in the pull request's description.".to_string(),
+        };
+
+        let check = CheckRun {
+            success: problem.is_none(),
+            title: description,
+            summary: format!("{}\n\n{}", sync.message(), explanation).trim_end().to_string(),
+            details_url: sync.ticket_url.clone(),
+        };
+        review_client.set_check_run(pr, &check).await?;
+    }
+
+    Ok(())
+}
+
+/// Posts the ticket's summary as a comment on the pull request, or edits the
+/// comment posted before when the ticket has changed. The comment is found by
+/// its hidden marker.
+async fn sync_pr_summary(review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker, pr: &ChangeRequest, jira_id: &str) -> Result<SyncAction, Error> {
+    let summary = tracker.get_ticket(jira_id).await?;
+    let body = summary.render_markdown(tracker.ticket_url(jira_id).as_str());
+
+    let existing = review_client.get_change_comments(pr).await?.into_iter()
+        .find(|comment| comment.body.contains(SUMMARY_MARKER));
+
+    match existing {
+        Some(comment) if comment.body.trim() == body.trim() => Ok(SyncAction::SummaryUnchanged),
+        Some(comment) => {
+            review_client.update_change_comment(pr, comment.id.as_str(), body.as_str()).await?;
+            Ok(SyncAction::SummaryUpdated)
+        }
+        None => {
+            review_client.post_change_comment(pr, body.as_str()).await?;
+            Ok(SyncAction::SummaryAdded)
+        }
+    }
+}
+
+/// What the state store recorded as synced for the pull request, if anything
+fn synced_record(options: &SyncOptions, pr: &ChangeRequest, jira_id: &str, kind: RecordKind) -> Result<Option<SyncRecord>, Error> {
+    match &options.state {
+        Some(state) => state.get(pr.html_url.as_str(), jira_id, kind),
+        None => Ok(None),
+    }
+}
+
+fn record(options: &SyncOptions, pr: &ChangeRequest, jira_id: &str, kind: RecordKind, created_id: Option<&str>, hash: &str) -> Result<(), Error> {
+    match &options.state {
+        Some(state) => state.record(pr.html_url.as_str(), jira_id, kind, created_id, hash),
+        None => Ok(()),
     }
 }
 
@@ -60,9 +608,15 @@ impl TakeUntil<Self> for &str {
 
 #[cfg(test)]
 mod test {
-    use crate::{GHPullRequest, sync_comments, TakeUntil};
-    use crate::github::{GHPullRequestBase, GHPullRequestOwner, GHRepo, MockGithubClient};
-    use crate::jira::{JiraComment, JiraCommentResponse, MockJiraClient};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use crate::{Blocking, ChangeRequest, Error, sync_comments, sync_comments_async, sync_pull_request, sync_pull_requests, SyncAction, SyncMode, SyncOptions, TakeUntil};
+    use crate::review::{Account, ChangeBase, ChangeComment, ChangeHead, ChangeRepo, CheckRun, CommitStatus, MockReviewClient, Provider, ReviewClient, UpdatedChanges};
+    use crate::tracker::SUMMARY_MARKER;
+    use crate::jira::{JiraComment, JiraCommentResponse, JiraRemoteLink, JiraRemoteLinkObject, JiraRemoteLinkStatus, MockJiraClient};
+    use crate::state::{RecordKind, StateStore};
+    use crate::users::UserDirectory;
 
     #[test]
     fn adds_comments_on_prs() {
@@ -72,45 +626,69 @@ mod test {
                 total: 2,
                 comments: vec![
                     JiraComment {
+                        id: String::new(),
                         rendered_body: "asdfageta".to_string()
                     },
                     JiraComment {
+                        id: String::new(),
                         rendered_body: "aeradadf asafsd asd ".to_string()
                     },
                 ],
-            })
+            }),
+            links: Box::new(Vec::new()),
         };
 
-        let gh_client = MockGithubClient {
+        let review_client = MockReviewClient {
             data: Box::new(vec![
-                GHPullRequest {
-                    base: GHPullRequestBase { repo: GHRepo { full_name: "org/repo".to_string() } },
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
                     html_url: "https://url/org/repo/1".to_string(),
                     title: "test title".to_string(),
                     body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
                     created_at: "datetime".to_string(),
-                    user: GHPullRequestOwner { login: "me".to_string() },
+                    user: Account { login: "me".to_string() },
+                    state: "open".to_string(),
+                    merged_at: None,
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
                 },
-                GHPullRequest {
-                    base: GHPullRequestBase { repo: GHRepo { full_name: "org/repo".to_string() } },
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
                     html_url: "https://url/org/repo/2".to_string(),
                     title: "test title".to_string(),
                     body: Some("test body".to_string()),
                     created_at: "datetime".to_string(),
-                    user: GHPullRequestOwner { login: "me".to_string() },
+                    user: Account { login: "me".to_string() },
+                    state: "open".to_string(),
+                    merged_at: None,
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
                 },
-                GHPullRequest {
-                    base: GHPullRequestBase { repo: GHRepo { full_name: "org/repo".to_string() } },
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
                     html_url: "https://url/org/repo/3".to_string(),
                     title: "test title".to_string(),
                     body: Some("test body".to_string()),
                     created_at: "datetime".to_string(),
-                    user: GHPullRequestOwner { login: "me".to_string() },
+                    user: Account { login: "me".to_string() },
+                    state: "open".to_string(),
+                    merged_at: None,
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
                 },
             ])
         };
 
-        let results = sync_comments("org/repo", "", &gh_client, &jira_client).unwrap();
+        let results = sync_comments("org/repo", "", &review_client, &jira_client).unwrap();
 
         assert_eq!(results, vec!["Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.".to_string(), "PR https://url/org/repo/2 does not contain a Jira ticket!".to_string(), "PR https://url/org/repo/3 does not contain a Jira ticket!".to_string()]);
     }
@@ -123,29 +701,39 @@ mod test {
                 total: 2,
                 comments: vec![
                     JiraComment {
+                        id: String::new(),
                         rendered_body: "asdfageta https://url/org/repo/1 asdadf".to_string()
                     },
                     JiraComment {
+                        id: String::new(),
                         rendered_body: "aeradadf asafsd asd ".to_string()
                     },
                 ],
-            })
+            }),
+            links: Box::new(Vec::new()),
         };
 
-        let gh_client = MockGithubClient {
+        let review_client = MockReviewClient {
             data: Box::new(vec![
-                GHPullRequest {
-                    base: GHPullRequestBase { repo: GHRepo { full_name: "org/repo".to_string() } },
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
                     html_url: "https://url/org/repo/1".to_string(),
                     title: "test title".to_string(),
                     body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
                     created_at: "datetime".to_string(),
-                    user: GHPullRequestOwner { login: "me".to_string() },
+                    user: Account { login: "me".to_string() },
+                    state: "open".to_string(),
+                    merged_at: None,
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
                 },
             ])
         };
 
-        let results = sync_comments("org/repo", "", &gh_client, &jira_client).unwrap();
+        let results = sync_comments("org/repo", "", &review_client, &jira_client).unwrap();
 
         assert_eq!(results, vec!["Jira ticket https://jira.domain/browse/A-1 already has comment for https://url/org/repo/1.".to_string()]);
     }
@@ -158,20 +746,23 @@ mod test {
                 total: 2,
                 comments: vec![
                     JiraComment {
+                        id: String::new(),
                         rendered_body: "asdfageta https://url/org/repo/1 asdadf".to_string()
                     },
                     JiraComment {
+                        id: String::new(),
                         rendered_body: "aeradadf asafsd asd ".to_string()
                     },
                 ],
-            })
+            }),
+            links: Box::new(Vec::new()),
         };
 
-        let gh_client = MockGithubClient {
+        let review_client = MockReviewClient {
             data: Box::new(Vec::new())
         };
 
-        let results = sync_comments("org/repo", "", &gh_client, &jira_client).unwrap();
+        let results = sync_comments("org/repo", "", &review_client, &jira_client).unwrap();
 
         assert_eq!(results, Vec::<String>::new());
     }
@@ -183,24 +774,486 @@ mod test {
             data: Box::new(JiraCommentResponse {
                 total: 0,
                 comments: Vec::new(),
-            })
+            }),
+            links: Box::new(Vec::new()),
+        };
+
+        let review_client = MockReviewClient {
+            data: Box::new(vec![
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+                    html_url: "https://url/org/repo/1".to_string(),
+                    title: "test title".to_string(),
+                    body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+                    created_at: "datetime".to_string(),
+                    user: Account { login: "me".to_string() },
+                    state: "open".to_string(),
+                    merged_at: None,
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
+                },
+            ])
+        };
+
+        let results = sync_comments("org/repo", "", &review_client, &jira_client).unwrap();
+
+        assert_eq!(results, vec!["Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.".to_string()]);
+    }
+
+    #[test]
+    fn adds_remote_links_on_prs() {
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse {
+                total: 0,
+                comments: Vec::new(),
+            }),
+            links: Box::new(Vec::new()),
+        };
+
+        let review_client = MockReviewClient {
+            data: Box::new(vec![
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+                    html_url: "https://url/org/repo/1".to_string(),
+                    title: "test title".to_string(),
+                    body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+                    created_at: "datetime".to_string(),
+                    user: Account { login: "me".to_string() },
+                    state: "open".to_string(),
+                    merged_at: None,
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
+                },
+            ])
+        };
+
+        let results = sync_pull_requests("org/repo", "", &SyncOptions { mode: SyncMode::Both, ..Default::default() }, &review_client, &jira_client).unwrap();
+
+        assert_eq!(results, vec!["Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.\nAdded Jira link on ticket https://jira.domain/browse/A-1 to https://url/org/repo/1.".to_string()]);
+    }
+
+    #[test]
+    fn updates_remote_link_status() {
+        let existing_link = |resolved: bool| JiraRemoteLink {
+            global_id: "https://url/org/repo/1".to_string(),
+            object: JiraRemoteLinkObject {
+                url: "https://url/org/repo/1".to_string(),
+                title: "test title".to_string(),
+                summary: None,
+                icon: None,
+                status: Some(JiraRemoteLinkStatus { resolved }),
+            },
+        };
+
+        let review_client = MockReviewClient {
+            data: Box::new(vec![
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+                    html_url: "https://url/org/repo/1".to_string(),
+                    title: "test title".to_string(),
+                    body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+                    created_at: "datetime".to_string(),
+                    user: Account { login: "me".to_string() },
+                    state: "closed".to_string(),
+                    merged_at: Some("datetime".to_string()),
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
+                },
+            ])
+        };
+
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(vec![existing_link(false)]),
+        };
+        let results = sync_pull_requests("org/repo", "", &SyncOptions { mode: SyncMode::RemoteLink, ..Default::default() }, &review_client, &jira_client).unwrap();
+        assert_eq!(results, vec!["Updated Jira link status on ticket https://jira.domain/browse/A-1 for https://url/org/repo/1.".to_string()]);
+
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(vec![existing_link(true)]),
+        };
+        let results = sync_pull_requests("org/repo", "", &SyncOptions { mode: SyncMode::RemoteLink, ..Default::default() }, &review_client, &jira_client).unwrap();
+        assert_eq!(results, vec!["Jira ticket https://jira.domain/browse/A-1 already has link for https://url/org/repo/1.".to_string()]);
+    }
+
+    #[test]
+    fn skips_unchanged_prs() {
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
+        };
+
+        let mut pr = ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+            html_url: "https://url/org/repo/1".to_string(),
+            title: "test title".to_string(),
+            body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        let state = Arc::new(StateStore::open_in_memory().unwrap());
+        let options = SyncOptions { mode: SyncMode::Both, state: Some(Arc::clone(&state)), ..Default::default() };
+        let review_client = MockReviewClient { data: Box::new(vec![pr.clone()]) };
+
+        let results = sync_pull_requests("org/repo", "", &options, &review_client, &jira_client).unwrap();
+        assert_eq!(results, vec!["Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.\nAdded Jira link on ticket https://jira.domain/browse/A-1 to https://url/org/repo/1.".to_string()]);
+        assert_eq!(state.get("https://url/org/repo/1", "A-1", RecordKind::Comment).unwrap().unwrap().jira_id, Some("10000".to_string()));
+
+        let results = sync_pull_requests("org/repo", "", &options, &review_client, &jira_client).unwrap();
+        assert_eq!(results, vec!["Jira ticket https://jira.domain/browse/A-1 comment for https://url/org/repo/1 is unchanged.\nJira ticket https://jira.domain/browse/A-1 link for https://url/org/repo/1 is unchanged.".to_string()]);
+
+        // Merging changes the link's status, so it's checked with Jira again
+        pr.merged_at = Some("datetime".to_string());
+        let review_client = MockReviewClient { data: Box::new(vec![pr]) };
+        let results = sync_pull_requests("org/repo", "", &SyncOptions { mode: SyncMode::RemoteLink, ..options }, &review_client, &jira_client).unwrap();
+        assert_eq!(results, vec!["Added Jira link on ticket https://jira.domain/browse/A-1 to https://url/org/repo/1.".to_string()]);
+    }
+
+    #[test]
+    fn updates_changed_comments() {
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse {
+                total: 1,
+                comments: vec![
+                    JiraComment {
+                        id: "10001".to_string(),
+                        rendered_body: "old title https://url/org/repo/1".to_string()
+                    },
+                ],
+            }),
+            links: Box::new(Vec::new()),
+        };
+
+        let review_client = MockReviewClient {
+            data: Box::new(vec![
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+                    html_url: "https://url/org/repo/1".to_string(),
+                    title: "new title".to_string(),
+                    body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+                    created_at: "datetime".to_string(),
+                    user: Account { login: "me".to_string() },
+                    state: "open".to_string(),
+                    merged_at: None,
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
+                },
+            ])
+        };
+
+        // The comment was posted for an earlier version of the PR
+        let state = Arc::new(StateStore::open_in_memory().unwrap());
+        state.record("https://url/org/repo/1", "A-1", RecordKind::Comment, Some("10001"), "old hash").unwrap();
+        let options = SyncOptions { state: Some(Arc::clone(&state)), ..Default::default() };
+
+        let results = sync_pull_requests("org/repo", "", &options, &review_client, &jira_client).unwrap();
+        assert_eq!(results, vec!["Updated Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.".to_string()]);
+        assert_ne!(state.get("https://url/org/repo/1", "A-1", RecordKind::Comment).unwrap().unwrap().content_hash, "old hash");
+    }
+
+    /// Review client whose user lookups always fail
+    struct UnreachableReviewClient;
+
+    impl ReviewClient for UnreachableReviewClient {
+        fn get_changes_for_repo(&self, _repo: &str, _filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+            Ok(Vec::new())
+        }
+
+        fn get_changes_updated_since(&self, _repo: &str, _since: Option<&str>) -> Result<UpdatedChanges, Error> {
+            Ok(UpdatedChanges::new(Vec::new(), |_| true))
+        }
+
+        fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+            Err(Error::from(format!("Unable to look up {}", login)))
+        }
+    }
+
+    #[test]
+    fn skips_unchanged_comments_without_user_lookups() {
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
+        };
+
+        let pr = ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+            html_url: "https://url/org/repo/1".to_string(),
+            title: "test title".to_string(),
+            body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        let state = Arc::new(StateStore::open_in_memory().unwrap());
+        let options = || SyncOptions {
+            users: UserDirectory::new(HashMap::new(), true),
+            state: Some(Arc::clone(&state)),
+            ..Default::default()
+        };
+
+        let sync = sync_pull_request(&MockReviewClient { data: Box::default() }, &jira_client, &pr, &options()).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::CommentAdded]);
+
+        let sync = sync_pull_request(&UnreachableReviewClient, &jira_client, &pr, &options()).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::CommentUnchanged]);
+    }
+
+    /// Review client which keeps the comments posted on its pull requests
+    struct CommentingReviewClient {
+        comments: Mutex<Vec<ChangeComment>>,
+        statuses: Mutex<Vec<CommitStatus>>,
+        checks: Mutex<Vec<CheckRun>>,
+    }
+
+    impl ReviewClient for CommentingReviewClient {
+        fn get_changes_for_repo(&self, _repo: &str, _filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+            Ok(Vec::new())
+        }
+
+        fn get_changes_updated_since(&self, _repo: &str, _since: Option<&str>) -> Result<UpdatedChanges, Error> {
+            Ok(UpdatedChanges::new(Vec::new(), |_| true))
+        }
+
+        fn get_user_email(&self, _login: &str) -> Result<Option<String>, Error> {
+            Ok(None)
+        }
+
+        fn get_change_comments(&self, _change: &ChangeRequest) -> Result<Vec<ChangeComment>, Error> {
+            Ok(self.comments.lock().unwrap().clone())
+        }
+
+        fn post_change_comment(&self, _change: &ChangeRequest, body: &str) -> Result<String, Error> {
+            let mut comments = self.comments.lock().unwrap();
+            let id = (comments.len() + 1).to_string();
+            comments.push(ChangeComment { id: id.clone(), body: body.to_string() });
+            Ok(id)
+        }
+
+        fn update_change_comment(&self, _change: &ChangeRequest, comment_id: &str, body: &str) -> Result<(), Error> {
+            let mut comments = self.comments.lock().unwrap();
+            let comment = comments.iter_mut().find(|comment| comment.id == comment_id).unwrap();
+            comment.body = body.to_string();
+            Ok(())
+        }
+
+        fn set_commit_status(&self, _change: &ChangeRequest, status: &CommitStatus) -> Result<(), Error> {
+            self.statuses.lock().unwrap().push(status.clone());
+            Ok(())
+        }
+
+        fn set_check_run(&self, _change: &ChangeRequest, check: &CheckRun) -> Result<(), Error> {
+            self.checks.lock().unwrap().push(check.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn posts_and_updates_pr_summary() {
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
+        };
+
+        let pr = ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+            html_url: "https://url/org/repo/1".to_string(),
+            title: "test title".to_string(),
+            body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        let review_client = CommentingReviewClient {
+            comments: Mutex::new(vec![ChangeComment { id: "1".to_string(), body: "LGTM".to_string() }]),
+            statuses: Mutex::new(Vec::new()),
+            checks: Mutex::new(Vec::new()),
+        };
+        let options = SyncOptions { mode: SyncMode::RemoteLink, pr_summary: true, ..Default::default() };
+
+        let sync = sync_pull_request(&review_client, &jira_client, &pr, &options).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::LinkAdded, SyncAction::SummaryAdded]);
+        assert!(review_client.comments.lock().unwrap()[1].body.contains("[A-1](https://jira.domain/browse/A-1): Mock ticket"));
+
+        let sync = sync_pull_request(&review_client, &jira_client, &pr, &options).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::LinkAdded, SyncAction::SummaryUnchanged]);
+
+        // The ticket changed since the summary was posted
+        review_client.comments.lock().unwrap()[1].body = format!("{}\nstale summary", SUMMARY_MARKER);
+        let sync = sync_pull_request(&review_client, &jira_client, &pr, &options).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::LinkAdded, SyncAction::SummaryUpdated]);
+        assert_eq!(review_client.comments.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reports_invalid_tickets_in_commit_status() {
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
+        };
+
+        let pr = ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+            html_url: "https://url/org/repo/1".to_string(),
+            title: "test title".to_string(),
+            body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: Some("abc123".to_string()) },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        let review_client = CommentingReviewClient { comments: Mutex::new(Vec::new()), statuses: Mutex::new(Vec::new()), checks: Mutex::new(Vec::new()) };
+
+        // A-1 isn't in an allowed project, so nothing is posted to it
+        let options = SyncOptions { mode: SyncMode::RemoteLink, ticket_projects: vec!["B".to_string()], commit_status: true, ..Default::default() };
+        let sync = sync_pull_request(&review_client, &jira_client, &pr, &options).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::TicketWrongProject]);
+
+        let options = SyncOptions { mode: SyncMode::RemoteLink, ticket_projects: vec!["A".to_string()], commit_status: true, ..Default::default() };
+        let sync = sync_pull_request(&review_client, &jira_client, &pr, &options).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::LinkAdded]);
+
+        let statuses = review_client.statuses.lock().unwrap();
+        assert!(!statuses[0].success);
+        assert_eq!(statuses[0].description, "Jira ticket A-1 is not in an allowed project");
+        assert!(statuses[1].success);
+        assert_eq!(statuses[1].target_url.as_deref(), Some("https://jira.domain/browse/A-1"));
+    }
+
+    #[test]
+    fn explains_failures_in_check_runs() {
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
+        };
+
+        let mut pr = ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+            html_url: "https://url/org/repo/1".to_string(),
+            title: "test title".to_string(),
+            body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: Some("abc123".to_string()) },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        let review_client = CommentingReviewClient { comments: Mutex::new(Vec::new()), statuses: Mutex::new(Vec::new()), checks: Mutex::new(Vec::new()) };
+
+        // The mock ticket is In Progress
+        let options = SyncOptions { mode: SyncMode::RemoteLink, ticket_statuses: vec!["Done".to_string()], check_run: true, ..Default::default() };
+        let sync = sync_pull_request(&review_client, &jira_client, &pr, &options).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::TicketWrongStatus]);
+
+        let options = SyncOptions { mode: SyncMode::RemoteLink, ticket_statuses: vec!["in progress".to_string()], check_run: true, ..Default::default() };
+        let sync = sync_pull_request(&review_client, &jira_client, &pr, &options).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::LinkAdded]);
+
+        pr.body = Some("test body".to_string());
+        let sync = sync_pull_request(&review_client, &jira_client, &pr, &options).unwrap();
+        assert_eq!(sync.actions, vec![SyncAction::NoTicket]);
+
+        let checks = review_client.checks.lock().unwrap();
+        assert!(!checks[0].success);
+        assert_eq!(checks[0].title, "Jira ticket A-1 is not in an allowed status");
+        assert!(checks[0].summary.ends_with("Tickets must be in one of these statuses: Done."));
+        assert!(checks[1].success);
+        assert_eq!(checks[1].details_url.as_deref(), Some("https://jira.domain/browse/A-1"));
+        assert!(!checks[2].success);
+        assert!(checks[2].summary.ends_with("Link the Jira ticket for this pull request in its description."));
+        assert!(review_client.statuses.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn syncs_with_async_clients() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
         };
 
-        let gh_client = MockGithubClient {
+        let review_client = MockReviewClient {
             data: Box::new(vec![
-                GHPullRequest {
-                    base: GHPullRequestBase { repo: GHRepo { full_name: "org/repo".to_string() } },
+                ChangeRequest {
+                    base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
                     html_url: "https://url/org/repo/1".to_string(),
                     title: "test title".to_string(),
                     body: Some("test body [A-1](https://jira.domain/asdf)".to_string()),
                     created_at: "datetime".to_string(),
-                    user: GHPullRequestOwner { login: "me".to_string() },
+                    user: Account { login: "me".to_string() },
+                    state: "open".to_string(),
+                    merged_at: None,
+                    updated_at: "datetime".to_string(),
+                    head: ChangeHead { branch: "feature".to_string(), sha: None },
+                    labels: Vec::new(),
+                    requested_reviewers: Vec::new(),
+                    provider: Provider::Github,
                 },
             ])
         };
 
-        let results = sync_comments("org/repo", "", &gh_client, &jira_client).unwrap();
+        // The future can be spawned onto a multithreaded runtime
+        let (review_client, jira_client) = (Blocking(&review_client), Blocking(&jira_client));
+        let sync = sync_comments_async("org/repo", "", &review_client, &jira_client);
+        assert_send(&sync);
 
+        let results = futures::executor::block_on(sync).unwrap();
         assert_eq!(results, vec!["Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.".to_string()]);
     }
 
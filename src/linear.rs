@@ -0,0 +1,362 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tokio::sync::Semaphore;
+
+use crate::credentials::{Credentials, LinearCredentials};
+use crate::error::Error;
+use crate::http;
+use crate::pool;
+use crate::tracker::{AsyncIssueTracker, CommentFormat, IssueLink, IssueTracker, TrackerComment};
+
+const LINEAR_API_URL: &str = "https://api.linear.app/graphql";
+
+/// Domain of Linear's issue links, which ticket links in pull request
+/// descriptions point to
+const LINEAR_DOMAIN: &str = "linear.app";
+
+const COMMENTS_QUERY: &str = "query($id: String!) { issue(id: $id) { comments(first: 250) { nodes { id body } } } }";
+const ATTACHMENTS_QUERY: &str = "query($id: String!) { issue(id: $id) { attachments(first: 250) { nodes { url title subtitle metadata } } } }";
+const USERS_QUERY: &str = "query($email: String!) { users(filter: { email: { eq: $email } }) { nodes { displayName } } }";
+const COMMENT_CREATE: &str = "mutation($input: CommentCreateInput!) { commentCreate(input: $input) { success comment { id } } }";
+const COMMENT_UPDATE: &str = "mutation($id: String!, $input: CommentUpdateInput!) { commentUpdate(id: $id, input: $input) { success } }";
+const ATTACHMENT_CREATE: &str = "mutation($input: AttachmentCreateInput!) { attachmentCreate(input: $input) { success } }";
+
+#[derive(Serialize)]
+struct GraphQLRequest<'a> {
+    query: &'a str,
+    variables: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct GraphQLResponse<T> {
+    data: Option<T>,
+
+    #[serde(default)]
+    errors: Vec<GraphQLError>,
+}
+
+#[derive(Deserialize)]
+struct GraphQLError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct IssueData<T> {
+    issue: T,
+}
+
+#[derive(Deserialize)]
+struct LNIssueComments {
+    comments: LNConnection<LNComment>,
+}
+
+#[derive(Deserialize)]
+struct LNIssueAttachments {
+    attachments: LNConnection<LNAttachment>,
+}
+
+#[derive(Deserialize)]
+struct LNConnection<T> {
+    nodes: Vec<T>,
+}
+
+#[derive(Deserialize)]
+pub struct LNComment {
+    pub id: String,
+    pub body: String,
+}
+
+/// An attachment on a Linear issue, which is how Linear links to pull
+/// requests. Linear keys attachments by URL, so creating an attachment with
+/// an existing URL updates it.
+#[derive(Deserialize)]
+pub struct LNAttachment {
+    pub url: String,
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub metadata: Option<LNAttachmentMetadata>,
+}
+
+impl LNAttachment {
+    pub fn into_link(self) -> IssueLink {
+        IssueLink {
+            url: self.url,
+            title: self.title,
+            summary: self.subtitle,
+            icon_url: None,
+            icon_title: None,
+            resolved: self.metadata.map(|metadata| metadata.resolved).unwrap_or(false),
+        }
+    }
+}
+
+/// Metadata autocomment stores on the attachments it creates
+#[derive(Deserialize, Default)]
+pub struct LNAttachmentMetadata {
+    #[serde(default)]
+    pub resolved: bool,
+}
+
+#[derive(Deserialize)]
+struct UsersData {
+    users: LNConnection<LNUser>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LNUser {
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CommentCreateData {
+    comment_create: CommentCreatePayload,
+}
+
+#[derive(Deserialize)]
+struct CommentCreatePayload {
+    comment: Option<LNCreatedComment>,
+}
+
+#[derive(Deserialize)]
+struct LNCreatedComment {
+    id: String,
+}
+
+/// Linear client which sends GraphQL requests with the async reqwest client,
+/// authenticating with a personal API key. Comments are written in Markdown.
+pub struct DefaultAsyncLinearClient {
+    client: Client,
+    creds: LinearCredentials,
+
+    /// Limits the requests sent to Linear at once
+    limit: Semaphore,
+}
+
+impl DefaultAsyncLinearClient {
+    /// `creds` are used for the host limit of Linear's API
+    pub fn new(linear: &LinearCredentials, creds: &Credentials) -> DefaultAsyncLinearClient {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let limit = Semaphore::new(creds.host_limit("api.linear.app").max(1));
+        DefaultAsyncLinearClient { client, creds: linear.clone(), limit }
+    }
+
+    /// Sends the query, returning its data. GraphQL errors are returned with
+    /// a successful status, so they're turned into an error here.
+    async fn query<T: DeserializeOwned>(&self, query: &str, variables: serde_json::Value) -> Result<T, Error> {
+        let url = self.creds.api_url.as_deref().unwrap_or(LINEAR_API_URL);
+        let request = self.client.post(url)
+            .header("Authorization", self.creds.api_key.as_str())
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&GraphQLRequest { query, variables })?);
+
+        let resp = http::send(&self.client, &self.limit, request).await?;
+        if !resp.is_success() {
+            return Err(resp.error());
+        }
+
+        let resp: GraphQLResponse<T> = resp.parse()?;
+        match resp.data {
+            Some(data) if resp.errors.is_empty() => Ok(data),
+            _ => {
+                let messages: Vec<String> = resp.errors.into_iter().map(|err| err.message).collect();
+                Err(Error::from(format!("Linear request failed: {}", messages.join(", "))))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncIssueTracker for DefaultAsyncLinearClient {
+    fn name(&self) -> &str {
+        "Linear"
+    }
+
+    fn get_domain(&self) -> &str {
+        LINEAR_DOMAIN
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        CommentFormat::Markdown
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        format!("https://{}/{}/issue/{}", LINEAR_DOMAIN, self.creds.workspace, ticket_id)
+    }
+
+    async fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        let data: IssueData<LNIssueComments> = self.query(COMMENTS_QUERY, json!({ "id": ticket_id })).await?;
+        Ok(data.issue.comments.nodes.into_iter()
+            .map(|comment| TrackerComment { id: comment.id, body: comment.body })
+            .collect())
+    }
+
+    async fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        let input = json!({ "issueId": ticket_id, "body": text });
+        let data: CommentCreateData = self.query(COMMENT_CREATE, json!({ "input": input })).await?;
+        data.comment_create.comment
+            .map(|comment| comment.id)
+            .ok_or(Error::from(format!("Linear didn't create a comment on {}", ticket_id)))
+    }
+
+    async fn update_comment(&self, _ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        let variables = json!({ "id": comment_id, "input": { "body": text } });
+        self.query::<serde_json::Value>(COMMENT_UPDATE, variables).await?;
+        Ok(())
+    }
+
+    async fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        let data: IssueData<LNIssueAttachments> = self.query(ATTACHMENTS_QUERY, json!({ "id": ticket_id })).await?;
+        Ok(data.issue.attachments.nodes.into_iter().map(LNAttachment::into_link).collect())
+    }
+
+    async fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        let input = json!({
+            "issueId": ticket_id,
+            "url": link.url,
+            "title": link.title,
+            "subtitle": link.summary,
+            "iconUrl": link.icon_url,
+            "metadata": { "resolved": link.resolved },
+        });
+        self.query::<serde_json::Value>(ATTACHMENT_CREATE, json!({ "input": input })).await?;
+        Ok(())
+    }
+
+    /// Finds the display name of the user, which Markdown comments mention
+    async fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        let data: UsersData = self.query(USERS_QUERY, json!({ "email": email })).await?;
+        Ok(data.users.nodes.into_iter().next().map(|user| user.display_name))
+    }
+}
+
+/// Blocking Linear client, which waits for `DefaultAsyncLinearClient`'s
+/// requests on a shared runtime. It can't be used from inside an async runtime.
+pub struct DefaultLinearClient {
+    inner: DefaultAsyncLinearClient,
+}
+
+impl DefaultLinearClient {
+    pub fn new(linear: &LinearCredentials, creds: &Credentials) -> DefaultLinearClient {
+        DefaultLinearClient { inner: DefaultAsyncLinearClient::new(linear, creds) }
+    }
+}
+
+impl IssueTracker for DefaultLinearClient {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn get_domain(&self) -> &str {
+        self.inner.get_domain()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        self.inner.comment_format()
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        self.inner.ticket_url(ticket_id)
+    }
+
+    fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        pool::block_on(self.inner.get_comments(ticket_id))
+    }
+
+    fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        pool::block_on(self.inner.post_comment(ticket_id, text))
+    }
+
+    fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        pool::block_on(self.inner.update_comment(ticket_id, comment_id, text))
+    }
+
+    fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        pool::block_on(self.inner.get_links(ticket_id))
+    }
+
+    fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        pool::block_on(self.inner.post_link(ticket_id, link))
+    }
+
+    fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.find_account_id(email))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use crate::credentials::{Credentials, LinearCredentials};
+    use crate::linear::DefaultLinearClient;
+    use crate::tracker::IssueTracker;
+
+    const COMMENTS: &str = include_str!("../tests/fixtures/linear_comments.json");
+
+    /// Serves `body` for a single request on a local port, sending back the
+    /// request's Authorization header and body
+    fn stub_server(body: &'static str) -> (String, mpsc::Receiver<(Option<String>, String)>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}/graphql", server.server_addr().to_ip().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut request = server.recv().unwrap();
+            let auth = request.headers().iter()
+                .find(|header| header.field.equiv("Authorization"))
+                .map(|header| header.value.to_string());
+            let mut content = String::new();
+            request.as_reader().read_to_string(&mut content).unwrap();
+            sender.send((auth, content)).unwrap();
+            request.respond(tiny_http::Response::from_string(body)).unwrap();
+        });
+
+        (addr, receiver)
+    }
+
+    fn client(api_url: String) -> DefaultLinearClient {
+        let linear = LinearCredentials { workspace: "acme".to_string(), api_key: "lin_api_key".to_string(), api_url: Some(api_url) };
+        DefaultLinearClient::new(&linear, &Credentials::default())
+    }
+
+    #[test]
+    fn fetches_comments_from_stub_server() {
+        let (addr, requests) = stub_server(COMMENTS);
+        let comments = client(addr).get_comments("ENG-42").unwrap();
+
+        let (auth, body) = requests.recv().unwrap();
+        assert_eq!(auth.as_deref(), Some("lin_api_key"));
+        assert!(body.contains("\"variables\":{\"id\":\"ENG-42\"}"));
+
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[1].id, "c2a0a6b4-6a8e-4d56-9d0f-2f1d3c1e7b21");
+        assert!(comments[1].body.contains("https://github.com/org/repo/pull/9"));
+    }
+
+    #[test]
+    fn returns_graphql_errors() {
+        let (addr, _requests) = stub_server("{\"data\":null,\"errors\":[{\"message\":\"Entity not found: Issue\"}]}");
+        let err = client(addr).get_comments("ENG-404").unwrap_err();
+
+        assert_eq!(err.to_string(), "Error occurred: Linear request failed: Entity not found: Issue");
+    }
+
+    #[test]
+    fn parses_linear_ticket_links() {
+        let client = client("http://localhost".to_string());
+
+        assert_eq!(client.parse_ticket("Fixes [ENG-42](https://linear.app/acme/issue/ENG-42/fix-login)"), Some("ENG-42".to_string()));
+        assert_eq!(client.ticket_url("ENG-42"), "https://linear.app/acme/issue/ENG-42");
+    }
+}
@@ -1,13 +1,64 @@
-use clap::{Parser, Subcommand};
-use autocomment::{sync_comments, Error, Credentials, DefaultGithubClient, DefaultJiraClient};
+use std::error::Error as _;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{ArgAction, Args, Parser, Subcommand};
+use tracing_subscriber::EnvFilter;
+use autocomment::{Error, Credentials, DefaultGithubClient, SyncMode, SyncOptions};
+use autocomment::action;
+use autocomment::error::ErrorKind;
+use autocomment::review::{self, Provider};
+use autocomment::jira::JiraFlavor;
+use autocomment::tracker::{self, Tracker};
+use autocomment::pool::DEFAULT_CONCURRENCY;
+use autocomment::report::{report_pull_requests, OutputFormat, SyncReport};
+use autocomment::server::WebhookServer;
+use autocomment::state::StateStore;
+use autocomment::template::CommentTemplate;
+use autocomment::users::UserDirectory;
+use autocomment::watch::{Watcher, Watermarks};
 
 #[derive(Parser)]
 #[command(name = "AutoComment")]
 #[command(about = "Adds comments to Jira tickets based on Github PR's")]
 #[command(version)]
+#[command(after_help = "Exit codes: 0 success, 1 failure, 2 some PR's failed to sync, 3 config error, 4 authentication error, 5 network error")]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Log more to stderr: -v for each action, -vv for requests, -vvv for
+    /// request and response bodies. RUST_LOG overrides this when it's set
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log format: text or json
+    #[arg(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format {}, expected text or json", s)),
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -15,12 +66,115 @@ enum Commands {
     /// Syncs Jira comments with Github PR's
     Sync {
         /// Full name of the repository to scan
-        #[arg(short, long)]
-        repo: String,
+        #[arg(short, long, required_unless_present = "event")]
+        repo: Option<String>,
 
         /// Filters to pass to Github when querying repos. Try state=open for open PR's
         #[arg(short, long)]
         filter: Option<String>,
+
+        /// What to create on Jira tickets: comment, link (remote issue link) or both.
+        /// Use state=all with link so merged and closed PR's update their links
+        #[arg(short, long, default_value = "comment")]
+        mode: SyncMode,
+
+        /// Number of PR's to process at once. Defaults to concurrency from the config file, or 4
+        #[arg(short, long)]
+        concurrency: Option<usize>,
+
+        /// How to write the results: text, json, ndjson, table, markdown or junit
+        #[arg(short, long, default_value = "text")]
+        output: OutputFormat,
+
+        /// Also post the ticket's summary, status, assignee, priority and fix versions as a
+        /// comment on the PR, edited in place whenever the ticket changes. Only Github PR's
+        /// and Jira tickets are supported
+        #[arg(long)]
+        pr_summary: bool,
+
+        #[command(flatten)]
+        checks: TicketChecks,
+
+        /// Where the repository is hosted: github, gitlab, bitbucket, bitbucket-server or gitea.
+        /// Defaults to the repo's provider in the config file, or github
+        #[arg(short, long)]
+        provider: Option<Provider>,
+
+        /// Sync only the PR in a Github pull_request event payload, like $GITHUB_EVENT_PATH in
+        /// Github Actions, instead of listing the repo's PR's. Credentials are also read from
+        /// AUTOCOMMENT_* environment variables and GITHUB_TOKEN, and a job summary is written
+        /// to $GITHUB_STEP_SUMMARY when it's set
+        #[arg(long, conflicts_with_all = ["repo", "filter", "provider"])]
+        event: Option<PathBuf>,
+    },
+
+    /// Runs an HTTP server which syncs pull requests from Github webhook deliveries.
+    /// Deliveries are received at /webhook, /health reports whether the server is up and
+    /// /metrics has Prometheus metrics
+    Serve {
+        /// Address to listen on
+        #[arg(short, long, default_value = "0.0.0.0:8080")]
+        addr: String,
+
+        /// Secret used to verify webhook signatures. Defaults to webhook_secret from the config file
+        #[arg(short, long)]
+        secret: Option<String>,
+
+        /// What to create on Jira tickets: comment, link (remote issue link) or both
+        #[arg(short, long, default_value = "comment")]
+        mode: SyncMode,
+
+        /// Also post the ticket's summary, status, assignee, priority and fix versions as a
+        /// comment on the PR, edited in place whenever the ticket changes. Only Github PR's
+        /// and Jira tickets are supported
+        #[arg(long)]
+        pr_summary: bool,
+
+        #[command(flatten)]
+        checks: TicketChecks,
+    },
+
+    /// Syncs repos on an interval, only fetching PR's updated since the last sync
+    Watch {
+        /// Full names of the repositories to watch. Defaults to the repos in the config file
+        #[arg(short, long)]
+        repo: Vec<String>,
+
+        /// Seconds between the start of each sync
+        #[arg(short, long, default_value_t = 300)]
+        interval: u64,
+
+        /// What to create on Jira tickets: comment, link (remote issue link) or both
+        #[arg(short, long, default_value = "comment")]
+        mode: SyncMode,
+
+        /// Number of PR's to process at once. Defaults to concurrency from the config file, or 4
+        #[arg(short, long)]
+        concurrency: Option<usize>,
+
+        /// Address to serve Prometheus metrics on, at /metrics. Metrics aren't served without it
+        #[arg(long)]
+        metrics_addr: Option<String>,
+
+        /// Also post the ticket's summary, status, assignee, priority and fix versions as a
+        /// comment on the PR, edited in place whenever the ticket changes. Only Github PR's
+        /// and Jira tickets are supported
+        #[arg(long)]
+        pr_summary: bool,
+
+        #[command(flatten)]
+        checks: TicketChecks,
+
+        /// Where the repositories are hosted: github, gitlab, bitbucket, bitbucket-server or gitea.
+        /// Defaults to the repos' provider in the config file, which must be the same for every repo
+        #[arg(short, long)]
+        provider: Option<Provider>,
+    },
+
+    /// Shows or changes the record of what has been synced to Jira
+    State {
+        #[command(subcommand)]
+        command: StateCommands,
     },
 
     /// Updates Github or Jira credentials
@@ -37,6 +191,10 @@ enum Commands {
         #[arg(long)]
         jira_domain: Option<String>,
 
+        /// Jira deployment, either cloud or server
+        #[arg(long)]
+        jira_flavor: Option<JiraFlavor>,
+
         /// Github User
         #[arg(long)]
         github_user: Option<String>,
@@ -48,66 +206,362 @@ enum Commands {
         /// Github Domain
         #[arg(long)]
         github_domain: Option<String>,
+
+        /// Issue tracker to sync to: jira, linear, youtrack, redmine or github-issues
+        #[arg(long)]
+        tracker: Option<Tracker>,
+
+        /// Find Jira accounts to mention by searching for Github users' public emails
+        #[arg(long)]
+        resolve_users_by_email: Option<bool>,
+    },
+}
+
+/// Checks on the tickets referenced by PR's
+#[derive(Args)]
+struct TicketChecks {
+    /// Check that referenced tickets exist, are visible to the configured user and are in
+    /// one of the ticket_projects and ticket_statuses from the config file. Broken
+    /// references aren't synced
+    #[arg(long)]
+    validate: bool,
+
+    /// Set a commit status on each PR's head reporting whether it references a valid
+    /// ticket, so a ticket can be required before merging. Implies --validate. Only
+    /// Github PR's are supported
+    #[arg(long)]
+    commit_status: bool,
+
+    /// Create or update a "Jira ticket" check run on each PR's head, failing with an
+    /// explanation when it doesn't reference a valid ticket. Implies --validate. Needs a
+    /// Github App installation token as github_pass
+    #[arg(long)]
+    check_run: bool,
+}
+
+#[derive(Subcommand)]
+enum StateCommands {
+    /// Lists the comments and links synced for each PR
+    List,
+
+    /// Forgets what was synced for a PR, so the next sync checks Jira for it again
+    Forget {
+        /// URL of the pull request
+        pr_url: String,
     },
+
+    /// Prints everything synced as JSON
+    Export,
+}
+
+/// Builds the sync options shared by each command which syncs pull requests
+fn sync_options(creds: &Credentials, mode: SyncMode, template: CommentTemplate, concurrency: Option<usize>, pr_summary: bool, checks: &TicketChecks) -> Result<SyncOptions, Error> {
+    Ok(SyncOptions {
+        mode,
+        template,
+        users: UserDirectory::new(creds.users.clone(), creds.resolve_users_by_email),
+        state: Some(Arc::new(StateStore::open(&StateStore::default_path())?)),
+        concurrency: concurrency.or(creds.concurrency).unwrap_or(DEFAULT_CONCURRENCY),
+        pr_summary,
+        validate_tickets: checks.validate,
+        ticket_projects: creds.ticket_projects.clone(),
+        ticket_statuses: creds.ticket_statuses.clone(),
+        commit_status: checks.commit_status,
+        check_run: checks.check_run,
+    })
 }
 
-fn main() {
+/// Process exit codes
+const EXIT_SUCCESS: u8 = 0;
+const EXIT_FAILURE: u8 = 1;
+const EXIT_PARTIAL: u8 = 2;
+const EXIT_CONFIG: u8 = 3;
+const EXIT_AUTH: u8 = 4;
+const EXIT_NETWORK: u8 = 5;
+
+fn exit_code(kind: ErrorKind) -> u8 {
+    match kind {
+        ErrorKind::Config => EXIT_CONFIG,
+        ErrorKind::Auth => EXIT_AUTH,
+        ErrorKind::Network => EXIT_NETWORK,
+        ErrorKind::Other => EXIT_FAILURE,
+    }
+}
+
+/// Writes the error and each of its causes to stderr, returning the exit
+/// code for the error
+fn report_error(context: &str, err: &Error) -> u8 {
+    eprintln!("{}: {}", context, err);
+
+    let mut source = err.source();
+    while let Some(cause) = source {
+        eprintln!("  caused by: {}", cause);
+        source = cause.source();
+    }
+
+    exit_code(err.kind())
+}
+
+/// Loads the config file, explaining what's wrong when it can't be used
+fn load_credentials() -> Result<Credentials, u8> {
+    let path = Credentials::config_file();
+
+    Credentials::from_env().map_err(|err| match err {
+        Error::FsError(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("No config file found at {}, create one with `autocomment credentials`", path.display());
+            EXIT_CONFIG
+        }
+        err => report_error(&format!("Unable to read config file {}", path.display()), &err),
+    })
+}
+
+/// Loads credentials for a Github Actions step, from environment variables
+/// applied over the config file when there is one
+fn load_action_credentials() -> Result<Credentials, u8> {
+    let path = Credentials::config_file();
+
+    let creds = match Credentials::from_env() {
+        Ok(creds) => creds,
+        Err(Error::FsError(err)) if err.kind() == std::io::ErrorKind::NotFound => Credentials::default(),
+        Err(err) => return Err(report_error(&format!("Unable to read config file {}", path.display()), &err)),
+    };
+
+    action::credentials_from_vars(creds, |name| std::env::var(name).ok())
+        .map_err(|err| report_error("Invalid credentials in environment variables", &err))
+}
+
+/// The exit code for a sync: partial when only some pull requests failed,
+/// otherwise based on why they failed
+fn sync_exit_code(reports: &[SyncReport]) -> u8 {
+    let failed: Vec<&SyncReport> = reports.iter().filter(|report| report.is_error()).collect();
+
+    match failed.first() {
+        None => EXIT_SUCCESS,
+        Some(_) if failed.len() < reports.len() => EXIT_PARTIAL,
+        Some(report) => report.error_kind.map(exit_code).unwrap_or(EXIT_FAILURE),
+    }
+}
+
+fn main() -> ExitCode {
     let cli: Cli = Cli::parse();
+    init_logging(&cli);
 
-    if let Some(cmd) = &cli.command {
-        match cmd {
-            Commands::Sync { repo, filter } => {
-                if let Ok(creds) = Credentials::from_env() {
-                    let mut filters = String::new();
+    let code = match &cli.command {
+        Some(cmd) => run(cmd).unwrap_or_else(|code| code),
+        None => EXIT_SUCCESS,
+    };
 
-                    if let Some(querystring) = filter {
-                        filters = "?".to_owned() + querystring;
-                    }
+    ExitCode::from(code)
+}
 
-                    let gh_client = DefaultGithubClient::new(&creds);
-                    let jira_client = DefaultJiraClient::new(&creds);
+/// Logs to stderr so output written to stdout stays parseable
+fn init_logging(cli: &Cli) {
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => "error",
+        (false, 0) => "warn",
+        (false, 1) => "info",
+        (false, 2) => "debug",
+        _ => "trace",
+    };
+    let default = if cli.quiet { "error" } else { "warn" };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("{},autocomment={}", default, level)));
 
-                    let result = sync_comments(repo, &filters, &gh_client, &jira_client);
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
 
-                    match result {
-                        Ok(msgs) => msgs.iter().for_each(|msg| println!("{}", msg)),
-                        Err(err) => match err {
-                            Error::AutocommentError(err) => println!("Unable to save credentials: {}", err),
-                            Error::SerdeYamlError(err) => println!("Error occurred while saving config file: {}", err.to_string()),
-                            Error::FsError(err) => println!("Error occurred while reading files: {}", err.to_string()),
-                            Error::ReqwestError(err) => println!("Network error occurred: {}", err.to_string()),
-                            Error::SerdeJsonError(err) => println!("Unable to read response: {}", err.to_string()),
-                        }
+    match cli.log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+fn run(cmd: &Commands) -> Result<u8, u8> {
+    match cmd {
+        Commands::Sync { repo, filter, mode, concurrency, output, pr_summary, checks, provider, event } => {
+            let creds = match event {
+                Some(_) => load_action_credentials()?,
+                None => load_credentials()?,
+            };
+
+            // An event payload already describes its PR, so the repo isn't listed
+            let event_pr = match event {
+                Some(path) => Some(action::read_event(path)
+                    .map_err(|err| report_error(&format!("Unable to read event {}", path.display()), &err))?),
+                None => None,
+            };
+            let repo = match &event_pr {
+                Some(pr) => pr.base.repo.full_name.clone(),
+                None => repo.clone().unwrap_or_default(),
+            };
+
+            let mut filters = String::new();
+
+            if let Some(querystring) = filter {
+                filters = "?".to_owned() + querystring;
+            }
+
+            let provider = match event_pr {
+                Some(_) => Provider::Github,
+                None => provider.unwrap_or(creds.provider_for_repo(&repo)),
+            };
+            let review_client = review::default_client(&creds, provider)
+                .map_err(|err| report_error("Unable to prepare sync", &err))?;
+            let tracker = tracker::default_client(&creds)
+                .map_err(|err| report_error("Unable to prepare sync", &err))?;
+
+            let options = creds.template_for_repo(&repo)
+                .and_then(|template| sync_options(&creds, *mode, template, *concurrency, *pr_summary, checks))
+                .map_err(|err| report_error("Unable to prepare sync", &err))?;
+
+            let prs = match event_pr {
+                Some(pr) => vec![pr],
+                None => review_client.get_changes_for_repo(&repo, &filters)
+                    .map_err(|err| report_error(&format!("Unable to get {}s for {}", provider.change_name().to_lowercase(), repo), &err))?,
+            };
+
+            let reports = report_pull_requests(review_client.as_ref(), tracker.as_ref(), &prs, &options);
+            let rendered = output.render(&reports)
+                .map_err(|err| report_error("Unable to write results", &err))?;
+            print!("{}", rendered);
+
+            if let Some(summary_path) = event.as_ref().and(std::env::var_os("GITHUB_STEP_SUMMARY")) {
+                OutputFormat::Markdown.render(&reports)
+                    .and_then(|summary| action::write_step_summary(summary_path.as_ref(), &summary))
+                    .map_err(|err| report_error("Unable to write job summary", &err))?;
+            }
+
+            for report in reports.iter().filter(|report| report.is_error()) {
+                eprintln!("Unable to sync {}: {}", report.pr_url, report.error.clone().unwrap_or_default());
+            }
+
+            Ok(sync_exit_code(&reports))
+        }
+        Commands::Serve { addr, secret, mode, pr_summary, checks } => {
+            let creds = load_credentials()?;
+
+            let review_client = DefaultGithubClient::new(&creds);
+            let tracker = tracker::default_client(&creds)
+                .map_err(|err| report_error("Unable to prepare sync", &err))?;
+
+            let secret = secret.as_ref().or(creds.webhook_secret.as_ref()).ok_or_else(|| {
+                eprintln!("A webhook secret is required, set it with --secret or webhook_secret in the config file");
+                EXIT_CONFIG
+            })?;
+
+            let options = sync_options(&creds, *mode, Default::default(), None, *pr_summary, checks)
+                .map_err(|err| report_error("Unable to prepare sync", &err))?;
+
+            let server = WebhookServer::new(secret, &creds, options, &review_client, tracker.as_ref());
+            println!("Listening for webhooks on {}", addr);
+
+            server.serve(addr).map_err(|err| report_error("Server error occurred", &err))?;
+            Ok(EXIT_SUCCESS)
+        }
+        Commands::Watch { repo, interval, mode, concurrency, metrics_addr, pr_summary, checks, provider } => {
+            let creds = load_credentials()?;
+
+            if let Some(addr) = metrics_addr {
+                autocomment::metrics::serve(addr).map_err(|err| report_error("Unable to serve metrics", &err))?;
+                println!("Serving metrics on {}", addr);
+            }
+
+            let repos: Vec<String> = if repo.is_empty() { creds.repos.keys().cloned().collect() } else { repo.clone() };
+
+            // A watcher syncs repos from a single provider
+            let provider = match provider {
+                Some(provider) => *provider,
+                None => {
+                    let mut providers: Vec<Provider> = repos.iter().map(|repo| creds.provider_for_repo(repo)).collect();
+                    providers.dedup();
+                    if providers.len() > 1 {
+                        eprintln!("The repos are hosted on different providers, run a watcher for each provider with --provider and --repo");
+                        return Err(EXIT_CONFIG);
                     }
+                    providers.first().copied().unwrap_or_default()
                 }
-            }
-            Commands::Credentials {
-                jira_user,
-                jira_pass,
-                jira_domain,
-                github_user,
-                github_pass,
-                github_domain,
-            } => {
-                // TODO password protect the credentials
-                let mut creds = Credentials::from_env().unwrap_or(Credentials::default());
-
-                if let Some(cred) = jira_user { creds.jira_user = cred.clone(); }
-                if let Some(cred) = jira_pass { creds.jira_pass = cred.clone(); }
-                if let Some(cred) = jira_domain { creds.jira_domain = cred.clone(); }
-                if let Some(cred) = github_user { creds.github_user = cred.clone(); }
-                if let Some(cred) = github_pass { creds.github_pass = cred.clone(); }
-                if let Some(cred) = github_domain { creds.github_domain = cred.clone(); }
-
-                if let Some(err) = creds.save().err() {
-                    match err {
-                        Error::AutocommentError(err) => println!("Unable to save credentials: {}", err),
-                        Error::SerdeYamlError(err) => println!("Error occurred while saving config file: {}", err.to_string()),
-                        Error::FsError(err) => println!("Error occurred while reading files: {}", err.to_string()),
-                        _ => println!("Unknown error occurred!")
+            };
+
+            let review_client = review::default_client(&creds, provider)
+                .map_err(|err| report_error("Unable to prepare sync", &err))?;
+            let tracker = tracker::default_client(&creds)
+                .map_err(|err| report_error("Unable to prepare sync", &err))?;
+
+            let result = sync_options(&creds, *mode, Default::default(), *concurrency, *pr_summary, checks).and_then(|options| {
+                let watermarks = Watermarks::load(Watermarks::default_path())?;
+                let mut watcher = Watcher::new(repos, Duration::from_secs(*interval), &creds, options, watermarks, review_client.as_ref(), tracker.as_ref());
+                watcher.run(|repo, result| match result {
+                    Ok(results) => results.iter().for_each(|result| match result {
+                        Ok(msg) => println!("{}", msg),
+                        Err(err) => {
+                            report_error(&format!("Unable to sync a PR in {}", repo), err);
+                        }
+                    }),
+                    Err(err) => {
+                        report_error(&format!("Unable to sync {}", repo), err);
+                    }
+                })
+            });
+
+            result.map_err(|err| report_error("Error occurred while watching repos", &err))?;
+            Ok(EXIT_SUCCESS)
+        }
+        Commands::State { command } => {
+            let result = StateStore::open(&StateStore::default_path()).and_then(|state| match command {
+                StateCommands::List => {
+                    for record in state.list()? {
+                        println!("{} {:?} {} {} {}", record.last_synced_at, record.kind, record.ticket_key, record.pr_url, record.jira_id.unwrap_or_default());
                     }
+                    Ok(())
                 }
-            }
+                StateCommands::Forget { pr_url } => {
+                    println!("Forgot {} synced records for {}", state.forget(pr_url)?, pr_url);
+                    Ok(())
+                }
+                StateCommands::Export => {
+                    println!("{}", serde_json::to_string_pretty(&state.list()?)?);
+                    Ok(())
+                }
+            });
+
+            result.map_err(|err| report_error("Error occurred while reading sync state", &err))?;
+            Ok(EXIT_SUCCESS)
+        }
+        Commands::Credentials {
+            jira_user,
+            jira_pass,
+            jira_domain,
+            jira_flavor,
+            github_user,
+            github_pass,
+            github_domain,
+            tracker,
+            resolve_users_by_email,
+        } => {
+            // TODO password protect the credentials
+            let mut creds = match Credentials::from_env() {
+                Ok(creds) => creds,
+                Err(Error::FsError(err)) if err.kind() == std::io::ErrorKind::NotFound => Credentials::default(),
+                // Saving would overwrite the rest of an invalid config file
+                Err(err) => return Err(report_error(&format!("Unable to read config file {}", Credentials::config_file().display()), &err)),
+            };
+
+            if let Some(cred) = jira_user { creds.jira_user = cred.clone(); }
+            if let Some(cred) = jira_pass { creds.jira_pass = cred.clone(); }
+            if let Some(cred) = jira_domain { creds.jira_domain = cred.clone(); }
+            if let Some(cred) = jira_flavor { creds.jira_flavor = *cred; }
+            if let Some(cred) = github_user { creds.github_user = cred.clone(); }
+            if let Some(cred) = github_pass { creds.github_pass = cred.clone(); }
+            if let Some(cred) = github_domain { creds.github_domain = cred.clone(); }
+            if let Some(tracker) = tracker { creds.tracker = *tracker; }
+            if let Some(resolve) = resolve_users_by_email { creds.resolve_users_by_email = *resolve; }
+
+            creds.save().map_err(|err| {
+                report_error("Unable to save credentials", &err);
+                EXIT_CONFIG
+            })?;
+            Ok(EXIT_SUCCESS)
         }
     }
 }
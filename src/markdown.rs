@@ -0,0 +1,495 @@
+use std::ops::Range;
+
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+
+use crate::adf::{Mark, Node, TaskState};
+
+/// Converts Github flavored markdown into ADF block nodes. When
+/// `max_blocks` is set, only that many top level blocks are kept.
+pub fn markdown_to_adf(markdown: &str, max_blocks: Option<usize>) -> Vec<Node> {
+    let mut converter = Converter::default();
+    for event in Parser::new_ext(markdown, options()) {
+        converter.event(event);
+    }
+
+    let mut blocks = converter.finish();
+    if let Some(max_blocks) = max_blocks {
+        blocks.truncate(max_blocks);
+    }
+    blocks
+}
+
+/// Splits Markdown into its top-level blocks as written, so a description
+/// can be cut short without splitting a code block or list
+pub fn markdown_blocks(markdown: &str) -> Vec<&str> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut depth = 0;
+    // HTML blocks are an event per line rather than a start and end tag
+    let mut in_html = false;
+
+    for (event, range) in Parser::new_ext(markdown, options()).into_offset_iter() {
+        match event {
+            Event::Start(_) => {
+                if depth == 0 {
+                    ranges.push(range);
+                }
+                depth += 1;
+                in_html = false;
+            }
+            Event::End(_) => depth -= 1,
+            Event::Html(_) if depth == 0 && in_html => {
+                if let Some(last) = ranges.last_mut() {
+                    last.end = range.end;
+                }
+            }
+            _ if depth == 0 => {
+                in_html = matches!(event, Event::Html(_));
+                ranges.push(range);
+            }
+            _ => {}
+        }
+    }
+
+    ranges.into_iter()
+        .map(|range| markdown[range].trim())
+        .filter(|block| !block.is_empty())
+        .collect()
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH
+}
+
+struct Frame {
+    node: Node,
+
+    /// Paragraphs opened to hold the inline content of tight list items,
+    /// table cells and loose text, which ADF doesn't allow outside a block
+    implicit: bool,
+
+    /// The checked state of list items which start with a task list marker
+    task: Option<bool>,
+}
+
+#[derive(Default)]
+struct Converter {
+    blocks: Vec<Node>,
+    stack: Vec<Frame>,
+    marks: Vec<Mark>,
+    in_table_head: bool,
+    local_ids: usize,
+
+    /// Blocks ADF doesn't allow where they were written, which are moved up
+    /// to follow the top level block they were in
+    hoisted: Vec<Node>,
+}
+
+impl Converter {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                // The code mark can only be combined with links
+                let mut marks: Vec<Mark> = self.marks.iter()
+                    .filter(|mark| matches!(mark, Mark::Link { .. }))
+                    .cloned()
+                    .collect();
+                marks.push(Mark::Code);
+                self.inline(Node::Text { text: code.to_string(), marks });
+            }
+            Event::Html(html) => self.text(&html),
+            Event::FootnoteReference(label) => self.text(&format!("[^{}]", label)),
+            Event::SoftBreak => self.text(" "),
+            Event::HardBreak => self.inline(Node::hard_break()),
+            Event::Rule => {
+                self.close_implicit();
+                self.push_block(Node::rule());
+            }
+            Event::TaskListMarker(checked) => {
+                if let Some(frame) = self.stack.iter_mut().rev().find(|frame| matches!(frame.node, Node::ListItem { .. })) {
+                    frame.task = Some(checked);
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        let node = match tag {
+            Tag::Paragraph => Node::paragraph(Vec::new()),
+            Tag::Heading(level, _, _) => Node::heading(level as u8, Vec::new()),
+            Tag::BlockQuote => Node::blockquote(Vec::new()),
+            Tag::CodeBlock(CodeBlockKind::Fenced(info)) => Node::code_block(info.split_whitespace().next(), ""),
+            Tag::CodeBlock(CodeBlockKind::Indented) => Node::code_block(None, ""),
+            Tag::List(Some(order)) => Node::ordered_list(order, Vec::new()),
+            Tag::List(None) => Node::bullet_list(Vec::new()),
+            Tag::Item => Node::list_item(Vec::new()),
+            Tag::Table(_) => Node::table(Vec::new()),
+            Tag::TableHead => {
+                self.in_table_head = true;
+                Node::table_row(Vec::new())
+            }
+            Tag::TableRow => Node::table_row(Vec::new()),
+            Tag::TableCell if self.in_table_head => Node::table_header(Vec::new()),
+            Tag::TableCell => Node::table_cell(Vec::new()),
+            Tag::Emphasis => return self.marks.push(Mark::Em),
+            Tag::Strong => return self.marks.push(Mark::Strong),
+            Tag::Strikethrough => return self.marks.push(Mark::Strike),
+            Tag::Link(_, url, _) | Tag::Image(_, url, _) => return self.marks.push(Mark::link(&url)),
+            // Footnote content is kept inline with the rest of the document
+            Tag::FootnoteDefinition(_) => return,
+        };
+
+        self.close_implicit();
+        self.stack.push(Frame { node, implicit: false, task: None });
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..) => {
+                self.marks.pop();
+                return;
+            }
+            Tag::FootnoteDefinition(_) => return,
+            Tag::TableHead => self.in_table_head = false,
+            _ => {}
+        }
+
+        self.close_implicit();
+        if let Some(frame) = self.stack.pop() {
+            for node in self.complete(frame) {
+                self.push_block(node);
+            }
+            if self.stack.is_empty() {
+                self.blocks.append(&mut self.hoisted);
+            }
+        }
+    }
+
+    /// Fixes up nodes whose ADF representation depends on their content.
+    /// Empty list items and quotes are dropped, and task items are followed
+    /// by their nested task lists.
+    fn complete(&mut self, frame: Frame) -> Vec<Node> {
+        let mut node = frame.node;
+
+        if let (Node::ListItem { .. }, Some(checked)) = (&node, frame.task) {
+            return self.task_item(node, checked);
+        }
+
+        match &mut node {
+            Node::CodeBlock { content, .. } => {
+                if let Some(Node::Text { text, .. }) = content.last_mut() {
+                    if text.ends_with('\n') {
+                        text.pop();
+                    }
+                }
+                content.retain(|text| !matches!(text, Node::Text { text, .. } if text.is_empty()));
+            }
+            Node::ListItem { content } => {
+                self.normalize_nested(content);
+                if content.is_empty() {
+                    return Vec::new();
+                }
+
+                // List items can't start with a nested list
+                if !matches!(content.first(), Some(Node::Paragraph { .. } | Node::CodeBlock { .. })) {
+                    content.insert(0, Node::paragraph(Vec::new()));
+                }
+            }
+            Node::Blockquote { content } => {
+                self.normalize_nested(content);
+                if content.is_empty() {
+                    return Vec::new();
+                }
+            }
+            Node::BulletList { content } | Node::OrderedList { content, .. } => {
+                if content.is_empty() {
+                    return Vec::new();
+                }
+
+                let tasks = content.iter().filter(|item| matches!(item, Node::TaskItem { .. } | Node::TaskList { .. })).count();
+                if tasks == content.len() {
+                    let local_id = self.local_id();
+                    node = Node::task_list(&local_id, std::mem::take(content));
+                } else if tasks > 0 {
+                    *content = untask_items(std::mem::take(content));
+                }
+            }
+            Node::TableHeader { content } | Node::TableCell { content } if content.is_empty() => {
+                content.push(Node::paragraph(Vec::new()));
+            }
+            _ => {}
+        }
+
+        vec![node]
+    }
+
+    /// Rewrites the children of a list item or quote into the blocks ADF
+    /// allows there. Headings become bold paragraphs, nested quotes are
+    /// flattened, and tables, task lists and rules are moved up.
+    fn normalize_nested(&mut self, content: &mut Vec<Node>) {
+        for child in std::mem::take(content) {
+            match child {
+                Node::Heading { content: inline, .. } => content.push(Node::paragraph(bold(inline))),
+                Node::Blockquote { content: quoted } => content.extend(quoted),
+                Node::Table { .. } | Node::TaskList { .. } | Node::Rule => self.hoisted.push(child),
+                child => content.push(child),
+            }
+        }
+    }
+
+    /// Task items hold inline content directly, so the paragraphs of the
+    /// list item are flattened into a single line. Nested task lists follow
+    /// the item, and other blocks are moved up.
+    fn task_item(&mut self, item: Node, checked: bool) -> Vec<Node> {
+        let children = match item {
+            Node::ListItem { content } => content,
+            item => return vec![item],
+        };
+
+        let mut content = Vec::new();
+        let mut nested = Vec::new();
+        for child in children {
+            let inline = match child {
+                Node::Paragraph { content } => content,
+                Node::Heading { content, .. } => bold(content),
+                Node::TaskList { .. } => {
+                    nested.push(child);
+                    continue;
+                }
+                child => {
+                    self.hoisted.push(child);
+                    continue;
+                }
+            };
+
+            if !content.is_empty() {
+                content.push(Node::hard_break());
+            }
+            content.extend(inline);
+        }
+
+        let state = if checked { TaskState::Done } else { TaskState::Todo };
+        let mut nodes = vec![Node::task_item(&self.local_id(), state, content)];
+        nodes.extend(nested);
+        nodes
+    }
+
+    fn local_id(&mut self) -> String {
+        self.local_ids += 1;
+        format!("autocomment-{}", self.local_ids)
+    }
+
+    fn text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        if let Some(Frame { node: Node::CodeBlock { content, .. }, .. }) = self.stack.last_mut() {
+            return content.push(Node::text(text));
+        }
+
+        self.inline(Node::Text { text: text.to_string(), marks: self.marks.clone() });
+    }
+
+    fn inline(&mut self, node: Node) {
+        let accepts_inline = matches!(self.stack.last(), Some(Frame { node: Node::Paragraph { .. } | Node::Heading { .. }, .. }));
+
+        if !accepts_inline {
+            self.stack.push(Frame {
+                node: Node::paragraph(Vec::new()),
+                implicit: true,
+                task: None,
+            });
+        }
+
+        let content = self.stack.last_mut().unwrap().node.content_mut().unwrap();
+
+        // Merge adjacent text with the same marks so soft breaks don't
+        // split sentences into separate nodes
+        if let (Some(Node::Text { text: last_text, marks: last_marks }), Node::Text { text, marks }) = (content.last_mut(), &node) {
+            if last_marks == marks {
+                return last_text.push_str(text);
+            }
+        }
+
+        content.push(node);
+    }
+
+    fn close_implicit(&mut self) {
+        if self.stack.last().map(|frame| frame.implicit).unwrap_or(false) {
+            let frame = self.stack.pop().unwrap();
+            self.push_block(frame.node);
+        }
+    }
+
+    fn push_block(&mut self, node: Node) {
+        match self.stack.last_mut().and_then(|frame| frame.node.content_mut()) {
+            Some(content) => content.push(node),
+            None => self.blocks.push(node),
+        }
+    }
+
+    fn finish(mut self) -> Vec<Node> {
+        while let Some(frame) = self.stack.pop() {
+            for node in self.complete(frame) {
+                self.push_block(node);
+            }
+        }
+        self.blocks.append(&mut self.hoisted);
+        self.blocks
+    }
+}
+
+/// Adds the strong mark to inline content, for headings written where ADF
+/// only allows paragraphs. Code can't be combined with other marks.
+fn bold(content: Vec<Node>) -> Vec<Node> {
+    content.into_iter()
+        .map(|node| match node {
+            Node::Text { text, mut marks } => {
+                if !marks.contains(&Mark::Code) && !marks.contains(&Mark::Strong) {
+                    marks.push(Mark::Strong);
+                }
+                Node::Text { text, marks }
+            }
+            node => node,
+        })
+        .collect()
+}
+
+/// Converts task items back to list items, for lists which mix tasks and
+/// regular items. Nested task lists become lists in the item before them.
+fn untask_items(items: Vec<Node>) -> Vec<Node> {
+    let mut untasked: Vec<Node> = Vec::new();
+
+    for item in items {
+        match item {
+            Node::TaskItem { content, attrs } => {
+                let marker = if attrs.state == TaskState::Done { "[x] " } else { "[ ] " };
+
+                let mut paragraph = vec![Node::text(marker)];
+                paragraph.extend(content);
+                untasked.push(Node::list_item(vec![Node::paragraph(paragraph)]));
+            }
+            Node::TaskList { content, .. } => {
+                let list = Node::bullet_list(untask_items(content));
+                match untasked.last_mut() {
+                    Some(Node::ListItem { content }) => content.push(list),
+                    _ => untasked.push(Node::list_item(vec![Node::paragraph(Vec::new()), list])),
+                }
+            }
+            item => untasked.push(item),
+        }
+    }
+
+    untasked
+}
+
+#[cfg(test)]
+mod test {
+    use crate::adf::{Document, Node};
+    use crate::markdown::{markdown_blocks, markdown_to_adf};
+
+    fn to_json(markdown: &str, max_blocks: Option<usize>) -> String {
+        serde_json::to_string(&markdown_to_adf(markdown, max_blocks)).unwrap()
+    }
+
+    /// Converts the markdown, checking the result is valid ADF
+    fn convert_valid(markdown: &str) -> Vec<Node> {
+        let blocks = markdown_to_adf(markdown, None);
+        if let Err(err) = Document::new(blocks.clone()).validate() {
+            panic!("{:?} converted to invalid ADF: {}", markdown, err);
+        }
+        blocks
+    }
+
+    fn types(nodes: &[Node]) -> Vec<&'static str> {
+        nodes.iter().map(Node::type_name).collect()
+    }
+
+    #[test]
+    fn converts_headings_and_inline_marks() {
+        let format = "[{\"type\":\"heading\",\"content\":[{\"type\":\"text\",\"text\":\"Title\"}],\"attrs\":{\"level\":2}},{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"some \"},{\"type\":\"text\",\"text\":\"bold\",\"marks\":[{\"type\":\"strong\"}]},{\"type\":\"text\",\"text\":\" and \"},{\"type\":\"text\",\"text\":\"code\",\"marks\":[{\"type\":\"code\"}]},{\"type\":\"text\",\"text\":\" with a \"},{\"type\":\"text\",\"text\":\"link\",\"marks\":[{\"type\":\"link\",\"attrs\":{\"href\":\"https://url\"}}]},{\"type\":\"text\",\"text\":\" across lines\"}]}]".to_string();
+
+        assert_eq!(format, to_json("## Title\n\nsome **bold** and `code` with a [link](https://url)\nacross lines", None))
+    }
+
+    #[test]
+    fn converts_lists_and_code_blocks() {
+        let format = "[{\"type\":\"bulletList\",\"content\":[{\"type\":\"listItem\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"one\"}]}]}]},{\"type\":\"orderedList\",\"content\":[{\"type\":\"listItem\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"two\"}]}]}],\"attrs\":{\"order\":3}},{\"type\":\"codeBlock\",\"content\":[{\"type\":\"text\",\"text\":\"fn main() {}\"}],\"attrs\":{\"language\":\"rust\"}},{\"type\":\"blockquote\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"quote\"}]}]}]".to_string();
+
+        assert_eq!(format, to_json("- one\n\n3. two\n\n```rust\nfn main() {}\n```\n\n> quote", None))
+    }
+
+    #[test]
+    fn converts_task_lists_and_tables() {
+        let format = "[{\"type\":\"taskList\",\"content\":[{\"type\":\"taskItem\",\"content\":[{\"type\":\"text\",\"text\":\"done\"}],\"attrs\":{\"localId\":\"autocomment-1\",\"state\":\"DONE\"}},{\"type\":\"taskItem\",\"content\":[{\"type\":\"text\",\"text\":\"todo\"}],\"attrs\":{\"localId\":\"autocomment-2\",\"state\":\"TODO\"}}],\"attrs\":{\"localId\":\"autocomment-3\"}},{\"type\":\"table\",\"content\":[{\"type\":\"tableRow\",\"content\":[{\"type\":\"tableHeader\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"a\"}]}]}]},{\"type\":\"tableRow\",\"content\":[{\"type\":\"tableCell\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"b\"}]}]}]}]}]".to_string();
+
+        assert_eq!(format, to_json("- [x] done\n- [ ] todo\n\n| a |\n|---|\n| b |", None))
+    }
+
+    #[test]
+    fn bolds_headings_in_quotes_and_list_items() {
+        let quote = convert_valid("> # heading");
+        assert_eq!(serde_json::to_string(&quote).unwrap(), "[{\"type\":\"blockquote\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"heading\",\"marks\":[{\"type\":\"strong\"}]}]}]}]");
+
+        let list = convert_valid("- ## heading `code`");
+        assert_eq!(types(&list), vec!["bulletList"]);
+        assert_eq!(types(list[0].content()[0].content()), vec!["paragraph"]);
+    }
+
+    #[test]
+    fn flattens_nested_quotes() {
+        let nested = convert_valid("> > nested");
+        assert_eq!(types(&nested), vec!["blockquote"]);
+        assert_eq!(types(nested[0].content()), vec!["paragraph"]);
+
+        let item = convert_valid("- > quote");
+        assert_eq!(types(item[0].content()[0].content()), vec!["paragraph"]);
+    }
+
+    #[test]
+    fn normalizes_nested_and_empty_list_items() {
+        let nested = convert_valid("- - nested");
+        assert_eq!(types(nested[0].content()[0].content()), vec!["paragraph", "bulletList"]);
+
+        assert!(convert_valid("-").is_empty());
+        let list = convert_valid("- a\n-\n- b");
+        assert_eq!(list[0].content().len(), 2);
+    }
+
+    #[test]
+    fn moves_tables_and_task_lists_to_top_level() {
+        assert_eq!(types(&convert_valid("- item\n\n  | a |\n  |---|\n  | b |")), vec!["bulletList", "table"]);
+        assert_eq!(types(&convert_valid("> | a |\n> |---|\n> | b |")), vec!["table"]);
+        assert_eq!(types(&convert_valid("> quote\n>\n> | a |\n> |---|\n> | b |\n\nafter")), vec!["blockquote", "table", "paragraph"]);
+        assert_eq!(types(&convert_valid("- a\n  - [ ] task")), vec!["bulletList", "taskList"]);
+    }
+
+    #[test]
+    fn keeps_lists_nested_in_task_items() {
+        let tasks = convert_valid("- [ ] parent\n  - [x] child");
+        assert_eq!(types(&tasks), vec!["taskList"]);
+        assert_eq!(types(tasks[0].content()), vec!["taskItem", "taskList"]);
+
+        assert_eq!(types(&convert_valid("- [ ] parent\n  - plain")), vec!["taskList", "bulletList"]);
+
+        let mixed = convert_valid("- [ ] task\n  - [x] child\n- plain");
+        assert_eq!(types(mixed[0].content()[0].content()), vec!["paragraph", "bulletList"]);
+    }
+
+    #[test]
+    fn truncates_blocks() {
+        let format = "[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"one\"}]}]".to_string();
+
+        assert_eq!(format, to_json("one\n\ntwo\n\nthree", Some(1)))
+    }
+
+    #[test]
+    fn splits_top_level_blocks() {
+        let markdown = "first\n\n- one\n\n- two\n\n<div>\nhtml\n</div>\n\n```\na\n\nb\n```\n\n---";
+
+        assert_eq!(markdown_blocks(markdown), vec!["first", "- one\n\n- two", "<div>\nhtml\n</div>", "```\na\n\nb\n```", "---"]);
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prometheus::{Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+
+use crate::error::Error;
+use crate::{PullRequestSync, SyncAction};
+
+/// Counters and gauges describing what autocomment has done since it started,
+/// exposed in the Prometheus text format for `serve` and `watch`
+pub struct Metrics {
+    registry: Registry,
+    pull_requests: IntCounterVec,
+    jira_updates: IntCounterVec,
+    dedup_hits: IntCounterVec,
+    errors: IntCounterVec,
+    http_duration: HistogramVec,
+    rate_limit_remaining: IntGaugeVec,
+    last_success: GaugeVec,
+}
+
+/// The metrics recorded by every client and sync in the process
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let metrics = Metrics {
+            registry: Registry::new(),
+            pull_requests: IntCounterVec::new(
+                Opts::new("autocomment_pull_requests_total", "Pull requests processed, by result"),
+                &["result"],
+            ).unwrap(),
+            jira_updates: IntCounterVec::new(
+                Opts::new("autocomment_jira_updates_total", "Comments and remote links posted or updated on Jira tickets"),
+                &["action"],
+            ).unwrap(),
+            dedup_hits: IntCounterVec::new(
+                Opts::new("autocomment_dedup_hits_total", "Comments and remote links skipped because they were already synced"),
+                &["action"],
+            ).unwrap(),
+            errors: IntCounterVec::new(
+                Opts::new("autocomment_errors_total", "Errors while syncing, by kind"),
+                &["kind"],
+            ).unwrap(),
+            http_duration: HistogramVec::new(
+                HistogramOpts::new("autocomment_http_request_duration_seconds", "Time taken by requests to Github and Jira"),
+                &["upstream", "status"],
+            ).unwrap(),
+            rate_limit_remaining: IntGaugeVec::new(
+                Opts::new("autocomment_rate_limit_remaining", "Requests remaining in the current rate limit window, as last reported by each upstream"),
+                &["upstream"],
+            ).unwrap(),
+            last_success: GaugeVec::new(
+                Opts::new("autocomment_last_successful_sync_timestamp_seconds", "Unix time of the last sync of each repo which completed without errors"),
+                &["repo"],
+            ).unwrap(),
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.pull_requests.clone())).unwrap();
+        registry.register(Box::new(metrics.jira_updates.clone())).unwrap();
+        registry.register(Box::new(metrics.dedup_hits.clone())).unwrap();
+        registry.register(Box::new(metrics.errors.clone())).unwrap();
+        registry.register(Box::new(metrics.http_duration.clone())).unwrap();
+        registry.register(Box::new(metrics.rate_limit_remaining.clone())).unwrap();
+        registry.register(Box::new(metrics.last_success.clone())).unwrap();
+
+        metrics
+    }
+
+    /// Records the result of syncing a single pull request
+    pub fn record_sync(&self, result: &Result<PullRequestSync, Error>) {
+        let sync = match result {
+            Ok(sync) => sync,
+            Err(err) => {
+                self.pull_requests.with_label_values(&["failed"]).inc();
+                self.record_error(err);
+                return;
+            }
+        };
+
+        let pr_result = if sync.ticket_key.is_none() {
+            "no_ticket"
+        } else if sync.actions.iter().any(SyncAction::is_ticket_problem) {
+            "invalid_ticket"
+        } else {
+            "synced"
+        };
+        self.pull_requests.with_label_values(&[pr_result]).inc();
+
+        for action in &sync.actions {
+            match action {
+                SyncAction::CommentAdded | SyncAction::CommentUpdated | SyncAction::LinkAdded | SyncAction::LinkUpdated
+                    | SyncAction::SummaryAdded | SyncAction::SummaryUpdated =>
+                    self.jira_updates.with_label_values(&[action.as_str()]).inc(),
+                SyncAction::CommentExists | SyncAction::CommentUnchanged | SyncAction::LinkExists | SyncAction::LinkUnchanged
+                    | SyncAction::SummaryUnchanged =>
+                    self.dedup_hits.with_label_values(&[action.as_str()]).inc(),
+                SyncAction::TicketNotFound | SyncAction::TicketNoPermission | SyncAction::TicketWrongProject | SyncAction::TicketWrongStatus
+                    | SyncAction::NoTicket => {}
+            }
+        }
+    }
+
+    pub fn record_error(&self, err: &Error) {
+        self.errors.with_label_values(&[err.kind().as_str()]).inc();
+    }
+
+    /// Records a response from Github or Jira. `status` is `None` when no
+    /// response was received.
+    pub fn record_response(&self, upstream: &str, status: Option<u16>, elapsed: Duration, rate_limit_remaining: Option<i64>) {
+        let status = status.map(|status| status.to_string()).unwrap_or_else(|| "error".to_string());
+        self.http_duration.with_label_values(&[upstream, &status]).observe(elapsed.as_secs_f64());
+
+        if let Some(remaining) = rate_limit_remaining {
+            self.rate_limit_remaining.with_label_values(&[upstream]).set(remaining);
+        }
+    }
+
+    /// Records that every pull request in the repo synced without errors
+    pub fn record_repo_synced(&self, repo: &str) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.last_success.with_label_values(&[repo]).set(now.as_secs_f64());
+    }
+
+    /// The metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf).expect("metrics can be encoded");
+        String::from_utf8(buf).expect("metrics are UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// Serves the process's metrics at `/metrics` on a background thread, for
+/// modes which don't already run an HTTP server
+pub fn serve(addr: &str) -> Result<(), Error> {
+    let server = tiny_http::Server::http(addr).map_err(|err| Error::from(err.to_string()))?;
+
+    std::thread::Builder::new()
+        .name("autocomment-metrics".to_string())
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                let response = match (request.method(), request.url()) {
+                    (tiny_http::Method::Get, "/metrics") => tiny_http::Response::from_string(metrics().render()),
+                    (tiny_http::Method::Get, "/health") => tiny_http::Response::from_string("ok"),
+                    _ => tiny_http::Response::from_string("Not found").with_status_code(404),
+                };
+                if let Err(err) = request.respond(response) {
+                    tracing::warn!(error = %err, "unable to respond to metrics request");
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::error::Error;
+    use crate::metrics::Metrics;
+    use crate::{PullRequestSync, SyncAction};
+
+    #[test]
+    fn renders_recorded_metrics() {
+        let metrics = Metrics::new();
+        metrics.record_sync(&Ok(PullRequestSync {
+            tracker: "Jira".to_string(),
+            pr_url: "https://url/org/repo/1".to_string(),
+            ticket_key: Some("A-1".to_string()),
+            actions: vec![SyncAction::CommentUnchanged, SyncAction::LinkUpdated],
+            comment_id: Some("10000".to_string()),
+            ticket_url: Some("https://jira.domain/browse/A-1".to_string()),
+        }));
+        metrics.record_sync(&Err(Error::ConfigError("missing template".to_string())));
+        metrics.record_response("api.github.com", Some(200), Duration::from_millis(30), Some(4999));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("autocomment_pull_requests_total{result=\"synced\"} 1"));
+        assert!(rendered.contains("autocomment_pull_requests_total{result=\"failed\"} 1"));
+        assert!(rendered.contains("autocomment_jira_updates_total{action=\"link_updated\"} 1"));
+        assert!(rendered.contains("autocomment_dedup_hits_total{action=\"comment_unchanged\"} 1"));
+        assert!(rendered.contains("autocomment_errors_total{kind=\"config\"} 1"));
+        assert!(rendered.contains("autocomment_http_request_duration_seconds_count{status=\"200\",upstream=\"api.github.com\"} 1"));
+        assert!(rendered.contains("autocomment_rate_limit_remaining{upstream=\"api.github.com\"} 4999"));
+    }
+}
@@ -0,0 +1,84 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use tokio::runtime::Runtime;
+
+/// Number of pull requests processed at once when not configured
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Number of requests sent to a single host at once when not configured
+pub const DEFAULT_HOST_LIMIT: usize = 4;
+
+/// Runtime which blocking clients wait for their requests on
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+    RUNTIME.get_or_init(|| tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("autocomment-http")
+        .enable_all()
+        .build()
+        .expect("the HTTP runtime can be started"))
+}
+
+/// Waits for the future on the shared runtime. Any number of threads can
+/// wait at once, but none of them can already be inside an async runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+/// Applies the function to each item using up to `concurrency` threads.
+/// Results are returned in the same order as the items, however long each
+/// one takes.
+pub fn map_ordered<T, R, F>(items: &[T], concurrency: usize, f: F) -> Vec<R>
+    where T: Sync, R: Send, F: Fn(&T) -> R + Sync
+{
+    let workers = concurrency.max(1).min(items.len());
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<Option<R>>>());
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let item = match items.get(idx) {
+                    Some(item) => item,
+                    None => break,
+                };
+
+                let result = f(item);
+                results.lock().unwrap_or_else(|err| err.into_inner())[idx] = Some(result);
+            });
+        }
+    });
+
+    results.into_inner().unwrap_or_else(|err| err.into_inner())
+        .into_iter()
+        .map(|result| result.expect("every item is processed before the workers finish"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::pool::map_ordered;
+
+    #[test]
+    fn keeps_results_in_order() {
+        let items: Vec<u64> = (0..20).collect();
+
+        // Earlier items take longer, so they finish after later ones
+        let results = map_ordered(&items, 8, |item| {
+            std::thread::sleep(Duration::from_millis(20 - item));
+            item * 2
+        });
+
+        assert_eq!(results, items.iter().map(|item| item * 2).collect::<Vec<u64>>());
+    }
+}
@@ -0,0 +1,278 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Semaphore;
+
+use crate::credentials::{Credentials, TrackerCredentials};
+use crate::error::Error;
+use crate::http::{self, HttpResponse};
+use crate::pool;
+use crate::tracker::{AsyncIssueTracker, CommentFormat, IssueLink, IssueTracker, TrackerComment};
+
+#[derive(Deserialize)]
+struct RMIssueResponse {
+    issue: RMIssue,
+}
+
+#[derive(Deserialize)]
+pub struct RMIssue {
+    pub id: u64,
+
+    #[serde(default)]
+    pub journals: Vec<RMJournal>,
+}
+
+impl RMIssue {
+    /// Redmine's comments are the notes on an issue's journals. Journals only
+    /// recording changes to the issue have empty notes and are left out.
+    pub fn comments(self) -> Vec<TrackerComment> {
+        self.journals.into_iter()
+            .filter(|journal| !journal.notes.is_empty())
+            .map(|journal| TrackerComment { id: journal.id.to_string(), body: journal.notes })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RMJournal {
+    pub id: u64,
+
+    #[serde(default)]
+    pub notes: String,
+}
+
+#[derive(Deserialize)]
+struct RMUsersResponse {
+    users: Vec<RMUser>,
+}
+
+#[derive(Deserialize)]
+struct RMUser {
+    login: String,
+    mail: Option<String>,
+}
+
+/// Finds the first Redmine issue referenced in the text, either as `#1234` or
+/// as a link to the issue on `domain`
+pub fn parse_redmine_issue_number(text: &str, domain: &str) -> Option<String> {
+    let re = regex::Regex::new(format!(r"(?:https?://{}/issues/|(?:^|[^\w&/])#)(\d+)\b", domain.replace('.', r"\.")).as_str()).unwrap();
+    re.captures(text).map(|group| group[1].to_string())
+}
+
+/// Redmine client which sends requests to the REST API with the async
+/// reqwest client, authenticating with an API key. Comments are written in
+/// Textile.
+pub struct DefaultAsyncRedmineClient {
+    client: Client,
+    creds: TrackerCredentials,
+
+    /// Limits the requests sent to Redmine at once
+    limit: Semaphore,
+}
+
+impl DefaultAsyncRedmineClient {
+    /// `creds` are used for the host limit of the Redmine domain
+    pub fn new(redmine: &TrackerCredentials, creds: &Credentials) -> DefaultAsyncRedmineClient {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let limit = Semaphore::new(creds.host_limit(redmine.host()).max(1));
+        DefaultAsyncRedmineClient { client, creds: redmine.clone(), limit }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        self.client.request(method, format!("{}{}", self.creds.base_url(), path))
+            .header("X-Redmine-API-Key", self.creds.token.as_str())
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<HttpResponse, Error> {
+        let resp = http::send(&self.client, &self.limit, request).await?;
+        if resp.is_success() {
+            Ok(resp)
+        } else {
+            Err(resp.error())
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncIssueTracker for DefaultAsyncRedmineClient {
+    fn name(&self) -> &str {
+        "Redmine"
+    }
+
+    fn get_domain(&self) -> &str {
+        self.creds.host()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        CommentFormat::Textile
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        format!("{}/issues/{}", self.creds.base_url(), ticket_id)
+    }
+
+    fn parse_ticket(&self, text: &str) -> Option<String> {
+        parse_redmine_issue_number(text, self.get_domain())
+    }
+
+    /// Redmine issues are numbered across all projects
+    fn ticket_project(&self, _ticket_id: &str) -> Option<String> {
+        None
+    }
+
+    async fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        let request = self.request(reqwest::Method::GET, &format!("/issues/{}.json", ticket_id))
+            .query(&[("include", "journals")]);
+        let resp: RMIssueResponse = self.send(request).await?.parse()?;
+
+        Ok(resp.issue.comments())
+    }
+
+    /// Adds the comment as notes on the issue. Redmine doesn't return the
+    /// journal it creates, so the ID is found by fetching the issue's journals.
+    async fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        let request = self.request(reqwest::Method::PUT, &format!("/issues/{}.json", ticket_id))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&json!({ "issue": { "notes": text } }))?);
+        self.send(request).await?;
+
+        let comment = self.get_comments(ticket_id).await?.into_iter()
+            .rev()
+            .find(|comment| comment.body == text);
+        Ok(comment.map(|comment| comment.id).unwrap_or_default())
+    }
+
+    /// Editing journal notes needs Redmine 5.0 or newer
+    async fn update_comment(&self, _ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        let request = self.request(reqwest::Method::PUT, &format!("/journals/{}.json", comment_id))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&json!({ "journal": { "notes": text } }))?);
+        self.send(request).await?;
+
+        Ok(())
+    }
+
+    async fn get_links(&self, _ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        Err(Error::ConfigError("Redmine doesn't support remote links, sync with --mode comment".to_string()))
+    }
+
+    async fn post_link(&self, _ticket_id: &str, _link: &IssueLink) -> Result<(), Error> {
+        Err(Error::ConfigError("Redmine doesn't support remote links, sync with --mode comment".to_string()))
+    }
+
+    /// Finds the login of the user, which comments mention. Listing users
+    /// needs an administrator's API key.
+    async fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        let request = self.request(reqwest::Method::GET, "/users.json")
+            .query(&[("name", email)]);
+        let resp: RMUsersResponse = self.send(request).await?.parse()?;
+
+        Ok(resp.users.into_iter()
+            .find(|user| user.mail.as_deref().map(|mail| mail.eq_ignore_ascii_case(email)).unwrap_or(false))
+            .map(|user| user.login))
+    }
+}
+
+/// Blocking Redmine client, which waits for `DefaultAsyncRedmineClient`'s
+/// requests on a shared runtime. It can't be used from inside an async runtime.
+pub struct DefaultRedmineClient {
+    inner: DefaultAsyncRedmineClient,
+}
+
+impl DefaultRedmineClient {
+    pub fn new(redmine: &TrackerCredentials, creds: &Credentials) -> DefaultRedmineClient {
+        DefaultRedmineClient { inner: DefaultAsyncRedmineClient::new(redmine, creds) }
+    }
+}
+
+impl IssueTracker for DefaultRedmineClient {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn get_domain(&self) -> &str {
+        self.inner.get_domain()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        self.inner.comment_format()
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        self.inner.ticket_url(ticket_id)
+    }
+
+    fn parse_ticket(&self, text: &str) -> Option<String> {
+        self.inner.parse_ticket(text)
+    }
+
+    fn ticket_project(&self, ticket_id: &str) -> Option<String> {
+        self.inner.ticket_project(ticket_id)
+    }
+
+    fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        pool::block_on(self.inner.get_comments(ticket_id))
+    }
+
+    fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        pool::block_on(self.inner.post_comment(ticket_id, text))
+    }
+
+    fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        pool::block_on(self.inner.update_comment(ticket_id, comment_id, text))
+    }
+
+    fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        pool::block_on(self.inner.get_links(ticket_id))
+    }
+
+    fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        pool::block_on(self.inner.post_link(ticket_id, link))
+    }
+
+    fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.find_account_id(email))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::credentials::{Credentials, TrackerCredentials};
+    use crate::redmine::{parse_redmine_issue_number, DefaultRedmineClient, RMIssueResponse};
+    use crate::tracker::IssueTracker;
+
+    const ISSUE: &str = include_str!("../tests/fixtures/redmine_issue.json");
+
+    #[test]
+    fn converts_journals_to_comments() {
+        let resp: RMIssueResponse = serde_json::from_str(ISSUE).unwrap();
+        assert_eq!(resp.issue.id, 1234);
+
+        // The status change has no notes, so it isn't a comment
+        let comments = resp.issue.comments();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].id, "5502");
+        assert!(comments[0].body.contains("https://github.com/org/repo/pull/9"));
+    }
+
+    #[test]
+    fn parses_redmine_issue_numbers() {
+        assert_eq!(parse_redmine_issue_number("Fixes #1234 and #99", "redmine.example.com"), Some("1234".to_string()));
+        assert_eq!(parse_redmine_issue_number("See https://redmine.example.com/issues/77", "redmine.example.com"), Some("77".to_string()));
+        assert_eq!(parse_redmine_issue_number("Anchors like page#12 and &#35; aren't issues", "redmine.example.com"), None);
+    }
+
+    #[test]
+    fn issue_numbers_have_no_project() {
+        let redmine = TrackerCredentials { domain: "redmine.example.com".to_string(), ..Default::default() };
+        let client = DefaultRedmineClient::new(&redmine, &Credentials::default());
+        assert_eq!(client.ticket_project("1234"), None);
+    }
+}
@@ -0,0 +1,291 @@
+use std::str::FromStr;
+use std::time::Instant;
+
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+
+use crate::error::{Error, ErrorKind};
+use crate::review::{AsyncReviewClient, ChangeRequest, ReviewClient};
+use crate::tracker::{AsyncIssueTracker, IssueTracker};
+use crate::pool::map_ordered;
+use crate::{sync_pull_request, sync_pull_request_async, PullRequestSync, SyncAction, SyncOptions};
+
+/// The result of syncing one pull request, in the form it's written out
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct SyncReport {
+    pub pr_url: String,
+    pub ticket_key: Option<String>,
+    pub actions: Vec<SyncAction>,
+    pub comment_id: Option<String>,
+    pub messages: Vec<String>,
+    pub error: Option<String>,
+    pub error_kind: Option<ErrorKind>,
+    pub duration_ms: u128,
+}
+
+impl SyncReport {
+    fn new(pr: &ChangeRequest, ticket_key: Option<String>, result: Result<PullRequestSync, Error>, started: Instant) -> SyncReport {
+        let duration_ms = started.elapsed().as_millis();
+
+        match result {
+            Ok(sync) => SyncReport {
+                messages: sync.message().lines().map(String::from).collect(),
+                pr_url: sync.pr_url,
+                ticket_key: sync.ticket_key,
+                actions: sync.actions,
+                comment_id: sync.comment_id,
+                error: None,
+                error_kind: None,
+                duration_ms,
+            },
+            Err(err) => SyncReport {
+                pr_url: pr.html_url.clone(),
+                ticket_key,
+                actions: Vec::new(),
+                comment_id: None,
+                messages: Vec::new(),
+                error: Some(err.to_string()),
+                error_kind: Some(err.kind()),
+                duration_ms,
+            },
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// Syncs the pull requests like `process_pull_requests`, reporting on every
+/// pull request even when some of them fail
+pub fn report_pull_requests(review_client: &dyn ReviewClient, tracker: &dyn IssueTracker, prs: &[ChangeRequest], options: &SyncOptions) -> Vec<SyncReport> {
+    // Workers run on their own threads, so they need to enter the caller's span
+    let span = tracing::Span::current();
+
+    map_ordered(prs, options.concurrency, |pr| span.in_scope(|| {
+        let started = Instant::now();
+        let result = sync_pull_request(review_client, tracker, pr, options);
+        let ticket_key = pr.body.as_deref().and_then(|body| tracker.parse_ticket(body));
+        SyncReport::new(pr, ticket_key, result, started)
+    }))
+}
+
+pub async fn report_pull_requests_async(review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker, prs: &[ChangeRequest], options: &SyncOptions) -> Vec<SyncReport> {
+    // Run the syncs concurrently in the same way as `process_pull_requests_async`
+    let syncs: Vec<_> = prs.iter()
+        .map(|pr| async move {
+            let started = Instant::now();
+            let result = sync_pull_request_async(review_client, tracker, pr, options).await;
+            let ticket_key = pr.body.as_deref().and_then(|body| tracker.parse_ticket(body));
+            SyncReport::new(pr, ticket_key, result, started)
+        })
+        .collect();
+
+    stream::iter(syncs)
+        .buffered(options.concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// How sync results are written out
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum OutputFormat {
+    /// A line describing each action taken. Errors are left for the caller
+    /// to report.
+    #[default]
+    Text,
+    Json,
+
+    /// One JSON object per line
+    Ndjson,
+    Table,
+    Markdown,
+
+    /// A JUnit XML test suite with a test case for each pull request
+    Junit,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "table" => Ok(OutputFormat::Table),
+            "markdown" => Ok(OutputFormat::Markdown),
+            "junit" => Ok(OutputFormat::Junit),
+            _ => Err(Error::from(format!("Unknown output format {}, expected one of text, json, ndjson, table, markdown, junit", s))),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn render(&self, reports: &[SyncReport]) -> Result<String, Error> {
+        match self {
+            OutputFormat::Text => Ok(render_text(reports)),
+            OutputFormat::Json => Ok(serde_json::to_string_pretty(reports)? + "\n"),
+            OutputFormat::Ndjson => reports.iter()
+                .map(|report| serde_json::to_string(report).map(|line| line + "\n").map_err(Error::from))
+                .collect(),
+            OutputFormat::Table => Ok(render_table(reports)),
+            OutputFormat::Markdown => Ok(render_markdown(reports)),
+            OutputFormat::Junit => Ok(render_junit(reports)),
+        }
+    }
+}
+
+const COLUMNS: [&str; 6] = ["PR", "Ticket", "Action", "Comment", "Time", "Error"];
+
+fn row(report: &SyncReport) -> [String; 6] {
+    let actions = report.actions.iter()
+        .map(SyncAction::as_str)
+        .collect::<Vec<&str>>()
+        .join(", ");
+
+    [
+        report.pr_url.clone(),
+        report.ticket_key.clone().unwrap_or_default(),
+        if report.is_error() { "failed".to_string() } else { actions },
+        report.comment_id.clone().unwrap_or_default(),
+        format!("{}ms", report.duration_ms),
+        report.error.clone().unwrap_or_default(),
+    ]
+}
+
+fn render_text(reports: &[SyncReport]) -> String {
+    reports.iter()
+        .flat_map(|report| report.messages.iter())
+        .map(|msg| format!("{}\n", msg))
+        .collect()
+}
+
+fn render_table(reports: &[SyncReport]) -> String {
+    let rows: Vec<[String; 6]> = reports.iter().map(row).collect();
+
+    let mut widths = COLUMNS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells.iter().zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        padded.join("  ").trim_end().to_string() + "\n"
+    };
+
+    let mut table = line(COLUMNS.to_vec());
+    for row in &rows {
+        table += &line(row.iter().map(String::as_str).collect());
+    }
+    table
+}
+
+fn render_markdown(reports: &[SyncReport]) -> String {
+    let escape = |cell: &str| cell.replace('|', "\\|").replace('\n', " ");
+
+    let mut table = format!("| {} |\n", COLUMNS.join(" | "));
+    table += &format!("|{}\n", " --- |".repeat(COLUMNS.len()));
+    for row in reports.iter().map(row) {
+        table += &format!("| {} |\n", row.iter().map(|cell| escape(cell)).collect::<Vec<String>>().join(" | "));
+    }
+    table
+}
+
+fn render_junit(reports: &[SyncReport]) -> String {
+    let failures = reports.iter().filter(|report| report.is_error()).count();
+    let total_ms: u128 = reports.iter().map(|report| report.duration_ms).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!("<testsuite name=\"autocomment\" tests=\"{}\" failures=\"{}\" time=\"{}\">\n", reports.len(), failures, seconds(total_ms));
+
+    for report in reports {
+        let name = match &report.ticket_key {
+            Some(ticket_key) => format!("{} ({})", report.pr_url, ticket_key),
+            None => report.pr_url.clone(),
+        };
+        xml += &format!("  <testcase classname=\"autocomment.sync\" name=\"{}\" time=\"{}\"", escape_xml(&name), seconds(report.duration_ms));
+
+        match &report.error {
+            Some(err) => xml += &format!(">\n    <failure message=\"{}\"/>\n  </testcase>\n", escape_xml(err)),
+            None if report.messages.is_empty() => xml += "/>\n",
+            None => xml += &format!(">\n    <system-out>{}</system-out>\n  </testcase>\n", escape_xml(&report.messages.join("\n"))),
+        }
+    }
+
+    xml + "</testsuite>\n"
+}
+
+fn seconds(ms: u128) -> String {
+    format!("{:.3}", ms as f64 / 1000.0)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::ErrorKind;
+    use crate::report::{OutputFormat, SyncReport};
+    use crate::SyncAction;
+
+    fn reports() -> Vec<SyncReport> {
+        vec![
+            SyncReport {
+                pr_url: "https://url/org/repo/1".to_string(),
+                ticket_key: Some("A-1".to_string()),
+                actions: vec![SyncAction::CommentAdded],
+                comment_id: Some("10000".to_string()),
+                messages: vec!["Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.".to_string()],
+                error: None,
+                error_kind: None,
+                duration_ms: 120,
+            },
+            SyncReport {
+                pr_url: "https://url/org/repo/2".to_string(),
+                ticket_key: Some("A-2".to_string()),
+                actions: Vec::new(),
+                comment_id: None,
+                messages: Vec::new(),
+                error: Some("Error occurred: <403> Forbidden".to_string()),
+                error_kind: Some(ErrorKind::Other),
+                duration_ms: 5,
+            },
+        ]
+    }
+
+    #[test]
+    fn renders_ndjson() {
+        assert_eq!(OutputFormat::Ndjson.render(&reports()[..1]).unwrap(), "{\"pr_url\":\"https://url/org/repo/1\",\"ticket_key\":\"A-1\",\"actions\":[\"comment_added\"],\"comment_id\":\"10000\",\"messages\":[\"Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.\"],\"error\":null,\"error_kind\":null,\"duration_ms\":120}\n");
+    }
+
+    #[test]
+    fn renders_markdown() {
+        assert_eq!(OutputFormat::Markdown.render(&reports()).unwrap(), "| PR | Ticket | Action | Comment | Time | Error |\n\
+            | --- | --- | --- | --- | --- | --- |\n\
+            | https://url/org/repo/1 | A-1 | comment_added | 10000 | 120ms |  |\n\
+            | https://url/org/repo/2 | A-2 | failed |  | 5ms | Error occurred: <403> Forbidden |\n");
+    }
+
+    #[test]
+    fn renders_junit() {
+        assert_eq!(OutputFormat::Junit.render(&reports()).unwrap(), "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <testsuite name=\"autocomment\" tests=\"2\" failures=\"1\" time=\"0.125\">\n  \
+            <testcase classname=\"autocomment.sync\" name=\"https://url/org/repo/1 (A-1)\" time=\"0.120\">\n    \
+            <system-out>Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://url/org/repo/1.</system-out>\n  \
+            </testcase>\n  \
+            <testcase classname=\"autocomment.sync\" name=\"https://url/org/repo/2 (A-2)\" time=\"0.005\">\n    \
+            <failure message=\"Error occurred: &lt;403&gt; Forbidden\"/>\n  \
+            </testcase>\n\
+            </testsuite>\n");
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+
+use crate::bitbucket::{DefaultBitbucketClient, DefaultBitbucketServerClient};
+use crate::credentials::Credentials;
+use crate::error::Error;
+use crate::github::DefaultGithubClient;
+use crate::gitea::DefaultGiteaClient;
+use crate::gitlab::DefaultGitlabClient;
+use crate::jira::{JiraCommentRequest, JiraRemoteLink};
+use crate::template::CommentTemplate;
+use crate::tracker::IssueLink;
+use crate::Blocking;
+
+/// The service hosting a repository and its changes
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    Github,
+    Gitlab,
+
+    /// Bitbucket Cloud
+    Bitbucket,
+
+    /// Bitbucket Server or Data Center
+    #[serde(rename = "bitbucket-server")]
+    BitbucketServer,
+
+    /// Gitea or Forgejo
+    Gitea,
+}
+
+impl Provider {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::Github => "Github",
+            Provider::Gitlab => "GitLab",
+            Provider::Bitbucket | Provider::BitbucketServer => "Bitbucket",
+            Provider::Gitea => "Gitea",
+        }
+    }
+
+    /// What the provider calls a change
+    pub fn change_name(&self) -> &'static str {
+        match self {
+            Provider::Github | Provider::Bitbucket | Provider::BitbucketServer | Provider::Gitea => "Pull Request",
+            Provider::Gitlab => "Merge Request",
+        }
+    }
+
+    pub fn icon_url(&self) -> &'static str {
+        match self {
+            Provider::Github => "https://github.com/favicon.ico",
+            Provider::Gitlab => "https://gitlab.com/favicon.ico",
+            Provider::Bitbucket | Provider::BitbucketServer => "https://bitbucket.org/favicon.ico",
+            Provider::Gitea => "https://gitea.com/favicon.ico",
+        }
+    }
+}
+
+impl FromStr for Provider {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "github" => Ok(Provider::Github),
+            "gitlab" => Ok(Provider::Gitlab),
+            "bitbucket" => Ok(Provider::Bitbucket),
+            "bitbucket-server" => Ok(Provider::BitbucketServer),
+            "gitea" | "forgejo" => Ok(Provider::Gitea),
+            _ => Err(Error::from(format!("Unknown provider {}, expected one of github, gitlab, bitbucket, bitbucket-server, gitea", s))),
+        }
+    }
+}
+
+/// A pull request, merge request or other change proposed for review, only
+/// including the fields needed to create a comment on a matching Jira ticket.
+/// Fields are named after Github's pull requests, so Github responses and
+/// webhook payloads deserialize directly into it; other providers convert
+/// their own responses.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChangeRequest {
+    pub base: ChangeBase,
+    pub html_url: String,
+    pub title: String,
+    pub body: Option<String>,
+    pub created_at: String,
+    pub user: Account,
+    pub state: String,
+    pub merged_at: Option<String>,
+    pub updated_at: String,
+    pub head: ChangeHead,
+
+    #[serde(default)]
+    pub labels: Vec<Label>,
+
+    #[serde(default)]
+    pub requested_reviewers: Vec<Account>,
+
+    /// Where the change is hosted
+    #[serde(default)]
+    pub provider: Provider,
+}
+
+impl ChangeRequest {
+    /// Builds a comment using the default template
+    pub fn build_jira_comment(&self) -> Result<JiraCommentRequest, Error> {
+        if self.body.is_none() {
+            return Err(Error::from(format!("Pull Request {} has an invalid description", self.html_url)));
+        }
+
+        CommentTemplate::default().render_adf(self, &HashMap::new())
+    }
+
+    /// A pull request is resolved once it has been merged or closed
+    pub fn is_resolved(&self) -> bool {
+        self.merged_at.is_some() || self.state == "closed"
+    }
+
+    /// Builds the link to the change shown on the ticket
+    pub fn build_issue_link(&self) -> IssueLink {
+        let state = if self.merged_at.is_some() { "merged" } else { self.state.as_str() };
+
+        IssueLink {
+            url: self.html_url.clone(),
+            title: self.title.clone(),
+            summary: Some(format!("{} in {} ({})", self.provider.change_name(), self.base.repo.full_name, state)),
+            icon_url: Some(self.provider.icon_url().to_string()),
+            icon_title: Some(format!("{} {}", self.provider.name(), self.provider.change_name())),
+            resolved: self.is_resolved(),
+        }
+    }
+
+    pub fn build_jira_remote_link(&self) -> JiraRemoteLink {
+        JiraRemoteLink::from(&self.build_issue_link())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChangeBase {
+    pub repo: ChangeRepo
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChangeHead {
+    #[serde(rename = "ref")]
+    pub branch: String,
+
+    /// The head commit, which commit statuses are set on. Only Github and
+    /// Gitea changes have it.
+    #[serde(default)]
+    pub sha: Option<String>,
+}
+
+/// A commit status reporting whether a change references a valid ticket
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CommitStatus {
+    pub success: bool,
+    pub description: String,
+    pub target_url: Option<String>,
+}
+
+/// A check run reporting whether a change references a valid ticket, with
+/// an explanation of what's wrong when it doesn't
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CheckRun {
+    pub success: bool,
+    pub title: String,
+    pub summary: String,
+    pub details_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Label {
+    pub name: String,
+}
+
+/// The repository or project a change is in
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChangeRepo {
+    pub full_name: String,
+}
+
+/// A user on the provider, identified by their login or username
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Account {
+    pub login: String,
+}
+
+/// Changes updated since a repo was last synced
+pub struct UpdatedChanges {
+    /// Changes created by the configured user, most recently updated first
+    pub changes: Vec<ChangeRequest>,
+
+    /// When the most recently updated change was updated, including changes
+    /// by other users, which is where the next sync starts from
+    pub latest_update: Option<String>,
+}
+
+impl UpdatedChanges {
+    /// Keeps the changes `is_author` accepts out of every change updated,
+    /// which are sorted most recently updated first
+    pub fn new<F>(updated: Vec<ChangeRequest>, is_author: F) -> Self where F: Fn(&ChangeRequest) -> bool {
+        let latest_update = updated.first().map(|change| change.updated_at.clone());
+        let changes = updated.into_iter().filter(|change| is_author(change)).collect();
+
+        UpdatedChanges { changes, latest_update }
+    }
+}
+
+/// A comment on a change
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ChangeComment {
+    pub id: String,
+    pub body: String,
+}
+
+/// A code review provider, such as Github or GitLab
+pub trait ReviewClient: Send + Sync {
+    /// Get a list of all changes for a repo, using the filters provided.
+    /// Filters are written as Github's pull request query parameters, like
+    /// `?state=open`. Only changes created by the user found in the
+    /// Credentials will be returned.
+    fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error>;
+
+    /// Get the changes for a repo which were updated after `since`, or all
+    /// changes if there is no `since`. Changes are returned most recently
+    /// updated first, and only those created by the user found in the
+    /// Credentials are returned. The latest update is taken from every
+    /// change, so it moves forward even when other users' changes are newer.
+    fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error>;
+
+    /// Get the public email address of a user, if they have one
+    fn get_user_email(&self, login: &str) -> Result<Option<String>, Error>;
+
+    /// Get the comments on a change. Only Github supports commenting on
+    /// changes.
+    fn get_change_comments(&self, change: &ChangeRequest) -> Result<Vec<ChangeComment>, Error> {
+        Err(comments_unsupported(change))
+    }
+
+    /// Posts the comment on the change, returning the ID of the new comment
+    fn post_change_comment(&self, change: &ChangeRequest, _body: &str) -> Result<String, Error> {
+        Err(comments_unsupported(change))
+    }
+
+    fn update_change_comment(&self, change: &ChangeRequest, _comment_id: &str, _body: &str) -> Result<(), Error> {
+        Err(comments_unsupported(change))
+    }
+
+    /// Sets the status on the change's head commit. Only Github supports
+    /// commit statuses.
+    fn set_commit_status(&self, change: &ChangeRequest, _status: &CommitStatus) -> Result<(), Error> {
+        Err(statuses_unsupported(change))
+    }
+
+    /// Creates the check run on the change's head commit, or updates it if
+    /// it was already created for the commit
+    fn set_check_run(&self, change: &ChangeRequest, _check: &CheckRun) -> Result<(), Error> {
+        Err(check_runs_unsupported(change))
+    }
+}
+
+/// Async version of `ReviewClient`, for use from async code
+#[async_trait]
+pub trait AsyncReviewClient: Send + Sync {
+    async fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error>;
+    async fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error>;
+    async fn get_user_email(&self, login: &str) -> Result<Option<String>, Error>;
+
+    async fn get_change_comments(&self, change: &ChangeRequest) -> Result<Vec<ChangeComment>, Error> {
+        Err(comments_unsupported(change))
+    }
+
+    async fn post_change_comment(&self, change: &ChangeRequest, _body: &str) -> Result<String, Error> {
+        Err(comments_unsupported(change))
+    }
+
+    async fn update_change_comment(&self, change: &ChangeRequest, _comment_id: &str, _body: &str) -> Result<(), Error> {
+        Err(comments_unsupported(change))
+    }
+
+    async fn set_commit_status(&self, change: &ChangeRequest, _status: &CommitStatus) -> Result<(), Error> {
+        Err(statuses_unsupported(change))
+    }
+
+    async fn set_check_run(&self, change: &ChangeRequest, _check: &CheckRun) -> Result<(), Error> {
+        Err(check_runs_unsupported(change))
+    }
+}
+
+fn comments_unsupported(change: &ChangeRequest) -> Error {
+    Error::ConfigError(format!("Commenting on {}s isn't supported for {}", change.provider.change_name().to_lowercase(), change.provider.name()))
+}
+
+fn statuses_unsupported(change: &ChangeRequest) -> Error {
+    Error::ConfigError(format!("Commit statuses aren't supported for {}", change.provider.name()))
+}
+
+fn check_runs_unsupported(change: &ChangeRequest) -> Error {
+    Error::ConfigError(format!("Check runs aren't supported for {}", change.provider.name()))
+}
+
+/// Creates the blocking client for the provider, using its configured
+/// credentials
+pub fn default_client(creds: &Credentials, provider: Provider) -> Result<Box<dyn ReviewClient>, Error> {
+    match provider {
+        Provider::Github => Ok(Box::new(DefaultGithubClient::new(creds))),
+        Provider::Gitlab => {
+            let gitlab = creds.gitlab.as_ref()
+                .ok_or(Error::ConfigError("GitLab is not configured, add gitlab to the config file".to_string()))?;
+            Ok(Box::new(DefaultGitlabClient::new(gitlab, creds)))
+        }
+        Provider::Bitbucket => {
+            let bitbucket = creds.bitbucket.as_ref()
+                .ok_or(Error::ConfigError("Bitbucket Cloud is not configured, add bitbucket to the config file".to_string()))?;
+            Ok(Box::new(DefaultBitbucketClient::new(bitbucket, creds)))
+        }
+        Provider::BitbucketServer => {
+            let bitbucket = creds.bitbucket_server.as_ref()
+                .ok_or(Error::ConfigError("Bitbucket Server is not configured, add bitbucket_server to the config file".to_string()))?;
+            Ok(Box::new(DefaultBitbucketServerClient::new(bitbucket, creds)))
+        }
+        Provider::Gitea => {
+            let gitea = creds.gitea.as_ref()
+                .ok_or(Error::ConfigError("Gitea is not configured, add gitea to the config file".to_string()))?;
+            Ok(Box::new(DefaultGiteaClient::new(gitea, creds)))
+        }
+    }
+}
+
+/// Lets async code use a blocking client, such as a mock
+#[async_trait]
+impl<C: ReviewClient + ?Sized> AsyncReviewClient for Blocking<'_, C> {
+    async fn get_changes_for_repo(&self, repo: &str, filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        self.0.get_changes_for_repo(repo, filters)
+    }
+
+    async fn get_changes_updated_since(&self, repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        self.0.get_changes_updated_since(repo, since)
+    }
+
+    async fn get_user_email(&self, login: &str) -> Result<Option<String>, Error> {
+        self.0.get_user_email(login)
+    }
+
+    async fn get_change_comments(&self, change: &ChangeRequest) -> Result<Vec<ChangeComment>, Error> {
+        self.0.get_change_comments(change)
+    }
+
+    async fn post_change_comment(&self, change: &ChangeRequest, body: &str) -> Result<String, Error> {
+        self.0.post_change_comment(change, body)
+    }
+
+    async fn update_change_comment(&self, change: &ChangeRequest, comment_id: &str, body: &str) -> Result<(), Error> {
+        self.0.update_change_comment(change, comment_id, body)
+    }
+
+    async fn set_commit_status(&self, change: &ChangeRequest, status: &CommitStatus) -> Result<(), Error> {
+        self.0.set_commit_status(change, status)
+    }
+
+    async fn set_check_run(&self, change: &ChangeRequest, check: &CheckRun) -> Result<(), Error> {
+        self.0.set_check_run(change, check)
+    }
+}
+
+pub struct MockReviewClient {
+    pub data: Box<Vec<ChangeRequest>>
+}
+
+impl ReviewClient for MockReviewClient {
+    fn get_changes_for_repo(&self, _repo: &str, _filters: &str) -> Result<Vec<ChangeRequest>, Error> {
+        Ok(*self.data.clone())
+    }
+
+    fn get_changes_updated_since(&self, _repo: &str, since: Option<&str>) -> Result<UpdatedChanges, Error> {
+        let mut prs: Vec<ChangeRequest> = self.data.iter()
+            .filter(|pr| since.map(|since| pr.updated_at.as_str() > since).unwrap_or(true))
+            .cloned()
+            .collect();
+        prs.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        Ok(UpdatedChanges::new(prs, |_| true))
+    }
+
+    fn get_user_email(&self, _login: &str) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::review::{Account, ChangeBase, ChangeHead, ChangeRepo, ChangeRequest, Provider, UpdatedChanges};
+
+    #[test]
+    fn latest_update_includes_other_users_changes() {
+        let change = |login: &str, updated_at: &str| ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: "test".to_string() } },
+            html_url: "https://url/org/repo".to_string(),
+            title: "test title".to_string(),
+            body: None,
+            created_at: "datetime".to_string(),
+            user: Account { login: login.to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: updated_at.to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        let updated = vec![change("someone", "2022-12-03T00:00:00Z"), change("me", "2022-12-01T00:00:00Z")];
+        let updated = UpdatedChanges::new(updated, |change| change.user.login == "me");
+        assert_eq!(updated.changes.len(), 1);
+        assert_eq!(updated.latest_update.as_deref(), Some("2022-12-03T00:00:00Z"));
+
+        let updated = UpdatedChanges::new(vec![change("someone", "2022-12-03T00:00:00Z")], |change| change.user.login == "me");
+        assert!(updated.changes.is_empty());
+        assert_eq!(updated.latest_update.as_deref(), Some("2022-12-03T00:00:00Z"));
+    }
+
+    #[test]
+    fn build_jira_comment_success() {
+        let pr = ChangeRequest{
+            base: ChangeBase {
+                repo: ChangeRepo { full_name: "test".to_string() }
+            },
+            html_url: "https://url/org/repo".to_string(),
+            title: "test title".to_string(),
+            body: Some("test body\nwith two lines".to_string()),
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        let format = "{\"body\":{\"version\":1,\"type\":\"doc\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"Pull Request in test: \"},{\"type\":\"text\",\"text\":\"test title\",\"marks\":[{\"type\":\"link\",\"attrs\":{\"href\":\"https://url/org/repo\"}}]}]},{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"test body\"}]},{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"Created at: datetime\"}]}]}}".to_string();
+
+        assert_eq!(format, serde_json::to_string(&pr.build_jira_comment().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn build_jira_comment_failure() {
+        let pr = ChangeRequest{
+            base: ChangeBase {
+                repo: ChangeRepo { full_name: "test".to_string() }
+            },
+            html_url: "https://url/org/repo".to_string(),
+            title: "test title".to_string(),
+            body: None,
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        assert!(pr.build_jira_comment().is_err())
+    }
+
+    #[test]
+    fn build_jira_remote_link_merged() {
+        let pr = ChangeRequest{
+            base: ChangeBase {
+                repo: ChangeRepo { full_name: "test".to_string() }
+            },
+            html_url: "https://url/org/repo".to_string(),
+            title: "test title".to_string(),
+            body: None,
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "closed".to_string(),
+            merged_at: Some("datetime".to_string()),
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        };
+
+        let format = "{\"globalId\":\"https://url/org/repo\",\"object\":{\"url\":\"https://url/org/repo\",\"title\":\"test title\",\"summary\":\"Pull Request in test (merged)\",\"icon\":{\"url16x16\":\"https://github.com/favicon.ico\",\"title\":\"Github Pull Request\"},\"status\":{\"resolved\":true}}}".to_string();
+
+        assert_eq!(format, serde_json::to_string(&pr.build_jira_remote_link()).unwrap())
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+
+use crate::credentials::Credentials;
+use crate::error::Error;
+use crate::review::{ChangeRequest, ReviewClient};
+use crate::tracker::IssueTracker;
+use crate::metrics::metrics;
+use crate::{process_pull_request, SyncOptions};
+
+/// Number of delivery IDs remembered to detect redelivered webhooks
+const MAX_DELIVERIES: usize = 1000;
+
+/// Pull request actions which can change what should be synced to Jira
+const SYNCED_ACTIONS: [&str; 7] = ["opened", "edited", "reopened", "closed", "synchronize", "ready_for_review", "review_requested"];
+
+/// Payload of a Github `pull_request` webhook event
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GHPullRequestEvent {
+    pub action: String,
+    pub pull_request: ChangeRequest,
+}
+
+/// The parts of an HTTP request needed to handle a webhook delivery
+pub struct WebhookRequest {
+    pub method: String,
+    pub path: String,
+    pub event: Option<String>,
+    pub delivery: Option<String>,
+    pub signature: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct WebhookResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl WebhookResponse {
+    fn new(status: u16, body: &str) -> Self {
+        WebhookResponse { status, body: body.to_string() }
+    }
+}
+
+/// Receives Github webhook deliveries and syncs the pull requests in them
+/// to Jira, in the same way as `sync_pull_requests`. Deliveries are
+/// acknowledged as soon as they're verified and the pull requests are synced
+/// afterwards, since Github gives up on deliveries which take more than 10
+/// seconds.
+pub struct WebhookServer<'a> {
+    secret: String,
+    creds: &'a Credentials,
+    options: SyncOptions,
+    review_client: &'a dyn ReviewClient,
+    jira_client: &'a dyn IssueTracker,
+
+    /// IDs of the most recent deliveries which were handled successfully
+    deliveries: Mutex<VecDeque<String>>,
+
+    /// Pull requests from accepted deliveries which haven't been synced yet
+    queue: Mutex<VecDeque<QueuedSync>>,
+    queued: Condvar,
+}
+
+/// A pull request waiting to be synced, with the deliveries it came from.
+/// The deliveries are only recorded as handled once the sync succeeds, so
+/// Github's redelivery of one which failed is synced again.
+struct QueuedSync {
+    pr: ChangeRequest,
+    deliveries: Vec<String>,
+}
+
+impl<'a> WebhookServer<'a> {
+    pub fn new(secret: &str, creds: &'a Credentials, options: SyncOptions, review_client: &'a dyn ReviewClient, jira_client: &'a dyn IssueTracker) -> WebhookServer<'a> {
+        WebhookServer {
+            secret: secret.to_string(),
+            creds,
+            options,
+            review_client,
+            jira_client,
+            deliveries: Mutex::new(VecDeque::new()),
+            queue: Mutex::new(VecDeque::new()),
+            queued: Condvar::new(),
+        }
+    }
+
+    /// Listens on the address and handles requests until the process exits.
+    /// Queued pull requests are synced one at a time on a worker thread.
+    pub fn serve(&self, addr: &str) -> Result<(), Error> {
+        let server = tiny_http::Server::http(addr).map_err(|err| Error::from(err.to_string()))?;
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| self.work());
+            self.respond(&server);
+        });
+
+        Ok(())
+    }
+
+    fn respond(&self, server: &tiny_http::Server) {
+        for mut request in server.incoming_requests() {
+            let header = |name: &str| request.headers().iter()
+                .find(|header| header.field.to_string().eq_ignore_ascii_case(name))
+                .map(|header| header.value.to_string());
+
+            let mut webhook = WebhookRequest {
+                method: request.method().to_string(),
+                path: request.url().to_string(),
+                event: header("X-GitHub-Event"),
+                delivery: header("X-GitHub-Delivery"),
+                signature: header("X-Hub-Signature-256"),
+                body: Vec::new(),
+            };
+
+            let response = match request.as_reader().read_to_end(&mut webhook.body) {
+                Ok(_) => self.handle(&webhook),
+                Err(err) => WebhookResponse::new(400, &err.to_string()),
+            };
+
+            let http_response = tiny_http::Response::from_string(response.body)
+                .with_status_code(response.status);
+            // A client which disconnected early mustn't stop the server
+            if let Err(err) = request.respond(http_response) {
+                tracing::warn!(error = %err, "unable to send response");
+            }
+        }
+    }
+
+    pub fn handle(&self, request: &WebhookRequest) -> WebhookResponse {
+        // Github can be configured to deliver to a URL with a query string
+        let path = request.path.split('?').next().unwrap_or_default();
+
+        match (request.method.as_str(), path) {
+            ("GET", "/health") => WebhookResponse::new(200, "ok"),
+            ("GET", "/metrics") => WebhookResponse::new(200, &metrics().render()),
+            ("POST", "/webhook") => self.handle_webhook(request),
+            _ => WebhookResponse::new(404, "Not found"),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(event = request.event.as_deref(), delivery = request.delivery.as_deref()))]
+    fn handle_webhook(&self, request: &WebhookRequest) -> WebhookResponse {
+        let verified = request.signature.as_deref()
+            .map(|signature| verify_signature(&self.secret, &request.body, signature))
+            .unwrap_or(false);
+        if !verified {
+            tracing::warn!("rejected delivery with an invalid signature");
+            return WebhookResponse::new(401, "Invalid signature");
+        }
+
+        if let Some(delivery) = &request.delivery {
+            if self.deliveries().contains(delivery) {
+                return WebhookResponse::new(200, &format!("Delivery {} was already handled", delivery));
+            }
+        }
+
+        let result = match request.event.as_deref() {
+            Some("ping") => Ok(self.handled(request, "pong".to_string())),
+            Some("pull_request") => self.handle_pull_request(request),
+            Some(event) => Ok(self.handled(request, format!("Ignored {} event", event))),
+            None => return WebhookResponse::new(400, "Missing X-GitHub-Event header"),
+        };
+
+        match result {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::error!(error = %err, "unable to handle delivery");
+                WebhookResponse::new(400, &err.to_string())
+            }
+        }
+    }
+
+    /// Queues the delivery's pull request to be synced. A pull request which
+    /// is already queued is replaced by the newer delivery.
+    fn handle_pull_request(&self, request: &WebhookRequest) -> Result<WebhookResponse, Error> {
+        let event: GHPullRequestEvent = serde_json::from_slice(&request.body)?;
+
+        if !SYNCED_ACTIONS.contains(&event.action.as_str()) {
+            return Ok(self.handled(request, format!("Ignored pull_request {} action", event.action)));
+        }
+
+        let pr = event.pull_request;
+        let msg = format!("Queued sync of {}", pr.html_url);
+
+        let mut queue = self.queue.lock().unwrap_or_else(|err| err.into_inner());
+        let mut deliveries = match queue.iter().position(|queued| queued.pr.html_url == pr.html_url) {
+            Some(idx) => queue.remove(idx).map(|replaced| replaced.deliveries).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        if let Some(delivery) = request.delivery.as_ref().filter(|delivery| !deliveries.contains(delivery)) {
+            deliveries.push(delivery.clone());
+        }
+        queue.push_back(QueuedSync { pr, deliveries });
+        self.queued.notify_one();
+
+        Ok(WebhookResponse::new(202, &msg))
+    }
+
+    /// Syncs the pull requests which are queued, without waiting for more
+    pub fn sync_queued(&self) -> Vec<Result<String, Error>> {
+        std::iter::from_fn(|| self.queue.lock().unwrap_or_else(|err| err.into_inner()).pop_front())
+            .map(|queued| self.sync_queued_pull_request(&queued))
+            .collect()
+    }
+
+    /// Syncs queued pull requests as they arrive, until the process exits
+    fn work(&self) {
+        loop {
+            let queued = {
+                let mut queue = self.queue.lock().unwrap_or_else(|err| err.into_inner());
+                loop {
+                    match queue.pop_front() {
+                        Some(queued) => break queued,
+                        None => queue = self.queued.wait(queue).unwrap_or_else(|err| err.into_inner()),
+                    }
+                }
+            };
+
+            let pr = &queued.pr.html_url;
+            match self.sync_queued_pull_request(&queued) {
+                Ok(msg) => tracing::info!(%pr, "{}", msg),
+                Err(err) => tracing::error!(%pr, error = %err, "unable to sync pull request"),
+            }
+        }
+    }
+
+    fn sync_queued_pull_request(&self, queued: &QueuedSync) -> Result<String, Error> {
+        let msg = self.sync_pull_request(&queued.pr)?;
+        for delivery in &queued.deliveries {
+            self.record_delivery(delivery);
+        }
+        Ok(msg)
+    }
+
+    fn sync_pull_request(&self, pr: &ChangeRequest) -> Result<String, Error> {
+        let options = SyncOptions {
+            template: self.creds.template_for_repo(&pr.base.repo.full_name)?,
+            ..self.options.clone()
+        };
+
+        let msg = process_pull_request(self.review_client, self.jira_client, pr, &options)?;
+        metrics().record_repo_synced(&pr.base.repo.full_name);
+        Ok(msg)
+    }
+
+    /// Records the delivery as handled, for events which don't need a sync
+    fn handled(&self, request: &WebhookRequest, msg: String) -> WebhookResponse {
+        if let Some(delivery) = &request.delivery {
+            self.record_delivery(delivery);
+        }
+        WebhookResponse::new(200, &msg)
+    }
+
+    fn record_delivery(&self, delivery: &str) {
+        let mut deliveries = self.deliveries();
+        if deliveries.len() == MAX_DELIVERIES {
+            deliveries.pop_front();
+        }
+        deliveries.push_back(delivery.to_string());
+    }
+
+    fn deliveries(&self) -> MutexGuard<'_, VecDeque<String>> {
+        self.deliveries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Computes the `X-Hub-Signature-256` header Github sends for a payload
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let digest = match signature.strip_prefix("sha256=").and_then(|digest| hex::decode(digest).ok()) {
+        Some(digest) => digest,
+        None => return false,
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    // Compares in constant time
+    mac.verify_slice(&digest).is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+
+    use crate::credentials::Credentials;
+    use crate::review::MockReviewClient;
+    use crate::jira::{FlakyJiraClient, JiraCommentResponse, MockJiraClient};
+    use crate::server::{signature, WebhookRequest, WebhookResponse, WebhookServer};
+    use crate::SyncOptions;
+
+    const OPENED: &str = include_str!("../tests/fixtures/pull_request_opened.json");
+    const LABELED: &str = include_str!("../tests/fixtures/pull_request_labeled.json");
+
+    fn webhook(event: &str, delivery: &str, body: &str, secret: &str) -> WebhookRequest {
+        WebhookRequest {
+            method: "POST".to_string(),
+            path: "/webhook".to_string(),
+            event: Some(event.to_string()),
+            delivery: Some(delivery.to_string()),
+            signature: Some(signature(secret, body.as_bytes())),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn clients() -> (MockReviewClient, MockJiraClient) {
+        let review_client = MockReviewClient { data: Box::new(Vec::new()) };
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
+        };
+        (review_client, jira_client)
+    }
+
+    #[test]
+    fn replays_pull_request_delivery() {
+        let creds = Credentials::default();
+        let (review_client, jira_client) = clients();
+        let server = WebhookServer::new("secret", &creds, SyncOptions::default(), &review_client, &jira_client);
+
+        assert_eq!(server.handle(&webhook("pull_request", "1", OPENED, "secret")), WebhookResponse {
+            status: 202,
+            body: "Queued sync of https://github.com/org/repo/pull/42".to_string(),
+        });
+        assert_eq!(server.handle(&webhook("pull_request", "2", LABELED, "secret")), WebhookResponse {
+            status: 200,
+            body: "Ignored pull_request labeled action".to_string(),
+        });
+
+        let synced: Vec<String> = server.sync_queued().into_iter().map(Result::unwrap).collect();
+        assert_eq!(synced, vec!["Added Jira Comment on ticket https://jira.domain/browse/A-1 from https://github.com/org/repo/pull/42.".to_string()]);
+        assert!(server.sync_queued().is_empty());
+
+        assert_eq!(server.handle(&webhook("pull_request", "1", OPENED, "secret")), WebhookResponse {
+            status: 200,
+            body: "Delivery 1 was already handled".to_string(),
+        });
+    }
+
+    #[test]
+    fn syncs_redelivery_after_failed_sync() {
+        let creds = Credentials::default();
+        let (review_client, jira_client) = clients();
+        let tracker = FlakyJiraClient { inner: jira_client, failures: AtomicUsize::new(1) };
+        let server = WebhookServer::new("secret", &creds, SyncOptions::default(), &review_client, &tracker);
+
+        assert_eq!(server.handle(&webhook("pull_request", "1", OPENED, "secret")).status, 202);
+        assert!(server.sync_queued()[0].is_err());
+
+        assert_eq!(server.handle(&webhook("pull_request", "1", OPENED, "secret")).status, 202);
+        assert!(server.sync_queued()[0].is_ok());
+        assert_eq!(server.handle(&webhook("pull_request", "1", OPENED, "secret")).status, 200);
+    }
+
+    #[test]
+    fn routes_paths_with_query_strings() {
+        let creds = Credentials::default();
+        let (review_client, jira_client) = clients();
+        let server = WebhookServer::new("secret", &creds, SyncOptions::default(), &review_client, &jira_client);
+
+        let mut request = webhook("pull_request", "1", OPENED, "secret");
+        request.path = "/webhook?source=github".to_string();
+        assert_eq!(server.handle(&request).status, 202);
+
+        // Redelivering a pull request which is still queued replaces it
+        assert_eq!(server.handle(&webhook("pull_request", "2", OPENED, "secret")).status, 202);
+        assert_eq!(server.sync_queued().len(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_signature() {
+        let creds = Credentials::default();
+        let (review_client, jira_client) = clients();
+        let server = WebhookServer::new("secret", &creds, SyncOptions::default(), &review_client, &jira_client);
+
+        assert_eq!(server.handle(&webhook("pull_request", "1", OPENED, "wrong")).status, 401);
+
+        let mut unsigned = webhook("pull_request", "1", OPENED, "secret");
+        unsigned.signature = None;
+        assert_eq!(server.handle(&unsigned).status, 401);
+    }
+
+    #[test]
+    fn health_check() {
+        let creds = Credentials::default();
+        let (review_client, jira_client) = clients();
+        let server = WebhookServer::new("secret", &creds, SyncOptions::default(), &review_client, &jira_client);
+
+        let request = WebhookRequest {
+            method: "GET".to_string(),
+            path: "/health".to_string(),
+            event: None,
+            delivery: None,
+            signature: None,
+            body: Vec::new(),
+        };
+        assert_eq!(server.handle(&request), WebhookResponse { status: 200, body: "ok".to_string() });
+    }
+}
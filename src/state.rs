@@ -0,0 +1,177 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::credentials::Credentials;
+use crate::error::Error;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS synced (
+    pr_url TEXT NOT NULL,
+    ticket_key TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    jira_id TEXT,
+    content_hash TEXT NOT NULL,
+    first_synced_at TEXT NOT NULL,
+    last_synced_at TEXT NOT NULL,
+    PRIMARY KEY (pr_url, ticket_key, kind)
+)";
+
+const COLUMNS: &str = "pr_url, ticket_key, kind, jira_id, content_hash, first_synced_at, last_synced_at";
+
+/// What was created on a Jira ticket for a pull request
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordKind {
+    Comment,
+    Link,
+}
+
+impl RecordKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RecordKind::Comment => "comment",
+            RecordKind::Link => "link",
+        }
+    }
+}
+
+/// Something autocomment created on a Jira ticket, and the hash of the
+/// content it was created from
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SyncRecord {
+    pub pr_url: String,
+    pub ticket_key: String,
+    pub kind: RecordKind,
+
+    /// ID of the Jira comment, when it was posted by autocomment
+    pub jira_id: Option<String>,
+    pub content_hash: String,
+    pub first_synced_at: String,
+    pub last_synced_at: String,
+}
+
+impl SyncRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<SyncRecord> {
+        let kind: String = row.get(2)?;
+
+        Ok(SyncRecord {
+            pr_url: row.get(0)?,
+            ticket_key: row.get(1)?,
+            kind: if kind == "link" { RecordKind::Link } else { RecordKind::Comment },
+            jira_id: row.get(3)?,
+            content_hash: row.get(4)?,
+            first_synced_at: row.get(5)?,
+            last_synced_at: row.get(6)?,
+        })
+    }
+}
+
+/// Local database of what has already been synced to Jira, so unchanged
+/// pull requests can be skipped without asking Jira.
+pub struct StateStore {
+    conn: Mutex<Connection>,
+}
+
+impl StateStore {
+    /// Opens the database, creating it if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<StateStore, Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        StateStore::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<StateStore, Error> {
+        StateStore::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<StateStore, Error> {
+        conn.execute(SCHEMA, [])?;
+        Ok(StateStore { conn: Mutex::new(conn) })
+    }
+
+    /// The default database file in the config directory
+    pub fn default_path() -> PathBuf {
+        Credentials::config_dir().join("state.db")
+    }
+
+    pub fn get(&self, pr_url: &str, ticket_key: &str, kind: RecordKind) -> Result<Option<SyncRecord>, Error> {
+        let sql = format!("SELECT {} FROM synced WHERE pr_url = ?1 AND ticket_key = ?2 AND kind = ?3", COLUMNS);
+
+        self.conn().query_row(&sql, params![pr_url, ticket_key, kind.as_str()], SyncRecord::from_row)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// Records that the content was synced. A previously recorded Jira ID is
+    /// kept when the new one isn't known.
+    pub fn record(&self, pr_url: &str, ticket_key: &str, kind: RecordKind, jira_id: Option<&str>, content_hash: &str) -> Result<(), Error> {
+        self.conn().execute(
+            "INSERT INTO synced (pr_url, ticket_key, kind, jira_id, content_hash, first_synced_at, last_synced_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+             ON CONFLICT (pr_url, ticket_key, kind) DO UPDATE SET
+                 jira_id = COALESCE(excluded.jira_id, jira_id),
+                 content_hash = excluded.content_hash,
+                 last_synced_at = excluded.last_synced_at",
+            params![pr_url, ticket_key, kind.as_str(), jira_id, content_hash],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn list(&self) -> Result<Vec<SyncRecord>, Error> {
+        let sql = format!("SELECT {} FROM synced ORDER BY last_synced_at DESC, pr_url, ticket_key, kind", COLUMNS);
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&sql)?;
+        let records = stmt.query_map([], SyncRecord::from_row)?
+            .collect::<rusqlite::Result<Vec<SyncRecord>>>()?;
+
+        Ok(records)
+    }
+
+    /// Forgets everything synced for the pull request, so the next sync
+    /// checks Jira again. Returns the number of records removed.
+    pub fn forget(&self, pr_url: &str) -> Result<usize, Error> {
+        Ok(self.conn().execute("DELETE FROM synced WHERE pr_url = ?1", params![pr_url])?)
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        // A panic while holding the lock can't leave a connection half-used
+        self.conn.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Hashes content synced to Jira to detect when it has changed
+pub fn content_hash(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::{content_hash, RecordKind, StateStore};
+
+    #[test]
+    fn records_and_forgets_syncs() {
+        let state = StateStore::open_in_memory().unwrap();
+
+        state.record("https://url/org/repo/1", "A-1", RecordKind::Comment, Some("10001"), &content_hash("one")).unwrap();
+        state.record("https://url/org/repo/1", "A-1", RecordKind::Link, None, &content_hash("link")).unwrap();
+        state.record("https://url/org/repo/2", "A-2", RecordKind::Comment, None, &content_hash("two")).unwrap();
+
+        // Updating without a comment ID keeps the one already recorded
+        state.record("https://url/org/repo/1", "A-1", RecordKind::Comment, None, &content_hash("changed")).unwrap();
+
+        let record = state.get("https://url/org/repo/1", "A-1", RecordKind::Comment).unwrap().unwrap();
+        assert_eq!(record.jira_id, Some("10001".to_string()));
+        assert_eq!(record.content_hash, content_hash("changed"));
+        assert_eq!(state.list().unwrap().len(), 3);
+
+        assert_eq!(state.forget("https://url/org/repo/1").unwrap(), 2);
+        assert_eq!(state.get("https://url/org/repo/1", "A-1", RecordKind::Comment).unwrap(), None);
+        assert_eq!(state.list().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,650 @@
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::review::ChangeRequest;
+use crate::adf::{self, Document};
+use crate::jira::JiraCommentRequest;
+use crate::markdown::{markdown_blocks, markdown_to_adf};
+use crate::TakeUntil;
+
+/// The template used when no template has been configured. It renders the
+/// repository and a link to the PR, the first line of the description, the
+/// date the PR was created, and mentions of the author and reviewers.
+pub const DEFAULT_TEMPLATE: &str = "Pull Request in {{ repo }}: [{{ title }}]({{ url }})
+
+{{ body_excerpt }}
+
+Created at: {{ created_at }}
+
+{% if author_mention %}Author: {{ author_mention }}{% endif %}
+{% if reviewer_mentions %}Reviewers: {{ reviewer_mentions }}{% endif %}";
+
+/// Marks where the description should be rendered in evaluated markup
+const DESCRIPTION_MARKER: char = '\u{E000}';
+
+/// Surround a mention in evaluated markup, as `account_id|login`
+const MENTION_START: char = '\u{E001}';
+const MENTION_END: char = '\u{E002}';
+
+/// Fields of a pull request which can be used in a template
+pub const TEMPLATE_FIELDS: [&str; 15] = [
+    "title",
+    "url",
+    "repo",
+    "author",
+    "author_mention",
+    "branch",
+    "labels",
+    "reviewers",
+    "reviewer_mentions",
+    "state",
+    "created_at",
+    "updated_at",
+    "merged_at",
+    "body_excerpt",
+    "description",
+];
+
+/// A comment template. Templates are plain text with a few additions:
+///
+/// - `{{ field }}` is replaced with the value of a pull request field
+/// - `{% if field %}...{% else %}...{% endif %}` renders a section only when
+///   the field is non-empty. `{% if not field %}` inverts the condition
+/// - `[text](url)` creates a link
+/// - Blank lines separate paragraphs, and single newlines become line breaks
+/// - `{{ author_mention }}` and `{{ reviewer_mentions }}` mention the Jira
+///   users mapped to the PR's author and requested reviewers. Users without
+///   a Jira account are left out
+/// - `{{ description }}` renders the PR's markdown description as formatted
+///   blocks in its own paragraph. `{{ description 3 }}` keeps only the first
+///   three blocks
+///
+/// Templates must include `{{ url }}` so existing comments can be detected.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CommentTemplate {
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Node {
+    Text(String),
+    Field(String),
+    Description(Option<usize>),
+    If {
+        field: String,
+        negated: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+enum Token {
+    Text(String),
+    Field(String),
+    Description(Option<usize>),
+    If(String, bool),
+    Else,
+    EndIf,
+}
+
+/// A block of rendered content
+#[derive(Clone, PartialEq, Debug)]
+enum Block {
+    Paragraph(Vec<Inline>),
+    Description(Option<usize>),
+}
+
+/// Inline content of a rendered paragraph
+#[derive(Clone, PartialEq, Debug)]
+enum Inline {
+    Text(String),
+    Link(String, String),
+    Mention(String, String),
+    Break,
+}
+
+/// Value of a field when rendering a template
+enum Value {
+    Text(String),
+
+    /// Jira account IDs and Github logins of mentioned users
+    Mentions(Vec<(String, String)>),
+}
+
+impl Value {
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::Text(text) => text.is_empty(),
+            Value::Mentions(mentions) => mentions.is_empty(),
+        }
+    }
+}
+
+impl Default for CommentTemplate {
+    fn default() -> Self {
+        CommentTemplate::parse(DEFAULT_TEMPLATE).unwrap()
+    }
+}
+
+impl CommentTemplate {
+    pub fn parse(source: &str) -> Result<CommentTemplate, Error> {
+        let mut tokens = tokenize(source)?.into_iter();
+        let (nodes, end) = parse_nodes(&mut tokens)?;
+
+        if end.is_some() {
+            return Err(Error::from("Template contains {% else %} or {% endif %} without a matching {% if %}".to_string()));
+        }
+
+        let template = CommentTemplate { nodes };
+        if !template.uses_field("url") {
+            return Err(Error::from("Template must include {{ url }} so existing comments can be detected".to_string()));
+        }
+
+        Ok(template)
+    }
+
+    /// Renders the template as an ADF comment for Jira Cloud. `accounts` maps
+    /// Github logins to the Jira account IDs used for mentions.
+    pub fn render_adf(&self, pr: &ChangeRequest, accounts: &HashMap<String, String>) -> Result<JiraCommentRequest, Error> {
+        let blocks = self.render_blocks(pr, accounts)?.into_iter()
+            .flat_map(|block| match block {
+                Block::Paragraph(paragraph) => vec![adf::Node::paragraph(paragraph.into_iter()
+                    .map(|inline| match inline {
+                        Inline::Text(text) => adf::Node::text(&text),
+                        Inline::Link(text, url) => adf::Node::link(&text, &url),
+                        Inline::Mention(account_id, login) => adf::Node::mention(&account_id, Some(&format!("@{}", login))),
+                        Inline::Break => adf::Node::hard_break(),
+                    })
+                    .collect())],
+                Block::Description(max_blocks) => markdown_to_adf(pr.body.as_deref().unwrap_or(""), max_blocks),
+            })
+            .collect();
+
+        let body = Document::new(blocks);
+        body.validate()?;
+
+        Ok(JiraCommentRequest { body })
+    }
+
+    /// Renders the template as wiki markup for Jira Server. `accounts` maps
+    /// Github logins to the Jira usernames used for mentions.
+    pub fn render_wiki(&self, pr: &ChangeRequest, accounts: &HashMap<String, String>) -> Result<String, Error> {
+        let paragraphs: Vec<String> = self.render_blocks(pr, accounts)?.into_iter()
+            .flat_map(|block| match block {
+                Block::Paragraph(paragraph) => vec![paragraph.into_iter()
+                    .map(|inline| match inline {
+                        Inline::Text(text) => escape_wiki(&text),
+                        Inline::Link(text, url) => format!("[{}|{}]", escape_wiki(&text), url),
+                        Inline::Mention(username, _) => format!("[~{}]", username),
+                        Inline::Break => "\n".to_string(),
+                    })
+                    .collect()],
+                // Markdown isn't converted for wiki markup, so the
+                // description is included as plain text
+                Block::Description(max_blocks) => markdown_blocks(pr.body.as_deref().unwrap_or(""))
+                    .into_iter()
+                    .take(max_blocks.unwrap_or(usize::MAX))
+                    .map(escape_wiki)
+                    .collect(),
+            })
+            .collect();
+
+        Ok(paragraphs.join("\n\n"))
+    }
+
+    /// Renders the comment as Markdown, for trackers such as Linear. Mentions
+    /// use the tracker's username, and the description is kept as written.
+    pub fn render_markdown(&self, pr: &ChangeRequest, accounts: &HashMap<String, String>) -> Result<String, Error> {
+        let paragraphs: Vec<String> = self.render_blocks(pr, accounts)?.into_iter()
+            .flat_map(|block| match block {
+                Block::Paragraph(paragraph) => vec![paragraph.into_iter()
+                    .map(|inline| match inline {
+                        Inline::Text(text) => escape_markdown(&text),
+                        Inline::Link(text, url) => format!("[{}]({})", escape_markdown(&text), url),
+                        Inline::Mention(username, _) => format!("@{}", username),
+                        Inline::Break => "  \n".to_string(),
+                    })
+                    .collect()],
+                Block::Description(max_blocks) => markdown_blocks(pr.body.as_deref().unwrap_or(""))
+                    .into_iter()
+                    .take(max_blocks.unwrap_or(usize::MAX))
+                    .map(String::from)
+                    .collect(),
+            })
+            .collect();
+
+        Ok(paragraphs.join("\n\n"))
+    }
+
+    /// Renders the comment as Textile, for Redmine
+    pub fn render_textile(&self, pr: &ChangeRequest, accounts: &HashMap<String, String>) -> Result<String, Error> {
+        let paragraphs: Vec<String> = self.render_blocks(pr, accounts)?.into_iter()
+            .flat_map(|block| match block {
+                Block::Paragraph(paragraph) => vec![paragraph.into_iter()
+                    .map(|inline| match inline {
+                        Inline::Text(text) => escape_textile(&text),
+                        Inline::Link(text, url) => format!("\"{}\":{}", text.replace('"', "&quot;"), url),
+                        Inline::Mention(username, _) => format!("@{}", username),
+                        Inline::Break => "\n".to_string(),
+                    })
+                    .collect()],
+                // Markdown isn't converted to Textile, so the description is
+                // included as plain text
+                Block::Description(max_blocks) => markdown_blocks(pr.body.as_deref().unwrap_or(""))
+                    .into_iter()
+                    .take(max_blocks.unwrap_or(usize::MAX))
+                    .map(escape_textile)
+                    .collect(),
+            })
+            .collect();
+
+        Ok(paragraphs.join("\n\n"))
+    }
+
+    fn uses_field(&self, field: &str) -> bool {
+        fn search(nodes: &[Node], field: &str) -> bool {
+            nodes.iter().any(|node| match node {
+                Node::Field(name) => name == field,
+                Node::If { then, otherwise, .. } => search(then, field) || search(otherwise, field),
+                Node::Description(_) => field == "description",
+                Node::Text(_) => false,
+            })
+        }
+        search(&self.nodes, field)
+    }
+
+    fn render_blocks(&self, pr: &ChangeRequest, accounts: &HashMap<String, String>) -> Result<Vec<Block>, Error> {
+        let context = template_context(pr, accounts);
+        let mut markup = String::new();
+        evaluate(&self.nodes, &context, &mut markup);
+        Ok(parse_markup(&markup))
+    }
+}
+
+fn template_context(pr: &ChangeRequest, accounts: &HashMap<String, String>) -> HashMap<&'static str, Value> {
+    let state = if pr.merged_at.is_some() { "merged" } else { pr.state.as_str() };
+    let body_excerpt = pr.body.as_deref().unwrap_or("").take_until('\n').trim().to_string();
+    let mentions = |logins: Vec<&str>| Value::Mentions(logins.into_iter()
+        .filter_map(|login| accounts.get(login).map(|account_id| (account_id.clone(), login.to_string())))
+        .collect());
+
+    HashMap::from([
+        ("title", Value::Text(pr.title.clone())),
+        ("url", Value::Text(pr.html_url.clone())),
+        ("repo", Value::Text(pr.base.repo.full_name.clone())),
+        ("author", Value::Text(pr.user.login.clone())),
+        ("author_mention", mentions(vec![pr.user.login.as_str()])),
+        ("branch", Value::Text(pr.head.branch.clone())),
+        ("labels", Value::Text(pr.labels.iter().map(|label| label.name.as_str()).collect::<Vec<_>>().join(", "))),
+        ("reviewers", Value::Text(pr.requested_reviewers.iter().map(|user| user.login.as_str()).collect::<Vec<_>>().join(", "))),
+        ("reviewer_mentions", mentions(pr.requested_reviewers.iter().map(|user| user.login.as_str()).collect())),
+        ("state", Value::Text(state.to_string())),
+        ("created_at", Value::Text(pr.created_at.clone())),
+        ("updated_at", Value::Text(pr.updated_at.clone())),
+        ("merged_at", Value::Text(pr.merged_at.clone().unwrap_or_default())),
+        ("body_excerpt", Value::Text(body_excerpt)),
+        ("description", Value::Text(pr.body.clone().unwrap_or_default())),
+    ])
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find('{') {
+        let (closing, is_field) = match &rest[start..] {
+            tag if tag.starts_with("{{") => ("}}", true),
+            tag if tag.starts_with("{%") => ("%}", false),
+            _ => {
+                tokens.push(Token::Text(rest[..start + 1].to_string()));
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+        }
+
+        let end = rest[start..].find(closing)
+            .ok_or(Error::from(format!("Unclosed template tag: {}", (&rest[start..]).take_until('\n'))))?;
+        let tag = rest[start + 2..start + end].trim();
+        rest = &rest[start + end + 2..];
+
+        if is_field {
+            let words: Vec<&str> = tag.split_whitespace().collect();
+            let token = match words.as_slice() {
+                ["description"] => Token::Description(None),
+                ["description", max_blocks] => Token::Description(Some(max_blocks.parse()
+                    .map_err(|_| Error::from(format!("Invalid number of description blocks: {}", max_blocks)))?)),
+                _ => Token::Field(check_field(tag)?),
+            };
+            tokens.push(token);
+            continue;
+        }
+
+        let words: Vec<&str> = tag.split_whitespace().collect();
+        let token = match words.as_slice() {
+            ["if", "not", field] => Token::If(check_field(field)?, true),
+            ["if", field] => Token::If(check_field(field)?, false),
+            ["else"] => Token::Else,
+            ["endif"] => Token::EndIf,
+            _ => return Err(Error::from(format!("Unknown template tag: {{% {} %}}", tag))),
+        };
+        tokens.push(token);
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+
+    Ok(tokens)
+}
+
+fn check_field(field: &str) -> Result<String, Error> {
+    if TEMPLATE_FIELDS.contains(&field) {
+        Ok(field.to_string())
+    } else {
+        Err(Error::from(format!("Unknown template field {}, expected one of {}", field, TEMPLATE_FIELDS.join(", "))))
+    }
+}
+
+/// Parses nodes until the end of the tokens or an `else`/`endif`, which is
+/// returned so the caller can match it with its `if`
+fn parse_nodes(tokens: &mut impl Iterator<Item = Token>) -> Result<(Vec<Node>, Option<Token>), Error> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text)),
+            Token::Field(field) => nodes.push(Node::Field(field)),
+            Token::Description(max_blocks) => nodes.push(Node::Description(max_blocks)),
+            Token::If(field, negated) => {
+                let (then, end) = parse_nodes(tokens)?;
+                let otherwise = match end {
+                    Some(Token::Else) => match parse_nodes(tokens)? {
+                        (otherwise, Some(Token::EndIf)) => otherwise,
+                        _ => return Err(Error::from(format!("Missing {{% endif %}} for {{% if {} %}}", field))),
+                    },
+                    Some(Token::EndIf) => Vec::new(),
+                    _ => return Err(Error::from(format!("Missing {{% endif %}} for {{% if {} %}}", field))),
+                };
+                nodes.push(Node::If { field, negated, then, otherwise });
+            }
+            end @ (Token::Else | Token::EndIf) => return Ok((nodes, Some(end))),
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+/// Evaluates the template into markup. Field values are escaped so that
+/// they are never interpreted as links, mentions or descriptions.
+fn evaluate(nodes: &[Node], context: &HashMap<&'static str, Value>, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Field(field) => match &context[field.as_str()] {
+                Value::Text(text) => {
+                    for c in text.chars() {
+                        if matches!(c, '\\' | '[' | ']' | '(' | ')' | DESCRIPTION_MARKER | MENTION_START | MENTION_END) {
+                            out.push('\\');
+                        }
+                        out.push(c);
+                    }
+                }
+                Value::Mentions(mentions) => {
+                    let mentions: Vec<String> = mentions.iter()
+                        .map(|(account_id, login)| format!("{}{}|{}{}", MENTION_START, account_id, login, MENTION_END))
+                        .collect();
+                    out.push_str(&mentions.join(", "));
+                }
+            },
+            Node::Description(max_blocks) => {
+                let max_blocks = max_blocks.map(|max_blocks| max_blocks.to_string()).unwrap_or_default();
+                out.push_str(&format!("\n\n{}{}\n\n", DESCRIPTION_MARKER, max_blocks));
+            }
+            Node::If { field, negated, then, otherwise } => {
+                if context[field.as_str()].is_empty() == *negated {
+                    evaluate(then, context, out);
+                } else {
+                    evaluate(otherwise, context, out);
+                }
+            }
+        }
+    }
+}
+
+/// Splits markup into paragraphs on blank lines and parses links and line
+/// breaks within each paragraph. Empty paragraphs are dropped.
+fn parse_markup(markup: &str) -> Vec<Block> {
+    let mut paragraphs = Vec::new();
+    let mut lines = Vec::new();
+
+    for line in markup.lines().chain(std::iter::once("")) {
+        if line.trim().is_empty() {
+            if !lines.is_empty() {
+                paragraphs.push(parse_block(&lines));
+                lines.clear();
+            }
+        } else {
+            lines.push(line);
+        }
+    }
+
+    paragraphs
+}
+
+fn parse_block(lines: &[&str]) -> Block {
+    if let [line] = lines {
+        if let Some(max_blocks) = line.trim().strip_prefix(DESCRIPTION_MARKER) {
+            return Block::Description(max_blocks.parse().ok());
+        }
+    }
+    Block::Paragraph(parse_paragraph(lines))
+}
+
+fn parse_paragraph(lines: &[&str]) -> Vec<Inline> {
+    let mut inlines = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            inlines.push(Inline::Break);
+        }
+
+        let mut text = String::new();
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => text.extend(chars.next()),
+                MENTION_START => {
+                    let rest = chars.as_str();
+                    if let Some((mention, rest)) = rest.split_once(MENTION_END) {
+                        if !text.is_empty() {
+                            inlines.push(Inline::Text(std::mem::take(&mut text)));
+                        }
+                        let (account_id, login) = mention.split_once('|').unwrap_or((mention, mention));
+                        inlines.push(Inline::Mention(account_id.to_string(), login.to_string()));
+                        chars = rest.chars();
+                    }
+                }
+                '[' => match parse_link(chars.as_str()) {
+                    Some((link_text, url, rest)) => {
+                        if !text.is_empty() {
+                            inlines.push(Inline::Text(std::mem::take(&mut text)));
+                        }
+                        inlines.push(Inline::Link(link_text, url));
+                        chars = rest.chars();
+                    }
+                    None => text.push(c),
+                },
+                _ => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            inlines.push(Inline::Text(text));
+        }
+    }
+
+    inlines
+}
+
+/// Parses the remainder of a `[text](url)` link after the opening bracket,
+/// returning the unescaped text and url and the rest of the line
+fn parse_link(s: &str) -> Option<(String, String, &str)> {
+    let (text, rest) = take_escaped(s, ']')?;
+    let rest = rest.strip_prefix('(')?;
+    let (url, rest) = take_escaped(rest, ')')?;
+    Some((text, url, rest))
+}
+
+fn take_escaped(s: &str, limit: char) -> Option<(String, &str)> {
+    let mut out = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next().map(|(_, c)| c)),
+            c if c == limit => return Some((out, &s[i + 1..])),
+            _ => out.push(c),
+        }
+    }
+    None
+}
+
+fn escape_wiki(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if matches!(c, '[' | ']' | '{' | '}' | '|' | '*' | '_') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if matches!(c, '[' | ']' | '*' | '_' | '`' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Textile has no escape character, so text containing markup characters is
+/// wrapped in `==`, which leaves it unformatted
+fn escape_textile(text: &str) -> String {
+    if text.contains(['*', '_', '+', '^', '~', '"', '!', '%', '|', '[', ']', '{', '}', '@']) {
+        format!("=={}==", text)
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::review::{Account, ChangeBase, ChangeHead, ChangeRepo, ChangeRequest, Label, Provider};
+    use crate::template::CommentTemplate;
+
+    fn pull_request() -> ChangeRequest {
+        ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+            html_url: "https://url/org/repo/1".to_string(),
+            title: "fix [bug]".to_string(),
+            body: Some("test body\nsecond line".to_string()),
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: "datetime".to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: vec![Label { name: "bug".to_string() }, Label { name: "urgent".to_string() }],
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        }
+    }
+
+    #[test]
+    fn render_adf_with_conditionals() {
+        let template = CommentTemplate::parse("[{{ title }}]({{ url }}) by {{ author }}\n{% if labels %}Labels: {{ labels }}{% endif %}\n\n{% if reviewers %}Reviewers: {{ reviewers }}{% else %}No reviewers{% endif %}").unwrap();
+
+        let format = "{\"body\":{\"version\":1,\"type\":\"doc\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"fix [bug]\",\"marks\":[{\"type\":\"link\",\"attrs\":{\"href\":\"https://url/org/repo/1\"}}]},{\"type\":\"text\",\"text\":\" by me\"},{\"type\":\"hardBreak\"},{\"type\":\"text\",\"text\":\"Labels: bug, urgent\"}]},{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"No reviewers\"}]}]}}".to_string();
+
+        assert_eq!(format, serde_json::to_string(&template.render_adf(&pull_request(), &HashMap::new()).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn render_wiki_default_template() {
+        let format = "Pull Request in org/repo: [fix \\[bug\\]|https://url/org/repo/1]\n\ntest body\n\nCreated at: datetime".to_string();
+
+        assert_eq!(format, CommentTemplate::default().render_wiki(&pull_request(), &HashMap::new()).unwrap())
+    }
+
+    #[test]
+    fn render_markdown_default_template() {
+        let format = "Pull Request in org/repo: [fix \\[bug\\]](https://url/org/repo/1)\n\ntest body\n\nCreated at: datetime".to_string();
+
+        assert_eq!(format, CommentTemplate::default().render_markdown(&pull_request(), &HashMap::new()).unwrap())
+    }
+
+    #[test]
+    fn render_textile_default_template() {
+        let mut pr = pull_request();
+        pr.body = Some("test *body*".to_string());
+        let format = "Pull Request in org/repo: \"fix [bug]\":https://url/org/repo/1\n\n==test *body*==\n\nCreated at: datetime".to_string();
+
+        assert_eq!(format, CommentTemplate::default().render_textile(&pr, &HashMap::new()).unwrap())
+    }
+
+    #[test]
+    fn render_adf_description() {
+        let mut pr = pull_request();
+        pr.body = Some("first **bold**\n\n- item\n\nthird".to_string());
+        let template = CommentTemplate::parse("[{{ title }}]({{ url }})\n{{ description 2 }}").unwrap();
+
+        let format = "{\"body\":{\"version\":1,\"type\":\"doc\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"fix [bug]\",\"marks\":[{\"type\":\"link\",\"attrs\":{\"href\":\"https://url/org/repo/1\"}}]}]},{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"first \"},{\"type\":\"text\",\"text\":\"bold\",\"marks\":[{\"type\":\"strong\"}]}]},{\"type\":\"bulletList\",\"content\":[{\"type\":\"listItem\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"item\"}]}]}]}]}}".to_string();
+
+        assert_eq!(format, serde_json::to_string(&template.render_adf(&pr, &HashMap::new()).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn render_description_keeps_code_blocks_whole() {
+        let mut pr = pull_request();
+        pr.body = Some("first\n\n```\nfn main() {\n\n}\n```\n\nthird".to_string());
+        let template = CommentTemplate::parse("{{ url }}\n{{ description 2 }}").unwrap();
+
+        assert_eq!("https://url/org/repo/1\n\nfirst\n\n```\nfn main() {\n\n}\n```", template.render_markdown(&pr, &HashMap::new()).unwrap());
+        assert!(template.render_textile(&pr, &HashMap::new()).unwrap().ends_with("}\n```=="));
+        assert!(!template.render_wiki(&pr, &HashMap::new()).unwrap().contains("third"));
+    }
+
+    #[test]
+    fn render_mentions() {
+        let mut pr = pull_request();
+        pr.requested_reviewers = vec![Account { login: "reviewer".to_string() }, Account { login: "unmapped".to_string() }];
+        let accounts = HashMap::from([
+            ("me".to_string(), "abc".to_string()),
+            ("reviewer".to_string(), "def".to_string()),
+        ]);
+        let template = CommentTemplate::parse("{{ url }} {{ author_mention }}\n{% if reviewer_mentions %}Review: {{ reviewer_mentions }}{% endif %}").unwrap();
+
+        let format = "{\"body\":{\"version\":1,\"type\":\"doc\",\"content\":[{\"type\":\"paragraph\",\"content\":[{\"type\":\"text\",\"text\":\"https://url/org/repo/1 \"},{\"type\":\"mention\",\"attrs\":{\"id\":\"abc\",\"text\":\"@me\"}},{\"type\":\"hardBreak\"},{\"type\":\"text\",\"text\":\"Review: \"},{\"type\":\"mention\",\"attrs\":{\"id\":\"def\",\"text\":\"@reviewer\"}}]}]}}".to_string();
+
+        assert_eq!(format, serde_json::to_string(&template.render_adf(&pr, &accounts).unwrap()).unwrap());
+        assert_eq!("https://url/org/repo/1 [~abc]\nReview: [~def]", template.render_wiki(&pr, &accounts).unwrap());
+        assert_eq!("https://url/org/repo/1 @abc  \nReview: @def", template.render_markdown(&pr, &accounts).unwrap());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(CommentTemplate::parse("{{ url }} {{ unknown }}").is_err());
+        assert!(CommentTemplate::parse("{{ url }} {% if title %}").is_err());
+        assert!(CommentTemplate::parse("{{ url }} {% endif %}").is_err());
+        assert!(CommentTemplate::parse("{{ title }}").is_err());
+    }
+}
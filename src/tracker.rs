@@ -0,0 +1,278 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+
+use crate::credentials::Credentials;
+use crate::error::Error;
+use crate::github_issues::DefaultGithubIssuesClient;
+use crate::jira::{self, DefaultJiraClient};
+use crate::linear::DefaultLinearClient;
+use crate::redmine::DefaultRedmineClient;
+use crate::youtrack::DefaultYoutrackClient;
+use crate::Blocking;
+
+/// The issue tracker tickets are synced to
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Tracker {
+    #[default]
+    Jira,
+    Linear,
+    Youtrack,
+    Redmine,
+
+    /// Issues in Github repositories, using the Github credentials
+    #[serde(rename = "github-issues")]
+    GithubIssues,
+}
+
+impl FromStr for Tracker {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jira" => Ok(Tracker::Jira),
+            "linear" => Ok(Tracker::Linear),
+            "youtrack" => Ok(Tracker::Youtrack),
+            "redmine" => Ok(Tracker::Redmine),
+            "github-issues" => Ok(Tracker::GithubIssues),
+            _ => Err(Error::from(format!("Unknown tracker {}, expected one of jira, linear, youtrack, redmine, github-issues", s))),
+        }
+    }
+}
+
+/// The format a tracker's comments are written in
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CommentFormat {
+    /// Atlassian Document Format, for Jira Cloud
+    Adf,
+
+    /// Wiki markup, for Jira Server
+    Wiki,
+
+    Markdown,
+
+    /// Textile, for Redmine
+    Textile,
+}
+
+/// A comment on a ticket
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TrackerComment {
+    pub id: String,
+
+    /// The comment's text, or its rendered HTML when the tracker has it
+    pub body: String,
+}
+
+/// A link from a ticket to a pull request. Links are identified by their
+/// URL, so posting a link with the same URL updates the existing link.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct IssueLink {
+    pub url: String,
+    pub title: String,
+    pub summary: Option<String>,
+    pub icon_url: Option<String>,
+    pub icon_title: Option<String>,
+    pub resolved: bool,
+}
+
+/// Hidden text identifying the ticket summary comment on a pull request
+pub const SUMMARY_MARKER: &str = "<!-- autocomment: ticket summary -->";
+
+/// The parts of a ticket shown on the pull requests referencing it
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct TicketSummary {
+    pub key: String,
+    pub summary: String,
+    pub status: String,
+    pub assignee: Option<String>,
+    pub priority: Option<String>,
+    pub fix_versions: Vec<String>,
+}
+
+impl TicketSummary {
+    /// Renders the summary as a Markdown comment for the pull request,
+    /// starting with `SUMMARY_MARKER`
+    pub fn render_markdown(&self, ticket_url: &str) -> String {
+        let cell = |value: Option<&str>, missing: &str| value.unwrap_or(missing).replace('|', "\\|");
+        let fix_versions = Some(self.fix_versions.join(", ")).filter(|versions| !versions.is_empty());
+
+        format!(
+            "{}\n### [{}]({}): {}\n\n| Status | Assignee | Priority | Fix versions |\n| --- | --- | --- | --- |\n| {} | {} | {} | {} |\n",
+            SUMMARY_MARKER,
+            self.key,
+            ticket_url,
+            self.summary,
+            cell(Some(self.status.as_str()).filter(|status| !status.is_empty()), "Unknown"),
+            cell(self.assignee.as_deref(), "Unassigned"),
+            cell(self.priority.as_deref(), "None"),
+            cell(fix_versions.as_deref(), "None"),
+        )
+    }
+}
+
+/// An issue tracker autocomment posts comments and links on
+pub trait IssueTracker: Send + Sync {
+    /// Name of the tracker used in messages, such as Jira
+    fn name(&self) -> &str;
+
+    /// Domain ticket links in pull request descriptions point to
+    fn get_domain(&self) -> &str;
+    fn comment_format(&self) -> CommentFormat;
+    fn ticket_url(&self, ticket_id: &str) -> String;
+
+    /// Finds the first ticket linked in the text
+    fn parse_ticket(&self, text: &str) -> Option<String> {
+        jira::parse_jira_ticket_number(text, self.get_domain())
+    }
+
+    /// Hidden text added to the pull request's comment, which identifies the
+    /// comment instead of the pull request's URL
+    fn comment_marker(&self, _pr_url: &str) -> Option<String> {
+        None
+    }
+
+    /// The project the ticket belongs to, when its ID names one, for
+    /// checking it against the allowed projects
+    fn ticket_project(&self, ticket_id: &str) -> Option<String> {
+        ticket_id.split_once('-').map(|(project, _)| project.to_string())
+    }
+
+    fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error>;
+
+    /// Posts the comment, returning the ID of the new comment
+    fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error>;
+    fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error>;
+
+    fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error>;
+
+    /// Creates the link on the ticket, or updates the existing link with the
+    /// same URL
+    fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error>;
+
+    /// Finds the ID of the tracker account with this email address
+    fn find_account_id(&self, email: &str) -> Result<Option<String>, Error>;
+
+    /// Gets the ticket's summary, status and other details shown on pull
+    /// requests. Only Jira supports this.
+    fn get_ticket(&self, _ticket_id: &str) -> Result<TicketSummary, Error> {
+        Err(Error::ConfigError(format!("Ticket summaries aren't supported for {}", self.name())))
+    }
+}
+
+/// Async version of `IssueTracker`, for use from async code
+#[async_trait]
+pub trait AsyncIssueTracker: Send + Sync {
+    fn name(&self) -> &str;
+    fn get_domain(&self) -> &str;
+    fn comment_format(&self) -> CommentFormat;
+    fn ticket_url(&self, ticket_id: &str) -> String;
+
+    fn parse_ticket(&self, text: &str) -> Option<String> {
+        jira::parse_jira_ticket_number(text, self.get_domain())
+    }
+
+    fn comment_marker(&self, _pr_url: &str) -> Option<String> {
+        None
+    }
+
+    fn ticket_project(&self, ticket_id: &str) -> Option<String> {
+        ticket_id.split_once('-').map(|(project, _)| project.to_string())
+    }
+
+    async fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error>;
+    async fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error>;
+    async fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error>;
+    async fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error>;
+    async fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error>;
+    async fn find_account_id(&self, email: &str) -> Result<Option<String>, Error>;
+
+    async fn get_ticket(&self, _ticket_id: &str) -> Result<TicketSummary, Error> {
+        Err(Error::ConfigError(format!("Ticket summaries aren't supported for {}", self.name())))
+    }
+}
+
+/// Lets async code use a blocking tracker, such as a mock
+#[async_trait]
+impl<C: IssueTracker + ?Sized> AsyncIssueTracker for Blocking<'_, C> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn get_domain(&self) -> &str {
+        self.0.get_domain()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        self.0.comment_format()
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        self.0.ticket_url(ticket_id)
+    }
+
+    fn parse_ticket(&self, text: &str) -> Option<String> {
+        self.0.parse_ticket(text)
+    }
+
+    fn comment_marker(&self, pr_url: &str) -> Option<String> {
+        self.0.comment_marker(pr_url)
+    }
+
+    fn ticket_project(&self, ticket_id: &str) -> Option<String> {
+        self.0.ticket_project(ticket_id)
+    }
+
+    async fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        self.0.get_comments(ticket_id)
+    }
+
+    async fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        self.0.post_comment(ticket_id, text)
+    }
+
+    async fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        self.0.update_comment(ticket_id, comment_id, text)
+    }
+
+    async fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        self.0.get_links(ticket_id)
+    }
+
+    async fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        self.0.post_link(ticket_id, link)
+    }
+
+    async fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        self.0.find_account_id(email)
+    }
+
+    async fn get_ticket(&self, ticket_id: &str) -> Result<TicketSummary, Error> {
+        self.0.get_ticket(ticket_id)
+    }
+}
+
+/// Creates a blocking client for the configured tracker
+pub fn default_client(creds: &Credentials) -> Result<Box<dyn IssueTracker>, Error> {
+    match creds.tracker {
+        Tracker::Jira => Ok(Box::new(DefaultJiraClient::new(creds))),
+        Tracker::Linear => {
+            let linear = creds.linear.as_ref()
+                .ok_or(Error::ConfigError("Linear is not configured, add linear to the config file".to_string()))?;
+            Ok(Box::new(DefaultLinearClient::new(linear, creds)))
+        }
+        Tracker::Youtrack => {
+            let youtrack = creds.youtrack.as_ref()
+                .ok_or(Error::ConfigError("YouTrack is not configured, add youtrack to the config file".to_string()))?;
+            Ok(Box::new(DefaultYoutrackClient::new(youtrack, creds)))
+        }
+        Tracker::Redmine => {
+            let redmine = creds.redmine.as_ref()
+                .ok_or(Error::ConfigError("Redmine is not configured, add redmine to the config file".to_string()))?;
+            Ok(Box::new(DefaultRedmineClient::new(redmine, creds)))
+        }
+        Tracker::GithubIssues => Ok(Box::new(DefaultGithubIssuesClient::new(creds))),
+    }
+}
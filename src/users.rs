@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error::Error;
+use crate::review::{AsyncReviewClient, ChangeRequest};
+use crate::tracker::AsyncIssueTracker;
+
+/// Maps Github logins to issue tracker accounts (Jira account IDs, or Linear
+/// usernames) so comments can mention the people involved in a pull request.
+/// Logins are looked up in the configured accounts first, then optionally by
+/// searching the tracker for the Github user's public email address.
+#[derive(Clone, Default)]
+pub struct UserDirectory {
+    accounts: HashMap<String, String>,
+    resolve_by_email: bool,
+
+    /// Logins already looked up by email during this run, shared between
+    /// clones of the directory
+    resolved: Arc<Mutex<HashMap<String, Option<String>>>>,
+}
+
+impl UserDirectory {
+    pub fn new(accounts: HashMap<String, String>, resolve_by_email: bool) -> Self {
+        UserDirectory { accounts, resolve_by_email, resolved: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    pub async fn account_id(&self, login: &str, review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker) -> Result<Option<String>, Error> {
+        if let Some(account_id) = self.accounts.get(login) {
+            return Ok(Some(account_id.clone()));
+        }
+
+        if !self.resolve_by_email {
+            return Ok(None);
+        }
+
+        if let Some(account_id) = self.resolved().get(login) {
+            return Ok(account_id.clone());
+        }
+
+        let account_id = match review_client.get_user_email(login).await? {
+            Some(email) => tracker.find_account_id(email.as_str()).await?,
+            None => None,
+        };
+
+        self.resolved().insert(login.to_string(), account_id.clone());
+        Ok(account_id)
+    }
+
+    /// Gets the account IDs of a pull request's author and requested
+    /// reviewers, keyed by Github login. Users without an account are left out.
+    pub async fn accounts_for(&self, pr: &ChangeRequest, review_client: &dyn AsyncReviewClient, tracker: &dyn AsyncIssueTracker) -> Result<HashMap<String, String>, Error> {
+        let mut accounts = HashMap::new();
+
+        let logins = std::iter::once(&pr.user).chain(pr.requested_reviewers.iter())
+            .map(|user| user.login.as_str());
+
+        for login in logins {
+            if let Some(account_id) = self.account_id(login, review_client, tracker).await? {
+                accounts.insert(login.to_string(), account_id);
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    /// Gets the configured account IDs of a pull request's author and
+    /// requested reviewers, keyed by Github login, without any lookups
+    pub fn configured_accounts_for(&self, pr: &ChangeRequest) -> HashMap<String, String> {
+        std::iter::once(&pr.user).chain(pr.requested_reviewers.iter())
+            .filter_map(|user| self.accounts.get(&user.login).map(|account_id| (user.login.clone(), account_id.clone())))
+            .collect()
+    }
+
+    fn resolved(&self) -> MutexGuard<'_, HashMap<String, Option<String>>> {
+        self.resolved.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::credentials::Credentials;
+use crate::error::{Error, ErrorContext};
+use crate::error::ErrorKind;
+use crate::review::{ChangeRequest, ReviewClient};
+use crate::tracker::IssueTracker;
+use crate::metrics::metrics;
+use crate::{process_pull_requests, SyncOptions};
+
+/// The result of syncing each pull request updated in a repo
+pub type RepoSync = Result<Vec<Result<String, Error>>, Error>;
+
+/// How often a sleeping watcher checks whether it should shut down
+const SHUTDOWN_POLL: Duration = Duration::from_millis(200);
+
+/// The `updated_at` time of the most recently updated pull request synced
+/// for each repo, persisted so that restarts only fetch newer pull requests.
+/// Pull requests older than the watermark which still need to be retried are
+/// persisted with it, so they aren't lost on a restart.
+pub struct Watermarks {
+    path: PathBuf,
+    stored: StoredWatermarks,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StoredWatermarks {
+    updated: HashMap<String, String>,
+    #[serde(default)]
+    retries: HashMap<String, Vec<ChangeRequest>>,
+}
+
+/// Watermarks files written before retries were persisted only contain the
+/// `updated_at` times
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WatermarksFile {
+    Current(StoredWatermarks),
+    Legacy(HashMap<String, String>),
+}
+
+impl Watermarks {
+    /// Loads the watermarks from the file, or starts with none if the file
+    /// doesn't exist yet
+    pub fn load(path: PathBuf) -> Result<Watermarks, Error> {
+        let stored = if path.exists() {
+            match serde_yaml::from_reader(std::fs::File::open(&path)?)? {
+                WatermarksFile::Current(stored) => stored,
+                WatermarksFile::Legacy(updated) => StoredWatermarks { updated, ..Default::default() },
+            }
+        } else {
+            StoredWatermarks::default()
+        };
+
+        Ok(Watermarks { path, stored })
+    }
+
+    /// The default watermarks file in the config directory
+    pub fn default_path() -> PathBuf {
+        Credentials::config_dir().join("watermarks.yaml")
+    }
+
+    pub fn get(&self, repo: &str) -> Option<&str> {
+        self.stored.updated.get(repo).map(String::as_str)
+    }
+
+    pub fn set(&mut self, repo: &str, updated_at: &str) -> Result<(), Error> {
+        self.stored.updated.insert(repo.to_string(), updated_at.to_string());
+        self.save()
+    }
+
+    /// Pull requests in the repo which failed to sync and should be retried
+    pub fn retries(&self, repo: &str) -> &[ChangeRequest] {
+        self.stored.retries.get(repo).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn set_retries(&mut self, repo: &str, retries: Vec<ChangeRequest>) -> Result<(), Error> {
+        if retries.is_empty() {
+            self.stored.retries.remove(repo);
+        } else {
+            self.stored.retries.insert(repo.to_string(), retries);
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let f = std::fs::File::create(&self.path)?;
+        serde_yaml::to_writer(f, &self.stored).map_err(Error::from)
+    }
+}
+
+/// Repeatedly syncs a set of repos on an interval. Each cycle only fetches
+/// pull requests updated since the last successful sync of the repo.
+/// Cycles run one after another, so a slow cycle delays the next one rather
+/// than overlapping it.
+pub struct Watcher<'a> {
+    repos: Vec<String>,
+    interval: Duration,
+    creds: &'a Credentials,
+    options: SyncOptions,
+    watermarks: Watermarks,
+    review_client: &'a dyn ReviewClient,
+    jira_client: &'a dyn IssueTracker,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl<'a> Watcher<'a> {
+    pub fn new(repos: Vec<String>, interval: Duration, creds: &'a Credentials, options: SyncOptions, watermarks: Watermarks, review_client: &'a dyn ReviewClient, jira_client: &'a dyn IssueTracker) -> Watcher<'a> {
+        Watcher {
+            repos,
+            interval,
+            creds,
+            options,
+            watermarks,
+            review_client,
+            jira_client,
+            shutdown: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Runs sync cycles until SIGTERM or SIGINT is received. A cycle in
+    /// progress finishes its current repo before the watcher stops.
+    pub fn run<F>(&mut self, mut report: F) -> Result<(), Error> where F: FnMut(&str, &RepoSync) {
+        signal_hook::flag::register(SIGTERM, Arc::clone(&self.shutdown))?;
+        signal_hook::flag::register(SIGINT, Arc::clone(&self.shutdown))?;
+
+        while !self.is_shutdown() {
+            let started = Instant::now();
+
+            for (repo, result) in self.run_cycle() {
+                report(&repo, &result);
+            }
+
+            while !self.is_shutdown() && started.elapsed() < self.interval {
+                std::thread::sleep(SHUTDOWN_POLL.min(self.interval - started.elapsed()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Syncs each repo once, returning the result for each repo
+    pub fn run_cycle(&mut self) -> Vec<(String, RepoSync)> {
+        let mut results = Vec::new();
+
+        for repo in self.repos.clone() {
+            if self.is_shutdown() {
+                break;
+            }
+            let result = self.sync_repo(&repo);
+            results.push((repo, result));
+        }
+
+        results
+    }
+
+    /// Syncs the pull requests updated since the repo's watermark, returning
+    /// the result for each pull request. The watermark moves forward even
+    /// when some fail, so a pull request which can't sync doesn't hold back
+    /// the rest. Those which failed because of a network error are persisted
+    /// with the watermark and retried in the next cycle, even after a restart;
+    /// others are retried once they're updated again.
+    #[tracing::instrument(skip(self))]
+    pub fn sync_repo(&mut self, repo: &str) -> RepoSync {
+        let updated = self.review_client.get_changes_updated_since(repo, self.watermarks.get(repo))
+            .map_err(|err| err.with_context(ErrorContext { repo: Some(repo.to_string()), ..Default::default() }))
+            .inspect_err(|err| metrics().record_error(err))?;
+
+        let options = SyncOptions {
+            template: self.creds.template_for_repo(repo)?,
+            ..self.options.clone()
+        };
+
+        let mut prs = updated.changes;
+        for retry in self.watermarks.retries(repo) {
+            if !prs.iter().any(|pr| pr.html_url == retry.html_url) {
+                prs.push(retry.clone());
+            }
+        }
+
+        let results = process_pull_requests(self.review_client, self.jira_client, &prs, &options);
+
+        let retries: Vec<ChangeRequest> = prs.into_iter().zip(&results)
+            .filter(|(_, result)| matches!(result, Err(err) if err.kind() == ErrorKind::Network))
+            .map(|(pr, _)| pr)
+            .collect();
+        // Retries are saved before the watermark moves past them, so a crash
+        // in between only syncs them again
+        if !retries.is_empty() || !self.watermarks.retries(repo).is_empty() {
+            self.watermarks.set_retries(repo, retries)?;
+        }
+        if let Some(latest_update) = &updated.latest_update {
+            tracing::debug!(updated_at = %latest_update, "advancing watermark");
+            self.watermarks.set(repo, latest_update)?;
+        }
+        if results.iter().all(Result::is_ok) {
+            metrics().record_repo_synced(repo);
+        }
+
+        Ok(results)
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use crate::credentials::Credentials;
+    use crate::review::{Account, ChangeBase, ChangeHead, ChangeRepo, ChangeRequest, MockReviewClient, Provider};
+    use crate::jira::{FlakyJiraClient, JiraCommentResponse, MockJiraClient};
+    use crate::watch::{Watcher, Watermarks};
+    use crate::SyncOptions;
+
+    fn pull_request(number: u32, updated_at: &str) -> ChangeRequest {
+        ChangeRequest {
+            base: ChangeBase { repo: ChangeRepo { full_name: "org/repo".to_string() } },
+            html_url: format!("https://url/org/repo/{}", number),
+            title: "test title".to_string(),
+            body: Some("test body".to_string()),
+            created_at: "datetime".to_string(),
+            user: Account { login: "me".to_string() },
+            state: "open".to_string(),
+            merged_at: None,
+            updated_at: updated_at.to_string(),
+            head: ChangeHead { branch: "feature".to_string(), sha: None },
+            labels: Vec::new(),
+            requested_reviewers: Vec::new(),
+            provider: Provider::Github,
+        }
+    }
+
+    #[test]
+    fn syncs_only_updated_pull_requests() {
+        let path = std::env::temp_dir().join(format!("autocomment-watermarks-{}.yaml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let creds = Credentials::default();
+        let review_client = MockReviewClient {
+            data: Box::new(vec![
+                pull_request(1, "2022-12-01T00:00:00Z"),
+                pull_request(2, "2022-12-03T00:00:00Z"),
+            ])
+        };
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
+        };
+
+        let watermarks = Watermarks::load(path.clone()).unwrap();
+        let mut watcher = Watcher::new(vec!["org/repo".to_string()], Duration::from_secs(60), &creds, SyncOptions::default(), watermarks, &review_client, &jira_client);
+
+        let msgs: Vec<String> = watcher.sync_repo("org/repo").unwrap().into_iter().map(Result::unwrap).collect();
+        assert_eq!(msgs, vec![
+            "PR https://url/org/repo/2 does not contain a Jira ticket!".to_string(),
+            "PR https://url/org/repo/1 does not contain a Jira ticket!".to_string(),
+        ]);
+        assert!(watcher.sync_repo("org/repo").unwrap().is_empty());
+
+        // The watermark is persisted for the next run
+        let watermarks = Watermarks::load(path.clone()).unwrap();
+        assert_eq!(watermarks.get("org/repo"), Some("2022-12-03T00:00:00Z"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn advances_past_pull_requests_which_fail() {
+        let path = std::env::temp_dir().join(format!("autocomment-watermarks-failed-{}.yaml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // A pull request without a description can never sync
+        let mut undescribed = pull_request(2, "2022-12-03T00:00:00Z");
+        undescribed.body = None;
+
+        let creds = Credentials::default();
+        let review_client = MockReviewClient {
+            data: Box::new(vec![pull_request(1, "2022-12-01T00:00:00Z"), undescribed])
+        };
+        let jira_client = MockJiraClient {
+            domain: "jira.domain".to_string(),
+            data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+            links: Box::new(Vec::new()),
+        };
+
+        let watermarks = Watermarks::load(path.clone()).unwrap();
+        let mut watcher = Watcher::new(vec!["org/repo".to_string()], Duration::from_secs(60), &creds, SyncOptions::default(), watermarks, &review_client, &jira_client);
+
+        let results = watcher.sync_repo("org/repo").unwrap();
+        assert!(results[0].is_err());
+        assert!(results[1].is_ok());
+
+        // It isn't retried until it's updated again
+        assert!(watcher.sync_repo("org/repo").unwrap().is_empty());
+        assert_eq!(Watermarks::load(path.clone()).unwrap().get("org/repo"), Some("2022-12-03T00:00:00Z"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn persists_retries_across_restarts() {
+        let path = std::env::temp_dir().join(format!("autocomment-watermarks-retries-{}.yaml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut pr = pull_request(1, "2022-12-01T00:00:00Z");
+        pr.body = Some("[A-1](https://jira.domain/browse/A-1)".to_string());
+
+        let creds = Credentials::default();
+        let review_client = MockReviewClient { data: Box::new(vec![pr]) };
+        let jira_client = FlakyJiraClient {
+            inner: MockJiraClient {
+                domain: "jira.domain".to_string(),
+                data: Box::new(JiraCommentResponse { total: 0, comments: Vec::new() }),
+                links: Box::new(Vec::new()),
+            },
+            failures: AtomicUsize::new(1),
+        };
+
+        let watermarks = Watermarks::load(path.clone()).unwrap();
+        let mut watcher = Watcher::new(vec!["org/repo".to_string()], Duration::from_secs(60), &creds, SyncOptions::default(), watermarks, &review_client, &jira_client);
+        assert!(watcher.sync_repo("org/repo").unwrap()[0].is_err());
+
+        // A new watcher, as after a restart, retries the pull request even
+        // though the watermark has moved past it
+        let watermarks = Watermarks::load(path.clone()).unwrap();
+        assert_eq!(watermarks.get("org/repo"), Some("2022-12-01T00:00:00Z"));
+        let mut watcher = Watcher::new(vec!["org/repo".to_string()], Duration::from_secs(60), &creds, SyncOptions::default(), watermarks, &review_client, &jira_client);
+        let results = watcher.sync_repo("org/repo").unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_ok());
+
+        assert!(Watermarks::load(path.clone()).unwrap().retries("org/repo").is_empty());
+        assert!(watcher.sync_repo("org/repo").unwrap().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn loads_watermarks_without_retries() {
+        let path = std::env::temp_dir().join(format!("autocomment-watermarks-legacy-{}.yaml", std::process::id()));
+        std::fs::write(&path, "org/repo: 2022-12-03T00:00:00Z\n").unwrap();
+
+        let watermarks = Watermarks::load(path.clone()).unwrap();
+        assert_eq!(watermarks.get("org/repo"), Some("2022-12-03T00:00:00Z"));
+        assert!(watermarks.retries("org/repo").is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
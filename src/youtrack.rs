@@ -0,0 +1,253 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::{Serialize, Deserialize};
+use tokio::sync::Semaphore;
+
+use crate::credentials::{Credentials, TrackerCredentials};
+use crate::error::Error;
+use crate::http::{self, HttpResponse};
+use crate::pool;
+use crate::tracker::{AsyncIssueTracker, CommentFormat, IssueLink, IssueTracker, TrackerComment};
+
+/// Most comments or users requested at once
+const TOP: usize = 500;
+
+/// A YouTrack issue comment, which is written in Markdown
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YTComment {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct YTUser {
+    pub login: String,
+    pub email: Option<String>,
+}
+
+/// YouTrack client which sends requests to the REST API with the async
+/// reqwest client, authenticating with a permanent token
+pub struct DefaultAsyncYoutrackClient {
+    client: Client,
+    creds: TrackerCredentials,
+
+    /// Limits the requests sent to YouTrack at once
+    limit: Semaphore,
+}
+
+impl DefaultAsyncYoutrackClient {
+    /// `creds` are used for the host limit of the YouTrack domain
+    pub fn new(youtrack: &TrackerCredentials, creds: &Credentials) -> DefaultAsyncYoutrackClient {
+        let client: Client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let limit = Semaphore::new(creds.host_limit(youtrack.host()).max(1));
+        DefaultAsyncYoutrackClient { client, creds: youtrack.clone(), limit }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        self.client.request(method, format!("{}/api{}", self.creds.base_url(), path))
+            .header("Authorization", format!("Bearer {}", self.creds.token))
+            .header("Accept", "application/json")
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<HttpResponse, Error> {
+        let resp = http::send(&self.client, &self.limit, request).await?;
+        if resp.is_success() {
+            Ok(resp)
+        } else {
+            Err(resp.error())
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncIssueTracker for DefaultAsyncYoutrackClient {
+    fn name(&self) -> &str {
+        "YouTrack"
+    }
+
+    fn get_domain(&self) -> &str {
+        self.creds.host()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        CommentFormat::Markdown
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        format!("{}/issue/{}", self.creds.base_url(), ticket_id)
+    }
+
+    async fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        let request = self.request(reqwest::Method::GET, &format!("/issues/{}/comments", ticket_id))
+            .query(&[("fields", "id,text")])
+            .query(&[("$top", TOP)]);
+        let comments: Vec<YTComment> = self.send(request).await?.parse()?;
+
+        Ok(comments.into_iter()
+            .map(|comment| TrackerComment { id: comment.id, body: comment.text })
+            .collect())
+    }
+
+    async fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        let body = YTComment { id: String::new(), text: text.to_string() };
+        let request = self.request(reqwest::Method::POST, &format!("/issues/{}/comments", ticket_id))
+            .query(&[("fields", "id")])
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&body)?);
+        let comment: YTComment = self.send(request).await?.parse()?;
+
+        Ok(comment.id)
+    }
+
+    /// YouTrack updates comments with a POST to the comment
+    async fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        let body = YTComment { id: String::new(), text: text.to_string() };
+        let request = self.request(reqwest::Method::POST, &format!("/issues/{}/comments/{}", ticket_id, comment_id))
+            .query(&[("fields", "id")])
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&body)?);
+        self.send(request).await?;
+
+        Ok(())
+    }
+
+    async fn get_links(&self, _ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        Err(Error::ConfigError("YouTrack doesn't support remote links, sync with --mode comment".to_string()))
+    }
+
+    async fn post_link(&self, _ticket_id: &str, _link: &IssueLink) -> Result<(), Error> {
+        Err(Error::ConfigError("YouTrack doesn't support remote links, sync with --mode comment".to_string()))
+    }
+
+    /// Finds the login of the user, which comments mention
+    async fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        let request = self.request(reqwest::Method::GET, "/users")
+            .query(&[("fields", "login,email"), ("query", email)])
+            .query(&[("$top", TOP)]);
+        let users: Vec<YTUser> = self.send(request).await?.parse()?;
+
+        // The query also matches logins and names, so the email is checked
+        Ok(users.into_iter()
+            .find(|user| user.email.as_deref().map(|user_email| user_email.eq_ignore_ascii_case(email)).unwrap_or(false))
+            .map(|user| user.login))
+    }
+}
+
+/// Blocking YouTrack client, which waits for `DefaultAsyncYoutrackClient`'s
+/// requests on a shared runtime. It can't be used from inside an async runtime.
+pub struct DefaultYoutrackClient {
+    inner: DefaultAsyncYoutrackClient,
+}
+
+impl DefaultYoutrackClient {
+    pub fn new(youtrack: &TrackerCredentials, creds: &Credentials) -> DefaultYoutrackClient {
+        DefaultYoutrackClient { inner: DefaultAsyncYoutrackClient::new(youtrack, creds) }
+    }
+}
+
+impl IssueTracker for DefaultYoutrackClient {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn get_domain(&self) -> &str {
+        self.inner.get_domain()
+    }
+
+    fn comment_format(&self) -> CommentFormat {
+        self.inner.comment_format()
+    }
+
+    fn ticket_url(&self, ticket_id: &str) -> String {
+        self.inner.ticket_url(ticket_id)
+    }
+
+    fn get_comments(&self, ticket_id: &str) -> Result<Vec<TrackerComment>, Error> {
+        pool::block_on(self.inner.get_comments(ticket_id))
+    }
+
+    fn post_comment(&self, ticket_id: &str, text: &str) -> Result<String, Error> {
+        pool::block_on(self.inner.post_comment(ticket_id, text))
+    }
+
+    fn update_comment(&self, ticket_id: &str, comment_id: &str, text: &str) -> Result<(), Error> {
+        pool::block_on(self.inner.update_comment(ticket_id, comment_id, text))
+    }
+
+    fn get_links(&self, ticket_id: &str) -> Result<Vec<IssueLink>, Error> {
+        pool::block_on(self.inner.get_links(ticket_id))
+    }
+
+    fn post_link(&self, ticket_id: &str, link: &IssueLink) -> Result<(), Error> {
+        pool::block_on(self.inner.post_link(ticket_id, link))
+    }
+
+    fn find_account_id(&self, email: &str) -> Result<Option<String>, Error> {
+        pool::block_on(self.inner.find_account_id(email))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use crate::credentials::{Credentials, TrackerCredentials};
+    use crate::tracker::IssueTracker;
+    use crate::youtrack::DefaultYoutrackClient;
+
+    const COMMENTS: &str = include_str!("../tests/fixtures/youtrack_comments.json");
+
+    /// Serves `body` for a single request on a local port, sending back the
+    /// request's URL and Authorization header
+    fn stub_server(body: &'static str) -> (String, mpsc::Receiver<(String, Option<String>)>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let request = server.recv().unwrap();
+            let auth = request.headers().iter()
+                .find(|header| header.field.equiv("Authorization"))
+                .map(|header| header.value.to_string());
+            sender.send((request.url().to_string(), auth)).unwrap();
+            request.respond(tiny_http::Response::from_string(body)).unwrap();
+        });
+
+        (addr, receiver)
+    }
+
+    #[test]
+    fn fetches_comments_from_stub_server() {
+        let (addr, requests) = stub_server(COMMENTS);
+        let youtrack = TrackerCredentials { domain: addr, token: "perm:secret".to_string() };
+        let client = DefaultYoutrackClient::new(&youtrack, &Credentials::default());
+
+        let comments = client.get_comments("APP-17").unwrap();
+
+        let (url, auth) = requests.recv().unwrap();
+        assert_eq!(url, "/api/issues/APP-17/comments?fields=id%2Ctext&%24top=500");
+        assert_eq!(auth.as_deref(), Some("Bearer perm:secret"));
+
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[1].id, "4-31");
+        assert!(comments[1].body.contains("https://github.com/org/repo/pull/9"));
+    }
+
+    #[test]
+    fn parses_youtrack_ticket_links() {
+        let youtrack = TrackerCredentials { domain: "youtrack.example.com".to_string(), token: "perm:secret".to_string() };
+        let client = DefaultYoutrackClient::new(&youtrack, &Credentials::default());
+
+        assert_eq!(client.parse_ticket("Fixes [APP-17](https://youtrack.example.com/issue/APP-17)"), Some("APP-17".to_string()));
+        assert_eq!(client.ticket_url("APP-17"), "https://youtrack.example.com/issue/APP-17");
+    }
+}